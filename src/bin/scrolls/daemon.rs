@@ -1,10 +1,10 @@
 use std::time::Duration;
 
 use clap::ArgMatches;
-use scrolls::{bootstrap, crosscut, enrich, reducers, sources, storage};
+use scrolls::{bootstrap, broadcast, crosscut, enrich, reducers, sources, storage};
 use serde::Deserialize;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 #[serde(tag = "type")]
 pub enum ChainConfig {
     Mainnet,
@@ -29,12 +29,17 @@ impl From<ChainConfig> for crosscut::ChainWellKnownInfo {
 }
 
 #[derive(Deserialize)]
-struct ConfigRoot {
-    source: sources::Config,
-    enrich: enrich::Config,
+pub(crate) struct ConfigRoot {
+    source: Option<sources::Config>,
+    enrich: Option<enrich::Config>,
+    #[serde(default)]
     reducers: Vec<reducers::Config>,
     storage: storage::Config,
-    intersect: crosscut::IntersectConfig,
+    intersect: Option<crosscut::IntersectConfig>,
+    finalize: Option<crosscut::FinalizeConfig>,
+    swarm: Option<crosscut::SwarmConfig>,
+    publish: Option<broadcast::Config>,
+    follow: Option<broadcast::Config>,
     chain: Option<ChainConfig>,
 }
 
@@ -58,6 +63,219 @@ impl ConfigRoot {
 
         s.build()?.try_deserialize()
     }
+
+    fn required_source(&self) -> Result<sources::Config, scrolls::Error> {
+        self.source
+            .clone()
+            .ok_or_else(|| scrolls::Error::config("missing source config"))
+    }
+
+    pub(crate) fn required_enrich(&self) -> Result<enrich::Config, scrolls::Error> {
+        self.enrich
+            .clone()
+            .ok_or_else(|| scrolls::Error::config("missing enrich config"))
+    }
+
+    pub(crate) fn storage_bootstrapper(
+        &self,
+        intersect: &crosscut::IntersectConfig,
+    ) -> storage::Bootstrapper {
+        let chain = self.chain.clone().unwrap_or_default().into();
+        self.storage.clone().plugin(&chain, intersect)
+    }
+
+    /// Enrich is optional, but only as long as none of the reducers need to
+    /// resolve tx inputs
+    fn enrich_bootstrapper(&self) -> Result<enrich::Bootstrapper, scrolls::Error> {
+        match &self.enrich {
+            Some(enrich) => Ok(enrich.clone().bootstrapper()),
+            None if self.reducers.iter().any(|x| x.requires_enrich()) => Err(
+                scrolls::Error::config("some of the reducers require an enrich config"),
+            ),
+            None => Ok(enrich::Bootstrapper::skip()),
+        }
+    }
+}
+
+fn log_pipeline_state(pipeline: &bootstrap::Pipeline) {
+    for (name, tether) in pipeline.tethers.iter() {
+        match tether.check_state() {
+            gasket::runtime::TetherState::Dropped => log::warn!("{} stage dropped", name),
+            gasket::runtime::TetherState::Blocked(x) => {
+                log::warn!("{} stage blocked, state: {:?}", name, x);
+            }
+            gasket::runtime::TetherState::Alive(x) => {
+                log::info!("{} stage alive, state: {:?}", name, x);
+            }
+        }
+
+        match tether.read_metrics() {
+            Ok(readings) => {
+                for (key, value) in readings {
+                    log::info!("stage {}, metric {}: {:?}", name, key, value);
+                }
+            }
+            Err(err) => log::warn!("couldn't read metrics of stage {}: {:?}", name, err),
+        }
+    }
+}
+
+/// Waits for every stage to stop, failing if any of them didn't finish its work
+fn join_pipeline(pipeline: bootstrap::Pipeline) -> Result<(), scrolls::Error> {
+    let failed = pipeline.failed_stages();
+
+    for (_, tether) in pipeline.tethers {
+        tether.join_stage();
+    }
+
+    match failed.is_empty() {
+        true => Ok(()),
+        false => Err(scrolls::Error::message(format!(
+            "pipeline stopped on error, failed stages: {}",
+            failed.join(", ")
+        ))),
+    }
+}
+
+/// Reads the last slot persisted by the storage stage of a swarm range
+fn read_range_slot(pipeline: &bootstrap::Pipeline) -> Option<String> {
+    pipeline
+        .tethers
+        .iter()
+        .filter_map(|(_, tether)| tether.read_metrics().ok())
+        .flatten()
+        .find(|(key, _)| *key == "last_slot")
+        .map(|(_, value)| format!("{:?}", value))
+}
+
+/// Crawls the history in parallel, one finite pipeline per range of slots
+///
+/// Each range persists its own cursor so that an interrupted swarm can resume
+/// every range from where it left off. Ranges write into storage in arbitrary
+/// order, so the reducers need to produce the same state regardless of the
+/// order in which blocks of different ranges are applied.
+///
+/// Every range runs its own enrich stage on top of the same enrich db. Inputs
+/// produced by a previous range that hasn't crawled that far yet can't be
+/// resolved, those are handled according to the policy of each reducer.
+fn run_swarm(
+    config: &ConfigRoot,
+    chain: &crosscut::ChainWellKnownInfo,
+    intersect: &crosscut::IntersectConfig,
+    swarm: &crosscut::SwarmConfig,
+) -> Result<(), scrolls::Error> {
+    if let Some(enrich) = &config.enrich {
+        enrich.check_swarm()?;
+    }
+
+    let mut pipelines = Vec::new();
+
+    for (idx, (intersect, finalize)) in swarm.ranges(intersect).into_iter().enumerate() {
+        log::info!(
+            "spawning swarm range {}, from {:?} to {:?}",
+            idx,
+            intersect,
+            finalize
+        );
+
+        let source = config
+            .required_source()?
+            .bootstrapper(chain, &intersect, &Some(finalize));
+
+        let enrich = config.enrich_bootstrapper()?;
+
+        let reducer = reducers::Bootstrapper::new(config.reducers.clone(), chain);
+
+        let cursor_key = format!("{}.swarm.{}", config.storage.cursor_key(), idx);
+
+        let storage = config
+            .storage
+            .clone()
+            .with_cursor_key(cursor_key)
+            .plugin(chain, &intersect);
+
+        pipelines.push(bootstrap::build(source, enrich, reducer, None, storage)?);
+    }
+
+    while !pipelines.iter().all(|x| x.is_finished()) {
+        for (idx, pipeline) in pipelines.iter().enumerate() {
+            log::info!(
+                "swarm range {}, last slot: {:?}",
+                idx,
+                read_range_slot(pipeline)
+            );
+        }
+
+        std::thread::sleep(Duration::from_secs(5));
+    }
+
+    for pipeline in pipelines {
+        join_pipeline(pipeline)?;
+    }
+
+    log::info!("all swarm ranges finished, switching to tip-following");
+
+    Ok(())
+}
+
+fn build_chain_pipeline(
+    config: &ConfigRoot,
+    chain: &crosscut::ChainWellKnownInfo,
+) -> Result<bootstrap::Pipeline, scrolls::Error> {
+    let intersect = config
+        .intersect
+        .clone()
+        .ok_or_else(|| scrolls::Error::config("missing intersect config"))?;
+
+    // a swarm is only needed if the tip-following pipeline hasn't started yet
+    let intersect = match &config.swarm {
+        Some(swarm) => {
+            let cursor = config
+                .storage
+                .clone()
+                .plugin(chain, &intersect)
+                .read_cursor()?;
+
+            match cursor {
+                Some(_) => intersect,
+                None => {
+                    run_swarm(config, chain, &intersect, swarm)?;
+                    swarm.tip_intersect().unwrap_or(intersect)
+                }
+            }
+        }
+        None => intersect,
+    };
+
+    let source = config
+        .required_source()?
+        .bootstrapper(chain, &intersect, &config.finalize);
+
+    let enrich = config.enrich_bootstrapper()?;
+
+    let reducer = reducers::Bootstrapper::new(config.reducers.clone(), chain);
+
+    let publisher = config.publish.clone().map(|x| x.publisher());
+
+    let storage = config.storage.clone().plugin(chain, &intersect);
+
+    bootstrap::build(source, enrich, reducer, publisher, storage)
+}
+
+fn build_follower_pipeline(
+    config: &ConfigRoot,
+    chain: &crosscut::ChainWellKnownInfo,
+    follow: &broadcast::Config,
+) -> Result<bootstrap::Pipeline, scrolls::Error> {
+    let follower = follow.clone().follower();
+
+    // followers don't pull data from the chain, they mirror the publisher's tip
+    let storage = config
+        .storage
+        .clone()
+        .plugin(chain, &crosscut::IntersectConfig::Tip);
+
+    bootstrap::build_follower(follower, storage)
 }
 
 pub fn run(args: &ArgMatches) -> Result<(), scrolls::Error> {
@@ -77,51 +295,28 @@ pub fn run(args: &ArgMatches) -> Result<(), scrolls::Error> {
     let config = ConfigRoot::new(explicit_config)
         .map_err(|err| scrolls::Error::ConfigError(format!("{:?}", err)))?;
 
-    let chain = config.chain.unwrap_or_default().into();
-
-    let source = config.source.bootstrapper(&chain, &config.intersect);
-
-    let enrich = config.enrich.bootstrapper();
+    let chain = config.chain.clone().unwrap_or_default().into();
 
-    let reducer = reducers::Bootstrapper::new(config.reducers, &chain);
-
-    let storage = config.storage.plugin(&chain, &config.intersect);
-
-    let pipeline = bootstrap::build(source, enrich, reducer, storage)?;
+    let pipeline = match &config.follow {
+        Some(follow) => build_follower_pipeline(&config, &chain, follow)?,
+        None => build_chain_pipeline(&config, &chain)?,
+    };
 
     loop {
-        for (name, tether) in pipeline.tethers.iter() {
-            match tether.check_state() {
-                gasket::runtime::TetherState::Dropped => log::warn!("{} stage dropped", name),
-                gasket::runtime::TetherState::Blocked(x) => {
-                    log::warn!("{} stage blocked, state: {:?}", name, x);
-                }
-                gasket::runtime::TetherState::Alive(x) => {
-                    log::info!("{} stage alive, state: {:?}", name, x);
-                }
-            }
+        log_pipeline_state(&pipeline);
 
-            match tether.read_metrics() {
-                Ok(readings) => {
-                    for (key, value) in readings {
-                        log::info!("stage {}, metric {}: {:?}", name, key, value);
-                    }
-                }
-                Err(err) => {
-                    println!("couldn't read metrics");
-                    dbg!(err);
-                }
-            }
+        // in finite mode, sources stop once the finalize condition is reached and
+        // the rest of the stages follow after draining their inputs. A failing
+        // stage also brings the rest down, which is told apart when joining.
+        if pipeline.is_finished() {
+            log::info!("all stages stopped, exiting");
+            break;
         }
 
         std::thread::sleep(Duration::from_secs(5));
     }
 
-    //for (name, tether) in tethers {
-    //    log::warn!("{}", name);
-    //    tether.dismiss_stage().expect("stage stops");
-    //    tether.join_stage();
-    //}
+    join_pipeline(pipeline)
 }
 
 /// Creates the clap definition for this sub-command
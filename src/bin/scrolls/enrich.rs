@@ -0,0 +1,97 @@
+use clap::ArgMatches;
+use scrolls::{crosscut, enrich::import};
+
+use crate::daemon::ConfigRoot;
+
+fn run_import(args: &ArgMatches) -> Result<(), scrolls::Error> {
+    let explicit_config = args.value_of("config").map(String::from);
+
+    let config = ConfigRoot::new(explicit_config)
+        .map_err(|err| scrolls::Error::ConfigError(format!("{:?}", err)))?;
+
+    let file = args
+        .value_of("file")
+        .ok_or_else(|| scrolls::Error::config("missing dump file"))?;
+
+    let format = args
+        .value_of("format")
+        .unwrap_or("cbor")
+        .parse::<import::DumpFormat>()?;
+
+    let point = args
+        .value_of_t::<crosscut::PointArg>("point")
+        .map_err(|err| scrolls::Error::ConfigError(format!("{:?}", err)))?;
+
+    let intersect = match &point {
+        crosscut::PointArg::Origin => crosscut::IntersectConfig::Origin,
+        crosscut::PointArg::Specific(slot, hash) => {
+            crosscut::IntersectConfig::Point(*slot, hash.clone())
+        }
+    };
+
+    // the pipeline needs to pick up exactly where the dump was taken, otherwise
+    // the utxo set in the enrich db would be inconsistent
+    let mut storage = config.storage_bootstrapper(&intersect);
+
+    if let Some(cursor) = storage.read_cursor()? {
+        return Err(scrolls::Error::config(format!(
+            "storage already has a cursor at {}, dumps can only seed a fresh storage",
+            cursor.to_string()
+        )));
+    }
+
+    let entries = import::read_dump(file, format)?;
+    let count = config.required_enrich()?.import_utxos(entries)?;
+    log::info!("imported {} utxos from {}", count, file);
+
+    storage.write_cursor(&point)?;
+    log::info!("cursor set to the dump's point {}", point.to_string());
+
+    Ok(())
+}
+
+pub fn run(args: &ArgMatches) -> Result<(), scrolls::Error> {
+    env_logger::init();
+
+    match args.subcommand() {
+        Some(("import", args)) => run_import(args),
+        _ => Err(scrolls::Error::config("nothing to do")),
+    }
+}
+
+/// Creates the clap definition for this sub-command
+pub(crate) fn command_definition<'a>() -> clap::Command<'a> {
+    clap::Command::new("enrich")
+        .arg_required_else_help(true)
+        .subcommand(
+            clap::Command::new("import")
+                .about("loads a utxo set dump into the enrich db")
+                .arg(
+                    clap::Arg::new("config")
+                        .long("config")
+                        .takes_value(true)
+                        .help("config file with the enrich backend to import into"),
+                )
+                .arg(
+                    clap::Arg::new("file")
+                        .long("file")
+                        .takes_value(true)
+                        .required(true)
+                        .help("path of the utxo dump"),
+                )
+                .arg(
+                    clap::Arg::new("point")
+                        .long("point")
+                        .takes_value(true)
+                        .required(true)
+                        .help("point of the chain where the dump was taken, as `slot,hash`"),
+                )
+                .arg(
+                    clap::Arg::new("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(["cbor", "csv"])
+                        .help("format of the utxo dump, defaults to cbor"),
+                ),
+        )
+}
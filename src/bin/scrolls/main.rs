@@ -2,6 +2,7 @@ use clap::Command;
 use std::process;
 
 mod daemon;
+mod enrich;
 
 fn main() {
     let args = Command::new("app")
@@ -9,11 +10,13 @@ fn main() {
         .about("cardano cache")
         .version(env!("CARGO_PKG_VERSION"))
         .subcommand(daemon::command_definition())
+        .subcommand(enrich::command_definition())
         .arg_required_else_help(true)
         .get_matches();
 
     let result = match args.subcommand() {
         Some(("daemon", args)) => daemon::run(args),
+        Some(("enrich", args)) => enrich::run(args),
         _ => Err(scrolls::Error::ConfigError("nothing to do".to_string())),
     };
 
@@ -1,22 +1,101 @@
-use crate::{enrich, reducers, sources, storage};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 
-use gasket::{messaging::connect_ports, runtime::Tether};
+use crate::{broadcast, enrich, reducers, sources, storage};
+
+use gasket::{
+    messaging::connect_ports,
+    runtime::{spawn_stage, Policy, Tether, TetherState, WorkOutcome, WorkResult, Worker},
+};
 
 type NamedTether = (&'static str, Tether);
 
+/// Wraps the worker of a stage to flag when it stops because its work is
+/// done, as opposed to stopping on an error
+struct Tracked<W> {
+    inner: W,
+    done: Arc<AtomicBool>,
+}
+
+impl<W: Worker> Worker for Tracked<W> {
+    fn metrics(&self) -> gasket::metrics::Registry {
+        self.inner.metrics()
+    }
+
+    fn bootstrap(&mut self) -> Result<(), gasket::error::Error> {
+        self.inner.bootstrap()
+    }
+
+    fn work(&mut self) -> WorkResult {
+        let outcome = self.inner.work()?;
+
+        if matches!(outcome, WorkOutcome::Done) {
+            self.done.store(true, Ordering::SeqCst);
+        }
+
+        Ok(outcome)
+    }
+
+    fn teardown(&mut self) -> Result<(), gasket::error::Error> {
+        let result = self.inner.teardown();
+
+        // a stage that fails to persist its state on the way out didn't finish
+        if result.is_err() {
+            self.done.store(false, Ordering::SeqCst);
+        }
+
+        result
+    }
+}
+
 pub struct Pipeline {
     pub tethers: Vec<NamedTether>,
+    done: Vec<Arc<AtomicBool>>,
 }
 
 impl Pipeline {
     pub fn new() -> Self {
         Self {
             tethers: Vec::new(),
+            done: Vec::new(),
         }
     }
 
-    pub fn register_stage(&mut self, name: &'static str, tether: Tether) {
-        self.tethers.push((name, tether));
+    pub fn register_stage<W>(&mut self, name: &'static str, worker: W, policy: Policy)
+    where
+        W: Worker + 'static,
+    {
+        let done = Arc::new(AtomicBool::new(false));
+
+        let worker = Tracked {
+            inner: worker,
+            done: done.clone(),
+        };
+
+        self.tethers.push((name, spawn_stage(worker, policy)));
+        self.done.push(done);
+    }
+
+    /// Checks if every stage of the pipeline has stopped working
+    pub fn is_finished(&self) -> bool {
+        self.tethers
+            .iter()
+            .all(|(_, tether)| matches!(tether.check_state(), TetherState::Dropped))
+    }
+
+    /// Names of the stages that stopped without finishing their work, either
+    /// because of an error or because another stage failed first
+    pub fn failed_stages(&self) -> Vec<&'static str> {
+        self.tethers
+            .iter()
+            .zip(self.done.iter())
+            .filter(|((_, tether), done)| {
+                matches!(tether.check_state(), TetherState::Dropped) && !done.load(Ordering::SeqCst)
+            })
+            .map(|((name, _), _)| *name)
+            .collect()
     }
 }
 
@@ -24,9 +103,11 @@ pub fn build(
     mut source: sources::Bootstrapper,
     mut enrich: enrich::Bootstrapper,
     mut reducer: reducers::Bootstrapper,
+    mut publisher: Option<broadcast::publisher::Bootstrapper>,
     mut storage: storage::Bootstrapper,
 ) -> Result<Pipeline, crate::Error> {
     let cursor = storage.read_cursor()?;
+    reducer.load_state(&mut storage)?;
 
     let mut pipeline = Pipeline::new();
 
@@ -38,15 +119,60 @@ pub fn build(
         100,
     );
 
+    match &mut publisher {
+        Some(publisher) => {
+            connect_ports(
+                reducer.borrow_output_port(),
+                publisher.borrow_input_port(),
+                100,
+            );
+
+            connect_ports(
+                publisher.borrow_output_port(),
+                storage.borrow_input_port(),
+                100,
+            );
+        }
+        None => {
+            connect_ports(
+                reducer.borrow_output_port(),
+                storage.borrow_input_port(),
+                100,
+            );
+        }
+    }
+
+    source.spawn_stages(&mut pipeline, &cursor);
+    enrich.spawn_stages(&mut pipeline, &cursor);
+    reducer.spawn_stages(&mut pipeline);
+
+    if let Some(publisher) = publisher {
+        publisher.spawn_stages(&mut pipeline, &cursor);
+    }
+
+    storage.spawn_stages(&mut pipeline);
+
+    Ok(pipeline)
+}
+
+/// Builds a pipeline that applies the command stream of another scrolls
+/// instance into our own storage
+pub fn build_follower(
+    mut follower: broadcast::follower::Bootstrapper,
+    mut storage: storage::Bootstrapper,
+) -> Result<Pipeline, crate::Error> {
+    let cursor = storage.read_cursor()?;
+    log::info!("following command stream, local cursor: {:?}", cursor);
+
+    let mut pipeline = Pipeline::new();
+
     connect_ports(
-        reducer.borrow_output_port(),
+        follower.borrow_output_port(),
         storage.borrow_input_port(),
         100,
     );
 
-    source.spawn_stages(&mut pipeline, &cursor);
-    enrich.spawn_stages(&mut pipeline);
-    reducer.spawn_stages(&mut pipeline);
+    follower.spawn_stages(&mut pipeline, &cursor);
     storage.spawn_stages(&mut pipeline);
 
     Ok(pipeline)
@@ -0,0 +1,181 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    time::Duration,
+};
+
+use gasket::{error::AsWorkError, retries, runtime::WorkOutcome};
+
+use crate::{bootstrap, crosscut, model};
+
+use super::{Config, Handshake, HandshakeReply, Stream, WireCommand};
+
+type OutputPort = gasket::messaging::OutputPort<model::CRDTCommand>;
+
+/// Time without hearing from the publisher before the connection is
+/// considered stalled and gets re-established
+const READ_TIMEOUT: Duration = Duration::from_secs(300);
+
+pub struct Bootstrapper {
+    config: Config,
+    output: OutputPort,
+}
+
+impl Bootstrapper {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            output: Default::default(),
+        }
+    }
+
+    pub fn borrow_output_port(&mut self) -> &'_ mut OutputPort {
+        &mut self.output
+    }
+
+    pub fn spawn_stages(self, pipeline: &mut bootstrap::Pipeline, cursor: &crosscut::Cursor) {
+        let worker = Worker {
+            config: self.config,
+            cursor: cursor.clone(),
+            reader: None,
+            block: Vec::new(),
+            output: self.output,
+            block_count: Default::default(),
+        };
+
+        pipeline.register_stage("follower", worker, Default::default());
+    }
+}
+
+/// Reads the command stream published by another scrolls instance
+///
+/// Commands are buffered until the end of each block, so that a connection
+/// dropped mid-block never results in a partially applied block. Every
+/// connection starts with a handshake carrying the point of the last applied
+/// block, the publisher resumes the stream from there or refuses the follower
+/// if the point is not in its replay log.
+///
+/// Rollbacks on the publisher arrive as a block of undo commands that finishes
+/// at the rollback point, they're applied like any other block.
+pub struct Worker {
+    config: Config,
+    cursor: crosscut::Cursor,
+    reader: Option<BufReader<Stream>>,
+    block: Vec<model::CRDTCommand>,
+    output: OutputPort,
+    block_count: gasket::metrics::Counter,
+}
+
+impl Worker {
+    fn connect(&mut self) -> Result<(), crate::Error> {
+        let stream = gasket::retries::retry_operation(
+            || self.config.connect().or_work_err(),
+            &retries::Policy {
+                max_retries: 5,
+                backoff_factor: 2,
+                backoff_unit: Duration::from_secs(1),
+                max_backoff: Duration::from_secs(60),
+            },
+            None,
+        )
+        .map_err(crate::Error::source)?;
+
+        stream
+            .set_timeouts(READ_TIMEOUT)
+            .map_err(crate::Error::network)?;
+
+        let mut reader = BufReader::new(stream);
+
+        let mut handshake = serde_json::to_vec(&Handshake {
+            cursor: self.cursor.clone(),
+        })
+        .map_err(|err| crate::Error::message(err.to_string()))?;
+        handshake.push(b'\n');
+
+        reader
+            .get_mut()
+            .write_all(&handshake)
+            .map_err(crate::Error::network)?;
+
+        let mut line = String::new();
+        reader.read_line(&mut line).map_err(crate::Error::network)?;
+
+        let reply: HandshakeReply =
+            serde_json::from_str(&line).map_err(|err| crate::Error::message(err.to_string()))?;
+
+        if let HandshakeReply::Refused(reason) = reply {
+            return Err(crate::Error::source(format!(
+                "publisher can't resume from our cursor: {}",
+                reason
+            )));
+        }
+
+        log::info!("following publisher from cursor {:?}", self.cursor);
+
+        self.reader = Some(reader);
+        self.block.clear();
+
+        Ok(())
+    }
+
+    fn read_command(&mut self) -> Result<Option<model::CRDTCommand>, crate::Error> {
+        let reader = self.reader.as_mut().unwrap();
+
+        let mut line = String::new();
+        let read = reader.read_line(&mut line).map_err(crate::Error::network)?;
+
+        if read == 0 {
+            return Ok(None);
+        }
+
+        let wire: WireCommand =
+            serde_json::from_str(&line).map_err(|err| crate::Error::message(err.to_string()))?;
+
+        wire.try_into().map(Some)
+    }
+}
+
+impl gasket::runtime::Worker for Worker {
+    fn metrics(&self) -> gasket::metrics::Registry {
+        gasket::metrics::Builder::new()
+            .with_counter("block_count", &self.block_count)
+            .build()
+    }
+
+    fn bootstrap(&mut self) -> Result<(), gasket::error::Error> {
+        self.connect().or_work_err()
+    }
+
+    fn work(&mut self) -> gasket::runtime::WorkResult {
+        let cmd = match self.read_command() {
+            Ok(Some(x)) => x,
+            Ok(None) | Err(crate::Error::NetworkError(_)) => {
+                log::warn!("publisher stream closed or stalled, reconnecting");
+                self.connect().or_work_err()?;
+                return Ok(WorkOutcome::Partial);
+            }
+            Err(err) => return Err(err).or_work_err(),
+        };
+
+        match cmd {
+            model::CRDTCommand::BlockFinished(ref point) => {
+                if !matches!(
+                    self.block.first(),
+                    Some(model::CRDTCommand::BlockStarting(_))
+                ) {
+                    log::warn!("publisher rolled back to {:?}", point);
+                }
+
+                for cmd in self.block.drain(..) {
+                    self.output.send(gasket::messaging::Message::from(cmd))?;
+                }
+
+                self.cursor = Some(point.clone().into());
+                self.output.send(gasket::messaging::Message::from(cmd))?;
+                self.block_count.inc(1);
+            }
+            _ => self.block.push(cmd),
+        }
+
+        Ok(WorkOutcome::Partial)
+    }
+}
@@ -0,0 +1,223 @@
+pub mod follower;
+pub mod publisher;
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+#[cfg(target_family = "unix")]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{crosscut::PointArg, model};
+
+/// Endpoint used to share the CRDT command stream between scrolls instances
+///
+/// A daemon can publish the commands produced by its reducers through this
+/// endpoint, other daemons can follow that stream and apply it to their own
+/// storage without running sources, enrich or reducer stages.
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type")]
+pub enum Config {
+    Tcp {
+        address: String,
+    },
+
+    #[cfg(target_family = "unix")]
+    Unix {
+        path: String,
+    },
+}
+
+impl Config {
+    pub fn publisher(self) -> publisher::Bootstrapper {
+        publisher::Bootstrapper::new(self)
+    }
+
+    pub fn follower(self) -> follower::Bootstrapper {
+        follower::Bootstrapper::new(self)
+    }
+
+    fn bind(&self) -> Result<Listener, crate::Error> {
+        match self {
+            Config::Tcp { address } => TcpListener::bind(address)
+                .map(Listener::Tcp)
+                .map_err(crate::Error::network),
+            #[cfg(target_family = "unix")]
+            Config::Unix { path } => {
+                // a socket file left behind by a previous run would prevent the bind
+                let _ = std::fs::remove_file(path);
+
+                UnixListener::bind(path)
+                    .map(Listener::Unix)
+                    .map_err(crate::Error::network)
+            }
+        }
+    }
+
+    fn connect(&self) -> Result<Stream, crate::Error> {
+        match self {
+            Config::Tcp { address } => TcpStream::connect(address)
+                .map(Stream::Tcp)
+                .map_err(crate::Error::network),
+            #[cfg(target_family = "unix")]
+            Config::Unix { path } => UnixStream::connect(path)
+                .map(Stream::Unix)
+                .map_err(crate::Error::network),
+        }
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(target_family = "unix")]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    fn accept(&self) -> std::io::Result<Stream> {
+        match self {
+            Listener::Tcp(x) => x.accept().map(|(s, _)| Stream::Tcp(s)),
+            #[cfg(target_family = "unix")]
+            Listener::Unix(x) => x.accept().map(|(s, _)| Stream::Unix(s)),
+        }
+    }
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    #[cfg(target_family = "unix")]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    fn set_timeouts(&self, timeout: Duration) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(x) => x
+                .set_read_timeout(Some(timeout))
+                .and_then(|_| x.set_write_timeout(Some(timeout))),
+            #[cfg(target_family = "unix")]
+            Stream::Unix(x) => x
+                .set_read_timeout(Some(timeout))
+                .and_then(|_| x.set_write_timeout(Some(timeout))),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(x) => x.read(buf),
+            #[cfg(target_family = "unix")]
+            Stream::Unix(x) => x.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(x) => x.write(buf),
+            #[cfg(target_family = "unix")]
+            Stream::Unix(x) => x.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(x) => x.flush(),
+            #[cfg(target_family = "unix")]
+            Stream::Unix(x) => x.flush(),
+        }
+    }
+}
+
+/// First line sent by a follower after connecting, carrying the point of the
+/// last block it applied so that the publisher can resume the stream from it
+#[derive(Serialize, Deserialize)]
+struct Handshake {
+    cursor: Option<PointArg>,
+}
+
+/// First line sent by the publisher in reply to the follower's handshake
+#[derive(Serialize, Deserialize)]
+enum HandshakeReply {
+    Accepted,
+    Refused(String),
+}
+
+/// A serialization-friendly version of the CRDT commands, sent over the wire
+/// as one JSON document per line
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "cmd", content = "args")]
+enum WireCommand {
+    BlockStarting(PointArg),
+    SetAdd(model::Set, model::Member),
+    SetRemove(model::Set, model::Member),
+    TwoPhaseSetAdd(model::Set, model::Member),
+    TwoPhaseSetRemove(model::Set, model::Member),
+    GrowOnlySetAdd(model::Set, model::Member),
+    SortedSetAdd(model::Set, model::Member, model::Score),
+    SortedSetRemove(model::Set, model::Member),
+    LastWriteWins(model::Key, model::Value, model::Timestamp),
+    AnyWriteWins(model::Key, model::Value),
+    /// bytes are sent as hex
+    AnyWriteWinsBytes(model::Key, String),
+    SortedSetPrune(model::Set, model::Key, model::Score),
+    PNCounter(model::Key, model::Delta),
+    BlockFinished(PointArg),
+}
+
+impl From<&model::CRDTCommand> for WireCommand {
+    fn from(other: &model::CRDTCommand) -> Self {
+        use model::CRDTCommand as C;
+
+        match other.clone() {
+            C::BlockStarting(x) => WireCommand::BlockStarting(x.into()),
+            C::SetAdd(s, m) => WireCommand::SetAdd(s, m),
+            C::SetRemove(s, m) => WireCommand::SetRemove(s, m),
+            C::TwoPhaseSetAdd(s, m) => WireCommand::TwoPhaseSetAdd(s, m),
+            C::TwoPhaseSetRemove(s, m) => WireCommand::TwoPhaseSetRemove(s, m),
+            C::GrowOnlySetAdd(s, m) => WireCommand::GrowOnlySetAdd(s, m),
+            C::SortedSetAdd(s, m, x) => WireCommand::SortedSetAdd(s, m, x),
+            C::SortedSetRemove(s, m) => WireCommand::SortedSetRemove(s, m),
+            C::LastWriteWins(k, v, t) => WireCommand::LastWriteWins(k, v, t),
+            C::AnyWriteWins(k, v) => WireCommand::AnyWriteWins(k, v),
+            C::AnyWriteWinsBytes(k, v) => WireCommand::AnyWriteWinsBytes(k, hex::encode(v)),
+            C::SortedSetPrune(s, k, x) => WireCommand::SortedSetPrune(s, k, x),
+            C::PNCounter(k, d) => WireCommand::PNCounter(k, d),
+            C::BlockFinished(x) => WireCommand::BlockFinished(x.into()),
+        }
+    }
+}
+
+impl TryFrom<WireCommand> for model::CRDTCommand {
+    type Error = crate::Error;
+
+    fn try_from(other: WireCommand) -> Result<Self, Self::Error> {
+        use model::CRDTCommand as C;
+
+        let cmd = match other {
+            WireCommand::BlockStarting(x) => C::BlockStarting(x.try_into()?),
+            WireCommand::SetAdd(s, m) => C::SetAdd(s, m),
+            WireCommand::SetRemove(s, m) => C::SetRemove(s, m),
+            WireCommand::TwoPhaseSetAdd(s, m) => C::TwoPhaseSetAdd(s, m),
+            WireCommand::TwoPhaseSetRemove(s, m) => C::TwoPhaseSetRemove(s, m),
+            WireCommand::GrowOnlySetAdd(s, m) => C::GrowOnlySetAdd(s, m),
+            WireCommand::SortedSetAdd(s, m, x) => C::SortedSetAdd(s, m, x),
+            WireCommand::SortedSetRemove(s, m) => C::SortedSetRemove(s, m),
+            WireCommand::LastWriteWins(k, v, t) => C::LastWriteWins(k, v, t),
+            WireCommand::AnyWriteWins(k, v) => C::AnyWriteWins(k, v),
+            WireCommand::AnyWriteWinsBytes(k, v) => {
+                let v = hex::decode(v).map_err(|err| crate::Error::message(err.to_string()))?;
+                C::AnyWriteWinsBytes(k, v)
+            }
+            WireCommand::SortedSetPrune(s, k, x) => C::SortedSetPrune(s, k, x),
+            WireCommand::PNCounter(k, d) => C::PNCounter(k, d),
+            WireCommand::BlockFinished(x) => C::BlockFinished(x.try_into()?),
+        };
+
+        Ok(cmd)
+    }
+}
@@ -0,0 +1,351 @@
+use std::{
+    collections::VecDeque,
+    io::{BufRead, BufReader, Write},
+    sync::{
+        mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use gasket::{error::AsWorkError, runtime::WorkOutcome};
+use pallas::network::miniprotocols::Point;
+
+use crate::{bootstrap, crosscut, model};
+
+use super::{Config, Handshake, HandshakeReply, Stream, WireCommand};
+
+type InputPort = gasket::messaging::InputPort<model::CRDTCommand>;
+type OutputPort = gasket::messaging::OutputPort<model::CRDTCommand>;
+
+/// An encoded wire command, shared by the queues of every follower
+type Line = Arc<Vec<u8>>;
+
+/// Amount of finished blocks kept in memory to resume followers that
+/// reconnect after a while
+const MAX_REPLAY_BLOCKS: usize = 2160;
+
+/// Amount of lines that can be queued for a follower before it's considered
+/// to be lagging and gets dropped
+const MAX_FOLLOWER_QUEUE: usize = 100_000;
+
+pub struct Bootstrapper {
+    config: Config,
+    input: InputPort,
+    output: OutputPort,
+}
+
+impl Bootstrapper {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            input: Default::default(),
+            output: Default::default(),
+        }
+    }
+
+    pub fn borrow_input_port(&mut self) -> &'_ mut InputPort {
+        &mut self.input
+    }
+
+    pub fn borrow_output_port(&mut self) -> &'_ mut OutputPort {
+        &mut self.output
+    }
+
+    pub fn spawn_stages(self, pipeline: &mut bootstrap::Pipeline, cursor: &crosscut::Cursor) {
+        let mut history = VecDeque::new();
+
+        // followers that applied everything up to our own cursor can resume
+        // right away, even if nothing was published since we started
+        let start: Option<Point> = cursor.clone().and_then(|x| x.try_into().ok());
+
+        if let Some(point) = start {
+            history.push_back((point, Vec::new()));
+        }
+
+        let worker = Worker {
+            config: self.config,
+            pending: Default::default(),
+            active: Vec::new(),
+            history_from_origin: cursor.is_none(),
+            history,
+            block: Vec::new(),
+            in_block: false,
+            input: self.input,
+            output: self.output,
+            subscribers: Default::default(),
+            ops_count: Default::default(),
+        };
+
+        pipeline.register_stage("publisher", worker, Default::default());
+    }
+}
+
+fn encode_line<T: serde::Serialize>(value: &T) -> Result<Line, crate::Error> {
+    let mut line =
+        serde_json::to_vec(value).map_err(|err| crate::Error::message(err.to_string()))?;
+
+    line.push(b'\n');
+
+    Ok(Arc::new(line))
+}
+
+/// Reads the handshake of a newly connected follower
+fn read_handshake(stream: &mut Stream) -> Result<Option<Point>, crate::Error> {
+    let mut line = String::new();
+
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .map_err(crate::Error::network)?;
+
+    let handshake: Handshake =
+        serde_json::from_str(&line).map_err(|err| crate::Error::message(err.to_string()))?;
+
+    handshake.cursor.map(|x| x.try_into()).transpose()
+}
+
+/// Writes the lines of a follower from a dedicated thread, so that a slow
+/// follower never blocks our own pipeline
+fn spawn_writer(mut stream: Stream, initial: Vec<Line>, queue: Receiver<Line>) {
+    std::thread::spawn(move || {
+        for line in initial.into_iter().chain(queue) {
+            if let Err(err) = stream.write_all(&line) {
+                log::warn!("dropping follower: {}", err);
+                return;
+            }
+        }
+    });
+}
+
+/// Forwards every command to the next stage while also sending a copy to each
+/// of the connected followers
+///
+/// The commands of the latest finished blocks are kept in memory so that a
+/// follower reconnecting with a known cursor can resume from it. Followers
+/// whose cursor is not in the replay log are refused, so are followers without
+/// a cursor unless the replay log goes back to the origin of the chain.
+///
+/// Rollbacks reach followers as the undo commands of the reducers, framed as a
+/// block that finishes at the rollback point without having started. Blocks
+/// after the rollback point are dropped from the replay log, so followers that
+/// applied them get refused instead of resuming from a fork.
+pub struct Worker {
+    config: Config,
+    pending: Arc<Mutex<Vec<(Stream, Option<Point>)>>>,
+    active: Vec<SyncSender<Line>>,
+    /// Nothing was dropped from the replay log since the chain origin
+    history_from_origin: bool,
+    history: VecDeque<(Point, Vec<model::CRDTCommand>)>,
+    block: Vec<model::CRDTCommand>,
+    /// A `BlockStarting` was seen and its `BlockFinished` is still pending
+    in_block: bool,
+    input: InputPort,
+    output: OutputPort,
+    subscribers: gasket::metrics::Gauge,
+    ops_count: gasket::metrics::Counter,
+}
+
+impl Worker {
+    /// Lines of every block finished after the specified point, or of the
+    /// whole replay log for followers starting from scratch
+    fn replay_after(&self, point: Option<&Point>) -> Result<Option<Vec<Line>>, crate::Error> {
+        let skip = match point {
+            Some(point) => match self.history.iter().rposition(|(x, _)| x == point) {
+                Some(idx) => idx + 1,
+                None => return Ok(None),
+            },
+            None if self.history_from_origin => 0,
+            None => return Ok(None),
+        };
+
+        let lines = self
+            .history
+            .iter()
+            .skip(skip)
+            .flat_map(|(_, cmds)| cmds.iter())
+            .map(|cmd| encode_line(&WireCommand::from(cmd)))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Some(lines))
+    }
+
+    /// Followers that just connected only start receiving at the next block
+    /// boundary, so that they never apply a partial block
+    fn activate_pending(&mut self) -> Result<(), crate::Error> {
+        let pending: Vec<_> = self
+            .pending
+            .lock()
+            .expect("pending subscribers lock")
+            .drain(..)
+            .collect();
+
+        for (stream, cursor) in pending {
+            let (reply, replay) = match self.replay_after(cursor.as_ref())? {
+                Some(replay) => (HandshakeReply::Accepted, replay),
+                None => match &cursor {
+                    Some(point) => (
+                        HandshakeReply::Refused(format!("point {:?} not in replay log", point)),
+                        vec![],
+                    ),
+                    None => (
+                        HandshakeReply::Refused(
+                            "followers without a cursor need a replay log from origin".into(),
+                        ),
+                        vec![],
+                    ),
+                },
+            };
+
+            let accepted = matches!(reply, HandshakeReply::Accepted);
+
+            let mut initial = vec![encode_line(&reply)?];
+            initial.extend(replay);
+
+            let (sender, receiver) = sync_channel(MAX_FOLLOWER_QUEUE);
+            spawn_writer(stream, initial, receiver);
+
+            match accepted {
+                true => {
+                    log::info!("activating follower from cursor {:?}", cursor);
+                    self.active.push(sender);
+                }
+                false => log::warn!("refused follower with cursor {:?}", cursor),
+            }
+        }
+
+        self.subscribers.set(self.active.len() as i64);
+
+        Ok(())
+    }
+
+    fn broadcast(&mut self, cmd: &model::CRDTCommand) -> Result<(), crate::Error> {
+        // nothing to encode if nobody is listening
+        if self.active.is_empty() {
+            return Ok(());
+        }
+
+        let line = encode_line(&WireCommand::from(cmd))?;
+
+        self.active
+            .retain(|queue| match queue.try_send(line.clone()) {
+                Ok(_) => true,
+                Err(TrySendError::Full(_)) => {
+                    log::warn!("dropping follower lagging behind");
+                    false
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+
+        self.subscribers.set(self.active.len() as i64);
+
+        Ok(())
+    }
+
+    fn track_history(&mut self, cmd: &model::CRDTCommand) {
+        self.block.push(cmd.clone());
+
+        match cmd {
+            model::CRDTCommand::BlockStarting(_) => self.in_block = true,
+            model::CRDTCommand::BlockFinished(point) => {
+                // a block that finishes without having started carries the
+                // undo commands of a rollback
+                if !std::mem::take(&mut self.in_block) {
+                    self.drop_history_after(point);
+                }
+
+                let block = std::mem::take(&mut self.block);
+                self.history.push_back((point.clone(), block));
+
+                while self.history.len() > MAX_REPLAY_BLOCKS {
+                    self.history.pop_front();
+                    self.history_from_origin = false;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Forgets the blocks rolled back from the replay log
+    fn drop_history_after(&mut self, point: &Point) {
+        let slot = match point {
+            Point::Origin => None,
+            Point::Specific(slot, _) => Some(*slot),
+        };
+
+        while let Some((last, _)) = self.history.back() {
+            let keep = match (last, slot) {
+                (Point::Specific(last, _), Some(slot)) => *last <= slot,
+                (Point::Origin, _) => true,
+                (_, None) => false,
+            };
+
+            if keep {
+                break;
+            }
+
+            self.history.pop_back();
+        }
+    }
+}
+
+impl gasket::runtime::Worker for Worker {
+    fn metrics(&self) -> gasket::metrics::Registry {
+        gasket::metrics::Builder::new()
+            .with_gauge("subscribers", &self.subscribers)
+            .with_counter("ops_count", &self.ops_count)
+            .build()
+    }
+
+    fn bootstrap(&mut self) -> Result<(), gasket::error::Error> {
+        let listener = self.config.bind().or_work_err()?;
+        let pending = self.pending.clone();
+
+        std::thread::spawn(move || loop {
+            let mut stream = match listener.accept() {
+                Ok(x) => x,
+                Err(err) => {
+                    log::warn!("error accepting follower: {}", err);
+                    continue;
+                }
+            };
+
+            if let Err(err) = stream.set_timeouts(Duration::from_secs(30)) {
+                log::warn!("can't set follower timeouts: {}", err);
+            }
+
+            match read_handshake(&mut stream) {
+                Ok(cursor) => {
+                    log::info!("new follower connected, cursor: {:?}", cursor);
+                    pending
+                        .lock()
+                        .expect("pending subscribers lock")
+                        .push((stream, cursor));
+                }
+                Err(err) => log::warn!("invalid follower handshake: {}", err),
+            }
+        });
+
+        Ok(())
+    }
+
+    fn work(&mut self) -> gasket::runtime::WorkResult {
+        let msg = match self.input.recv() {
+            Ok(x) => x,
+            // upstream stages are gone (eg: finalized sync), nothing else will arrive
+            Err(gasket::error::Error::RecvError) => return Ok(WorkOutcome::Done),
+            Err(err) => return Err(err),
+        };
+
+        if let model::CRDTCommand::BlockStarting(_) = &msg.payload {
+            self.activate_pending().or_work_err()?;
+        }
+
+        self.broadcast(&msg.payload).or_work_err()?;
+        self.track_history(&msg.payload);
+        self.ops_count.inc(1);
+
+        self.output.send(msg)?;
+
+        Ok(WorkOutcome::Partial)
+    }
+}
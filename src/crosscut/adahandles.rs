@@ -0,0 +1,51 @@
+/// CIP-68 label (222) of user tokens, prefixed to the name of the new handles
+const CIP68_USER_TOKEN_LABEL: [u8; 4] = [0x00, 0x0d, 0xe1, 0x40];
+
+/// CIP-68 label (100) of reference tokens, which hold the handle metadata
+const CIP68_REFERENCE_TOKEN_LABEL: [u8; 4] = [0x00, 0x06, 0x43, 0xb0];
+
+/// Extracts the handle out of an asset name under the handle policy
+///
+/// Legacy handles use the plain name as the asset name while CIP-68 handles
+/// prefix it with the label of the token. Reference tokens aren't handles
+/// themselves, so they are ignored.
+pub fn handle_name(asset_name: &[u8]) -> Option<String> {
+    let name = match asset_name {
+        x if x.starts_with(&CIP68_USER_TOKEN_LABEL) => &x[4..],
+        x if x.starts_with(&CIP68_REFERENCE_TOKEN_LABEL) => return None,
+        x => x,
+    };
+
+    String::from_utf8(name.to_vec()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_handles_use_the_plain_name() {
+        assert_eq!(handle_name(b"scrolls"), Some("scrolls".to_string()));
+    }
+
+    #[test]
+    fn cip68_user_tokens_drop_the_label() {
+        let mut name = CIP68_USER_TOKEN_LABEL.to_vec();
+        name.extend_from_slice(b"scrolls");
+
+        assert_eq!(handle_name(&name), Some("scrolls".to_string()));
+    }
+
+    #[test]
+    fn cip68_reference_tokens_are_ignored() {
+        let mut name = CIP68_REFERENCE_TOKEN_LABEL.to_vec();
+        name.extend_from_slice(b"scrolls");
+
+        assert_eq!(handle_name(&name), None);
+    }
+
+    #[test]
+    fn non_utf8_names_are_ignored() {
+        assert_eq!(handle_name(&[0xff, 0xfe]), None);
+    }
+}
@@ -11,3 +11,179 @@ pub fn is_smart_contract(address: &[u8]) -> bool {
     let byte_1 = address[0];
     return get_bit_at(byte_1, 4);
 }
+
+/// The delegation part of a Shelley address
+#[derive(Debug, PartialEq)]
+pub enum StakePart {
+    /// Stake key or script hash, along with the address header
+    Credential { header: u8, hash: Vec<u8> },
+    /// Points to the certificate that registered the stake credential, as
+    /// (slot, tx index, cert index)
+    Pointer(u64, u64, u64),
+    /// Enterprise and Byron addresses, which can't be delegated
+    None,
+}
+
+/// Reads a variable-length natural number, as used by pointer addresses
+fn read_varnat(bytes: &mut impl Iterator<Item = u8>) -> Option<u64> {
+    let mut value = 0u64;
+
+    loop {
+        let byte = bytes.next()?;
+        value = (value << 7) | (byte & 0x7f) as u64;
+
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+}
+
+// https://github.com/input-output-hk/cardano-ledger/blob/master/eras/babbage/test-suite/cddl-files/babbage.cddl#L118
+pub fn stake_part(address: &[u8]) -> StakePart {
+    let header = match address.first() {
+        Some(x) => *x,
+        None => return StakePart::None,
+    };
+
+    match header >> 4 {
+        // base addresses, the stake credential follows the payment one
+        0..=3 if address.len() >= 57 => StakePart::Credential {
+            header,
+            hash: address[29..57].to_vec(),
+        },
+        4 | 5 => {
+            let mut bytes = address.iter().skip(29).copied();
+
+            let pointer = read_varnat(&mut bytes).and_then(|slot| {
+                let tx = read_varnat(&mut bytes)?;
+                let cert = read_varnat(&mut bytes)?;
+                Some(StakePart::Pointer(slot, tx, cert))
+            });
+
+            pointer.unwrap_or(StakePart::None)
+        }
+        _ => StakePart::None,
+    }
+}
+
+/// Decodes the bytes of a bech32 address, Byron addresses aren't bech32
+/// encoded and yield `None`
+pub fn bech32_to_bytes(address: &str) -> Option<Vec<u8>> {
+    use bech32::FromBase32;
+
+    let (_, data, _) = bech32::decode(address).ok()?;
+    Vec::<u8>::from_base32(&data).ok()
+}
+
+/// Builds the reward address bytes of a stake credential found in a base
+/// address, keeping the network of the address header
+pub fn reward_bytes(header: u8, hash: &[u8]) -> Vec<u8> {
+    // base address types 2 and 3 delegate to a script, so does reward type 15
+    let reward_type = match header >> 4 {
+        0 | 1 => 0xe0,
+        _ => 0xf0,
+    };
+
+    let network = header & 0x0f;
+
+    let mut bytes = vec![reward_type | network];
+    bytes.extend_from_slice(hash);
+
+    bytes
+}
+
+/// Encodes the reward address of a stake credential found in a base address
+pub fn stake_address(address_hrp: &str, header: u8, hash: &[u8]) -> Option<String> {
+    reward_address(address_hrp, &reward_bytes(header, hash))
+}
+
+/// Encodes the raw bytes of a reward address (eg: a pool reward account) as
+/// bech32, using the stake flavor of the chain's address prefix
+pub fn reward_address(address_hrp: &str, bytes: &[u8]) -> Option<String> {
+    use bech32::ToBase32;
+
+    let hrp = address_hrp.replacen("addr", "stake", 1);
+
+    bech32::encode(&hrp, bytes.to_base32(), bech32::Variant::Bech32).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // test vectors from CIP-19
+    const BASE_ADDRESS: &str = "addr1qx2fxv2umyhttkxyxp8x0dlpdt3k6cwng5pxj3jhsydzer3n0d3vllmyqwsx5wktcd8cc3sq835lu7drv2xwl2wywfgse35a3x";
+    const SCRIPT_STAKE_ADDRESS: &str = "addr1yx2fxv2umyhttkxyxp8x0dlpdt3k6cwng5pxj3jhsydzerkr0vd4msrxnuwnccdxlhdjar77j6lg0wypcc9uar5d2shs2z78ve";
+    const POINTER_ADDRESS: &str =
+        "addr1gx2fxv2umyhttkxyxp8x0dlpdt3k6cwng5pxj3jhsydzer5pnz75xxcrzqf96k";
+    const ENTERPRISE_ADDRESS: &str = "addr1vx2fxv2umyhttkxyxp8x0dlpdt3k6cwng5pxj3jhsydzers66hrl8";
+
+    const STAKE_KEY_HASH: &str = "337b62cfff6403a06a3acbc34f8c46003c69fe79a3628cefa9c47251";
+    const STAKE_SCRIPT_HASH: &str = "c37b1b5dc0669f1d3c61a6fddb2e8fde96be87b881c60bce8e8d542f";
+
+    #[test]
+    fn read_varnat_single_and_multi_byte() {
+        assert_eq!(read_varnat(&mut [0x03].into_iter()), Some(3));
+        assert_eq!(read_varnat(&mut [0x81, 0x00].into_iter()), Some(128));
+        assert_eq!(
+            read_varnat(&mut [0x81, 0x98, 0xbd, 0x43].into_iter()),
+            Some(2498243)
+        );
+
+        // the continuation bit is set on the last available byte
+        assert_eq!(read_varnat(&mut [0x81].into_iter()), None);
+    }
+
+    #[test]
+    fn stake_part_of_base_addresses() {
+        let bytes = bech32_to_bytes(BASE_ADDRESS).unwrap();
+
+        assert_eq!(
+            stake_part(&bytes),
+            StakePart::Credential {
+                header: 0x01,
+                hash: hex::decode(STAKE_KEY_HASH).unwrap(),
+            }
+        );
+
+        let bytes = bech32_to_bytes(SCRIPT_STAKE_ADDRESS).unwrap();
+
+        assert_eq!(
+            stake_part(&bytes),
+            StakePart::Credential {
+                header: 0x21,
+                hash: hex::decode(STAKE_SCRIPT_HASH).unwrap(),
+            }
+        );
+    }
+
+    #[test]
+    fn stake_part_of_pointer_and_enterprise_addresses() {
+        let bytes = bech32_to_bytes(POINTER_ADDRESS).unwrap();
+        assert_eq!(stake_part(&bytes), StakePart::Pointer(2498243, 27, 3));
+
+        let bytes = bech32_to_bytes(ENTERPRISE_ADDRESS).unwrap();
+        assert_eq!(stake_part(&bytes), StakePart::None);
+
+        // truncated pointer
+        assert_eq!(stake_part(&bytes[..30]), StakePart::None);
+        assert_eq!(stake_part(&[]), StakePart::None);
+    }
+
+    #[test]
+    fn stake_address_keeps_credential_type_and_network() {
+        let key = hex::decode(STAKE_KEY_HASH).unwrap();
+
+        assert_eq!(
+            stake_address("addr", 0x01, &key).as_deref(),
+            Some("stake1uyehkck0lajq8gr28t9uxnuvgcqrc6070x3k9r8048z8y5gh6ffgw")
+        );
+
+        let script = hex::decode(STAKE_SCRIPT_HASH).unwrap();
+
+        assert_eq!(
+            stake_address("addr", 0x21, &script).as_deref(),
+            Some("stake178phkx6acpnf78fuvxn0mkew3l0fd058hzquvz7w36x4gtcccycj5")
+        );
+    }
+}
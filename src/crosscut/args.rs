@@ -139,6 +139,189 @@ impl IntersectConfig {
     }
 }
 
+/// Conditions that turn a continuous sync into a finite one
+///
+/// When any of the configured conditions is met, sources stop pulling data
+/// from upstream and the pipeline is drained before exiting.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct FinalizeConfig {
+    pub until_slot: Option<u64>,
+    pub until_epoch: Option<u64>,
+    pub until_tip: Option<bool>,
+    pub max_block_count: Option<u64>,
+}
+
+impl FinalizeConfig {
+    /// Checks if a point lies past the configured bounds, in which case it
+    /// should not reach the pipeline
+    pub fn is_beyond(&self, chain: &ChainWellKnownInfo, point: &Point) -> bool {
+        let slot = match point {
+            Point::Origin => return false,
+            Point::Specific(slot, _) => *slot,
+        };
+
+        if let Some(until_slot) = self.until_slot {
+            if slot > until_slot {
+                return true;
+            }
+        }
+
+        if let Some(until_epoch) = self.until_epoch {
+            if super::epochs::slot_epoch(chain, slot) > until_epoch {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Checks if the sync should stop right after emitting a point
+    pub fn is_last(
+        &self,
+        chain: &ChainWellKnownInfo,
+        point: &Point,
+        tip: &Point,
+        block_count: u64,
+    ) -> bool {
+        if let Some(max) = self.max_block_count {
+            if block_count >= max {
+                return true;
+            }
+        }
+
+        let slot = match point {
+            Point::Origin => return false,
+            Point::Specific(slot, _) => *slot,
+        };
+
+        if let Some(until_slot) = self.until_slot {
+            if slot >= until_slot {
+                return true;
+            }
+        }
+
+        if let Some(until_epoch) = self.until_epoch {
+            // this is the last slot of the epoch if the next one belongs to a new epoch
+            if super::epochs::slot_epoch(chain, slot + 1) > until_epoch {
+                return true;
+            }
+        }
+
+        if self.until_tip.unwrap_or(false) {
+            if let Point::Specific(tip_slot, _) = tip {
+                if slot >= *tip_slot {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+}
+
+/// Checkpoints used to split the history of the chain into slot ranges that
+/// are crawled concurrently, what we call "swarm mode"
+#[derive(Debug, Deserialize, Clone)]
+pub struct SwarmConfig {
+    pub checkpoints: Vec<(u64, String)>,
+}
+
+impl SwarmConfig {
+    fn sorted_checkpoints(&self) -> Vec<(u64, String)> {
+        let mut sorted = self.checkpoints.clone();
+        sorted.sort_by_key(|(slot, _)| *slot);
+        sorted
+    }
+
+    /// Splits the history into ranges, each one defined by the point to
+    /// intersect and the condition to finalize
+    ///
+    /// The first range starts at the configured intersect and each range ends
+    /// (inclusive) at the next checkpoint, where the following one starts.
+    pub fn ranges(&self, intersect: &IntersectConfig) -> Vec<(IntersectConfig, FinalizeConfig)> {
+        let mut ranges = Vec::new();
+        let mut start = intersect.clone();
+
+        for (slot, hash) in self.sorted_checkpoints() {
+            let finalize = FinalizeConfig {
+                until_slot: Some(slot),
+                ..Default::default()
+            };
+
+            ranges.push((start, finalize));
+            start = IntersectConfig::Point(slot, hash);
+        }
+
+        ranges
+    }
+
+    /// The point where the tip-following pipeline starts once all ranges are
+    /// done, which is the last of the checkpoints
+    pub fn tip_intersect(&self) -> Option<IntersectConfig> {
+        self.sorted_checkpoints()
+            .pop()
+            .map(|(slot, hash)| IntersectConfig::Point(slot, hash))
+    }
+}
+
+/// Update quorum and initial protocol parameters of the Shelley genesis file
+///
+/// Rational values are expressed as `numerator/denominator` strings.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShelleyGenesisValues {
+    pub update_quorum: u32,
+    pub min_fee_a: u64,
+    pub min_fee_b: u64,
+    pub max_block_body_size: u64,
+    pub max_tx_size: u64,
+    pub max_block_header_size: u64,
+    pub key_deposit: u64,
+    pub pool_deposit: u64,
+    pub max_epoch: u64,
+    pub n_opt: u64,
+    pub pool_pledge_influence: String,
+    pub monetary_expansion_rate: String,
+    pub treasury_growth_rate: String,
+    pub decentralisation_param: String,
+    pub protocol_version: (u64, u64),
+    pub min_utxo_value: u64,
+    pub min_pool_cost: u64,
+    /// Slots before the end of an epoch after which update proposals only
+    /// target the next epoch, 3k/f of the genesis values
+    #[serde(default = "default_stability_window")]
+    pub stability_window: u64,
+}
+
+fn default_stability_window() -> u64 {
+    129600
+}
+
+impl ShelleyGenesisValues {
+    /// Values shared by both the mainnet and testnet genesis files
+    fn well_known() -> Self {
+        ShelleyGenesisValues {
+            update_quorum: 5,
+            min_fee_a: 44,
+            min_fee_b: 155381,
+            max_block_body_size: 65536,
+            max_tx_size: 16384,
+            max_block_header_size: 1100,
+            key_deposit: 2000000,
+            pool_deposit: 500000000,
+            max_epoch: 18,
+            n_opt: 150,
+            pool_pledge_influence: "3/10".to_string(),
+            monetary_expansion_rate: "3/1000".to_string(),
+            treasury_growth_rate: "1/5".to_string(),
+            decentralisation_param: "1/1".to_string(),
+            protocol_version: (2, 0),
+            min_utxo_value: 1000000,
+            min_pool_cost: 340000000,
+            stability_window: default_stability_window(),
+        }
+    }
+}
+
 /// Well-known information about the blockhain network
 ///
 /// Some of the logic in Scrolls depends on particular characteristic of the
@@ -160,6 +343,8 @@ pub struct ChainWellKnownInfo {
     pub shelley_known_time: u64,
     pub address_hrp: String,
     pub adahandle_policy: String,
+    #[serde(default)]
+    pub shelley_genesis: Option<ShelleyGenesisValues>,
 }
 
 impl ChainWellKnownInfo {
@@ -182,6 +367,7 @@ impl ChainWellKnownInfo {
             address_hrp: "addr".to_string(),
             adahandle_policy: "f0ff48bbb7bbe9d59a40f1ce90e9e9d0ff5002ec48f232b49ca0fb9a"
                 .to_string(),
+            shelley_genesis: Some(ShelleyGenesisValues::well_known()),
         }
     }
 
@@ -204,6 +390,7 @@ impl ChainWellKnownInfo {
             address_hrp: "addr_test".to_string(),
             adahandle_policy: "8d18d786e92776c824607fd8e193ec535c79dc61ea2405ddf3b09fe3"
                 .to_string(),
+            shelley_genesis: Some(ShelleyGenesisValues::well_known()),
         }
     }
 
@@ -225,3 +412,115 @@ impl Default for ChainWellKnownInfo {
         Self::mainnet()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(slot: u64) -> Point {
+        Point::Specific(slot, vec![0u8; 32])
+    }
+
+    #[test]
+    fn finalize_beyond_slot() {
+        let chain = ChainWellKnownInfo::mainnet();
+
+        let config = FinalizeConfig {
+            until_slot: Some(100),
+            ..Default::default()
+        };
+
+        assert!(!config.is_beyond(&chain, &Point::Origin));
+        assert!(!config.is_beyond(&chain, &point(100)));
+        assert!(config.is_beyond(&chain, &point(101)));
+    }
+
+    #[test]
+    fn finalize_beyond_epoch() {
+        let chain = ChainWellKnownInfo::mainnet();
+
+        let config = FinalizeConfig {
+            until_epoch: Some(208),
+            ..Default::default()
+        };
+
+        // epoch 209 starts at slot 4924800 on mainnet
+        assert!(!config.is_beyond(&chain, &point(4924799)));
+        assert!(config.is_beyond(&chain, &point(4924800)));
+    }
+
+    #[test]
+    fn finalize_last_by_slot_and_epoch() {
+        let chain = ChainWellKnownInfo::mainnet();
+        let tip = point(u64::MAX);
+
+        let by_slot = FinalizeConfig {
+            until_slot: Some(100),
+            ..Default::default()
+        };
+
+        assert!(!by_slot.is_last(&chain, &point(99), &tip, 1));
+        assert!(by_slot.is_last(&chain, &point(100), &tip, 1));
+
+        let by_epoch = FinalizeConfig {
+            until_epoch: Some(208),
+            ..Default::default()
+        };
+
+        assert!(!by_epoch.is_last(&chain, &point(4924798), &tip, 1));
+        assert!(by_epoch.is_last(&chain, &point(4924799), &tip, 1));
+    }
+
+    #[test]
+    fn finalize_last_by_tip_and_count() {
+        let chain = ChainWellKnownInfo::mainnet();
+
+        let by_tip = FinalizeConfig {
+            until_tip: Some(true),
+            ..Default::default()
+        };
+
+        assert!(!by_tip.is_last(&chain, &point(10), &point(20), 1));
+        assert!(by_tip.is_last(&chain, &point(20), &point(20), 1));
+
+        let by_count = FinalizeConfig {
+            max_block_count: Some(3),
+            ..Default::default()
+        };
+
+        assert!(!by_count.is_last(&chain, &point(10), &point(20), 2));
+        assert!(by_count.is_last(&chain, &point(10), &point(20), 3));
+        assert!(!FinalizeConfig::default().is_last(&chain, &point(10), &point(10), 100));
+    }
+
+    #[test]
+    fn swarm_ranges_follow_sorted_checkpoints() {
+        let swarm = SwarmConfig {
+            checkpoints: vec![(200, "bb".into()), (100, "aa".into())],
+        };
+
+        let ranges = swarm.ranges(&IntersectConfig::Origin);
+        assert_eq!(ranges.len(), 2);
+
+        assert!(matches!(ranges[0].0, IntersectConfig::Origin));
+        assert_eq!(ranges[0].1.until_slot, Some(100));
+
+        assert!(matches!(&ranges[1].0, IntersectConfig::Point(100, hash) if hash == "aa"));
+        assert_eq!(ranges[1].1.until_slot, Some(200));
+
+        assert!(matches!(
+            swarm.tip_intersect(),
+            Some(IntersectConfig::Point(200, hash)) if hash == "bb"
+        ));
+    }
+
+    #[test]
+    fn swarm_without_checkpoints_has_no_ranges() {
+        let swarm = SwarmConfig {
+            checkpoints: vec![],
+        };
+
+        assert!(swarm.ranges(&IntersectConfig::Origin).is_empty());
+        assert!(swarm.tip_intersect().is_none());
+    }
+}
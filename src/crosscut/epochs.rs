@@ -31,3 +31,20 @@ pub fn block_epoch(chain: &super::ChainWellKnownInfo, block: &MultiEraBlock) ->
         _ => post_byron_epoch_for_slot(chain.shelley_known_slot, chain.shelley_epoch_length, slot),
     }
 }
+
+/// Computes the epoch of a slot, without needing the block to know its era
+pub fn slot_epoch(chain: &super::ChainWellKnownInfo, slot: u64) -> u64 {
+    match slot < chain.shelley_known_slot {
+        true => byron_epoch_for_slot(chain.byron_epoch_length, chain.byron_slot_length, slot),
+        false => {
+            post_byron_epoch_for_slot(chain.shelley_known_slot, chain.shelley_epoch_length, slot)
+        }
+    }
+}
+
+/// Computes the first slot of a post-Byron epoch
+pub fn epoch_first_slot(chain: &super::ChainWellKnownInfo, epoch: u64) -> u64 {
+    let last_byron_epoch_no = 208;
+
+    chain.shelley_known_slot + (epoch - last_byron_epoch_no) * chain.shelley_epoch_length as u64
+}
@@ -1,6 +1,8 @@
+pub mod adahandles;
 pub mod addresses;
 mod args;
 pub mod epochs;
 pub mod policies;
+pub mod utxos;
 
 pub use args::*;
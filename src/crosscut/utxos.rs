@@ -0,0 +1,58 @@
+use pallas::ledger::traverse::{MultiEraOutput, MultiEraTx, OutputRef};
+
+/// Inputs actually spent by the tx. Txs that failed script validation consume
+/// their collateral instead of their regular inputs.
+pub fn consumed_inputs(tx: &MultiEraTx) -> Vec<OutputRef> {
+    let inputs = match tx.is_valid() {
+        true => tx.inputs(),
+        false => tx.collateral(),
+    };
+
+    inputs.iter().filter_map(|x| x.output_ref()).collect()
+}
+
+/// Inputs referenced by the tx that remain unspent after it, such as reference
+/// inputs or the collateral of a valid tx
+pub fn read_only_inputs(tx: &MultiEraTx) -> Vec<OutputRef> {
+    let not_consumed = match tx.is_valid() {
+        true => tx.collateral(),
+        false => tx.inputs(),
+    };
+
+    tx.reference_inputs()
+        .iter()
+        .chain(not_consumed.iter())
+        .filter_map(|x| x.output_ref())
+        .collect()
+}
+
+/// Every input that the tx references, either spent or not
+pub fn all_inputs(tx: &MultiEraTx) -> Vec<OutputRef> {
+    let mut all = consumed_inputs(tx);
+    all.extend(read_only_inputs(tx));
+    all
+}
+
+/// Finds an output of the tx by index, including the collateral return which
+/// sits right after the regular outputs
+pub fn output_at<'a>(tx: &'a MultiEraTx, index: usize) -> Option<MultiEraOutput<'a>> {
+    let outputs = tx.outputs();
+
+    match index.cmp(&outputs.len()) {
+        std::cmp::Ordering::Less => outputs.into_iter().nth(index),
+        std::cmp::Ordering::Equal => tx.collateral_return(),
+        std::cmp::Ordering::Greater => None,
+    }
+}
+
+/// Outputs actually created by the tx, along with their index. Txs that failed
+/// script validation only create their collateral return.
+pub fn produced_outputs<'a>(tx: &'a MultiEraTx) -> Vec<(usize, MultiEraOutput<'a>)> {
+    match tx.is_valid() {
+        true => tx.outputs().into_iter().enumerate().collect(),
+        false => tx
+            .collateral_return()
+            .map(|x| vec![(tx.outputs().len(), x)])
+            .unwrap_or_default(),
+    }
+}
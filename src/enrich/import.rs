@@ -0,0 +1,243 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    str::FromStr,
+};
+
+use pallas::codec::minicbor::{self, bytes::ByteVec};
+
+// Dumps describe the utxo set at a specific point of the chain, one entry per
+// unspent output. Two formats are supported:
+//
+// - cbor: a sequence of arrays [tx_hash: bytes, index: uint, era: uint, output: bytes]
+// - csv: lines with `tx_hash,index,era,output` where hash and output are hex
+//
+// The era uses the same numbering as `pallas::ledger::traverse::Era`.
+
+/// An unspent output as found in a utxo dump
+pub struct UtxoEntry {
+    pub tx_hash: String,
+    pub index: u64,
+    pub era: u16,
+    pub cbor: Vec<u8>,
+}
+
+impl UtxoEntry {
+    pub fn key(&self) -> String {
+        format!("{}#{}", self.tx_hash, self.index)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum DumpFormat {
+    Cbor,
+    Csv,
+}
+
+impl FromStr for DumpFormat {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "cbor" => Ok(DumpFormat::Cbor),
+            "csv" => Ok(DumpFormat::Csv),
+            x => Err(crate::Error::config(format!("unknown dump format {}", x))),
+        }
+    }
+}
+
+/// Entries of a dump, read lazily so that large dumps don't need to fit in
+/// memory
+pub type DumpEntries = Box<dyn Iterator<Item = Result<UtxoEntry, crate::Error>>>;
+
+const CBOR_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Decodes the entries of a cbor dump, reading the source in chunks
+struct CborEntries<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    chunk_size: usize,
+    eof: bool,
+}
+
+impl<R: Read> CborEntries<R> {
+    fn new(reader: R, chunk_size: usize) -> Self {
+        Self {
+            reader,
+            buffer: Vec::new(),
+            chunk_size,
+            eof: false,
+        }
+    }
+
+    fn try_decode(&mut self) -> Result<Option<UtxoEntry>, crate::Error> {
+        let mut decoder = minicbor::Decoder::new(&self.buffer);
+
+        let (tx_hash, index, era, cbor): (ByteVec, u64, u16, ByteVec) = match decoder.decode() {
+            Ok(x) => x,
+            // the entry continues in the next chunk
+            Err(err) if err.is_end_of_input() && !self.eof => return Ok(None),
+            Err(err) => return Err(crate::Error::cbor(err)),
+        };
+
+        let entry = UtxoEntry {
+            tx_hash: hex::encode(tx_hash.as_slice()),
+            index,
+            era,
+            cbor: cbor.to_vec(),
+        };
+
+        let used = decoder.position();
+        self.buffer.drain(..used);
+
+        Ok(Some(entry))
+    }
+
+    fn read_chunk(&mut self) -> Result<(), crate::Error> {
+        let mut chunk = vec![0u8; self.chunk_size];
+        let read = self
+            .reader
+            .read(&mut chunk)
+            .map_err(crate::Error::storage)?;
+
+        match read {
+            0 => self.eof = true,
+            n => self.buffer.extend_from_slice(&chunk[..n]),
+        };
+
+        Ok(())
+    }
+
+    fn next_entry(&mut self) -> Result<Option<UtxoEntry>, crate::Error> {
+        loop {
+            if !self.buffer.is_empty() {
+                if let Some(entry) = self.try_decode()? {
+                    return Ok(Some(entry));
+                }
+            } else if self.eof {
+                // nothing left to read
+                return Ok(None);
+            }
+
+            self.read_chunk()?;
+        }
+    }
+}
+
+impl<R: Read> Iterator for CborEntries<R> {
+    type Item = Result<UtxoEntry, crate::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
+}
+
+fn parse_csv_line(line: &str) -> Result<UtxoEntry, crate::Error> {
+    let invalid = || crate::Error::message(format!("invalid dump line: {}", line));
+
+    let mut parts = line.trim().split(',');
+
+    let tx_hash = parts.next().ok_or_else(invalid)?.to_string();
+    let index = parts.next().ok_or_else(invalid)?;
+    let era = parts.next().ok_or_else(invalid)?;
+    let cbor = parts.next().ok_or_else(invalid)?;
+
+    Ok(UtxoEntry {
+        tx_hash,
+        index: index.parse().map_err(|_| invalid())?,
+        era: era.parse().map_err(|_| invalid())?,
+        cbor: hex::decode(cbor).map_err(|_| invalid())?,
+    })
+}
+
+fn csv_entries<R: BufRead>(reader: R) -> impl Iterator<Item = Result<UtxoEntry, crate::Error>> {
+    reader.lines().filter_map(|line| {
+        let line = match line {
+            Ok(x) => x,
+            Err(err) => return Some(Err(crate::Error::storage(err))),
+        };
+
+        // skip blanks and an optional header
+        if line.trim().is_empty() || line.starts_with("tx_hash") {
+            return None;
+        }
+
+        Some(parse_csv_line(&line))
+    })
+}
+
+pub fn read_dump(path: &str, format: DumpFormat) -> Result<DumpEntries, crate::Error> {
+    let file = File::open(path).map_err(crate::Error::storage)?;
+
+    match format {
+        DumpFormat::Cbor => Ok(Box::new(CborEntries::new(file, CBOR_CHUNK_SIZE))),
+        DumpFormat::Csv => Ok(Box::new(csv_entries(BufReader::new(file)))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cbor_dump(entries: &[(&[u8], u64, u16, &[u8])]) -> Vec<u8> {
+        let mut encoder = minicbor::Encoder::new(Vec::new());
+
+        for (hash, index, era, cbor) in entries {
+            encoder
+                .array(4)
+                .and_then(|e| e.bytes(hash))
+                .and_then(|e| e.u64(*index))
+                .and_then(|e| e.u16(*era))
+                .and_then(|e| e.bytes(cbor))
+                .unwrap();
+        }
+
+        encoder.into_writer()
+    }
+
+    #[test]
+    fn cbor_entries_span_chunks() {
+        let raw = cbor_dump(&[(&[0xaa; 32], 0, 5, &[1, 2, 3]), (&[0xbb; 32], 7, 6, &[4])]);
+
+        // a tiny chunk size forces every entry to be read in several steps
+        let entries: Vec<_> = CborEntries::new(raw.as_slice(), 3)
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].key(), format!("{}#0", "aa".repeat(32)));
+        assert_eq!(entries[0].era, 5);
+        assert_eq!(entries[0].cbor, vec![1, 2, 3]);
+        assert_eq!(entries[1].key(), format!("{}#7", "bb".repeat(32)));
+    }
+
+    #[test]
+    fn cbor_truncated_entry_fails() {
+        let raw = cbor_dump(&[(&[0xaa; 32], 0, 5, &[1, 2, 3])]);
+        let truncated = &raw[..raw.len() - 1];
+
+        let result: Result<Vec<_>, _> = CborEntries::new(truncated, 8).collect();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn csv_entries_skip_header_and_blanks() {
+        let raw = "tx_hash,index,era,output\n\naabb,1,5,8200\n";
+
+        let entries: Vec<_> = csv_entries(raw.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].key(), "aabb#1");
+        assert_eq!(entries[0].era, 5);
+        assert_eq!(entries[0].cbor, vec![0x82, 0x00]);
+    }
+
+    #[test]
+    fn csv_invalid_line_fails() {
+        assert!(parse_csv_line("aabb,1,5").is_err());
+        assert!(parse_csv_line("aabb,x,5,8200").is_err());
+        assert!(parse_csv_line("aabb,1,5,zz").is_err());
+    }
+}
@@ -0,0 +1,939 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fs::{File, OpenOptions},
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+};
+
+use gasket::{error::AsWorkError, runtime::WorkOutcome};
+
+use pallas::{codec::minicbor, ledger::traverse::MultiEraBlock, network::miniprotocols::Point};
+use serde::Deserialize;
+
+use crate::{
+    bootstrap, crosscut,
+    model::{self, BlockContext},
+};
+
+type InputPort = gasket::messaging::InputPort<model::RawBlockPayload>;
+type OutputPort = gasket::messaging::OutputPort<model::EnrichedBlockPayload>;
+
+#[derive(Deserialize, Clone)]
+pub struct Config {
+    pub max_entries: usize,
+    pub mode: Option<super::Mode>,
+    pub snapshot_path: Option<String>,
+    /// Amount of blocks between compactions of the journal into the snapshot,
+    /// besides the one done on teardown
+    pub snapshot_interval: Option<u64>,
+}
+
+impl Config {
+    pub fn boostrapper(self) -> Bootstrapper {
+        Bootstrapper {
+            config: self,
+            input: Default::default(),
+            output: Default::default(),
+        }
+    }
+
+    /// Adds the entries to the snapshot, so that the cache starts warm with them
+    pub fn import_utxos(self, entries: super::import::DumpEntries) -> Result<u64, crate::Error> {
+        if !matches!(self.mode.unwrap_or_default(), super::Mode::Utxos) {
+            return Err(crate::Error::config(
+                "utxo dumps can only be imported in Utxos mode",
+            ));
+        }
+
+        let path = self
+            .snapshot_path
+            .ok_or_else(|| crate::Error::config("utxo imports require a snapshot path"))?;
+
+        let mut snapshot = load_snapshot(&path)?.unwrap_or_default();
+
+        if !read_journal(&path, snapshot.generation)?.is_empty() {
+            return Err(crate::Error::config(
+                "the snapshot has pending journal records, run the daemon once before importing",
+            ));
+        }
+
+        let mut cache = LruCache::new(self.max_entries);
+        cache.load(std::mem::take(&mut snapshot.entries));
+
+        let mut count = 0;
+
+        for entry in entries {
+            // entries beyond the limit would evict others right away, leaving a
+            // partial utxo set behind
+            if cache.len() >= self.max_entries {
+                return Err(crate::Error::config(format!(
+                    "utxo dump doesn't fit in the cache, max entries is {}",
+                    self.max_entries
+                )));
+            }
+
+            let entry = entry?;
+            cache.insert(entry.key(), (entry.era, entry.cbor));
+            count += 1;
+        }
+
+        snapshot.entries = cache.dump();
+        save_snapshot(&path, &snapshot)?;
+
+        Ok(count)
+    }
+}
+
+pub struct Bootstrapper {
+    config: Config,
+    input: InputPort,
+    output: OutputPort,
+}
+
+impl Bootstrapper {
+    pub fn borrow_input_port(&mut self) -> &'_ mut InputPort {
+        &mut self.input
+    }
+
+    pub fn borrow_output_port(&mut self) -> &'_ mut OutputPort {
+        &mut self.output
+    }
+
+    pub fn spawn_stages(self, pipeline: &mut bootstrap::Pipeline, cursor: &crosscut::Cursor) {
+        let worker = Worker {
+            cache: LruCache::new(self.config.max_entries),
+            config: self.config,
+            cursor: cursor.clone(),
+            point: None,
+            undo: Default::default(),
+            undo_floor: None,
+            generation: 0,
+            journal: None,
+            ops: Vec::new(),
+            blocks_since_snapshot: 0,
+            input: self.input,
+            output: self.output,
+            hit_count: Default::default(),
+            miss_count: Default::default(),
+            entries: Default::default(),
+        };
+
+        pipeline.register_stage("enrich-memory", worker, Default::default());
+    }
+}
+
+type Entry = (u16, Vec<u8>);
+
+/// A change to the cache, either an insert or a removal of the key
+type Op = (String, Option<Entry>);
+
+/// A point as persisted, `None` standing for the origin
+type SavedPoint = Option<(u64, Vec<u8>)>;
+
+fn save_point(point: &Point) -> SavedPoint {
+    match point {
+        Point::Origin => None,
+        Point::Specific(slot, hash) => Some((*slot, hash.clone())),
+    }
+}
+
+fn restore_point(point: SavedPoint) -> Point {
+    match point {
+        None => Point::Origin,
+        Some((slot, hash)) => Point::Specific(slot, hash),
+    }
+}
+
+/// The state of the cache after applying the block of a point, along with the
+/// undo data needed to roll it back
+///
+/// Snapshots are only saved once in a while, the changes applied in between
+/// are appended to a journal that is replayed on top of the snapshot. Records
+/// of the journal carry the generation of the snapshot they build upon, so
+/// that leftovers of a previous generation are never applied twice.
+#[derive(Default)]
+struct Snapshot {
+    generation: u64,
+    point: Option<SavedPoint>,
+    undo_floor: Option<u64>,
+    undo: Vec<(u64, Vec<(String, Entry)>, Vec<String>)>,
+    /// Entries of the cache, from least to most recently used
+    entries: Vec<(String, u16, Vec<u8>)>,
+}
+
+type RawSnapshot = (
+    u64,
+    Option<SavedPoint>,
+    Option<u64>,
+    Vec<(u64, Vec<(String, Entry)>, Vec<String>)>,
+    Vec<(String, u16, Vec<u8>)>,
+);
+
+fn load_snapshot(path: &str) -> Result<Option<Snapshot>, crate::Error> {
+    let raw = match std::fs::read(path) {
+        Ok(x) => x,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(crate::Error::storage(err)),
+    };
+
+    let (generation, point, undo_floor, undo, entries): RawSnapshot =
+        minicbor::decode(&raw).map_err(crate::Error::cbor)?;
+
+    Ok(Some(Snapshot {
+        generation,
+        point,
+        undo_floor,
+        undo,
+        entries,
+    }))
+}
+
+/// Writes the snapshot into a temporary file first, so that a crash while
+/// saving never leaves a truncated snapshot behind
+fn save_snapshot(path: &str, snapshot: &Snapshot) -> Result<(), crate::Error> {
+    let raw = minicbor::to_vec((
+        snapshot.generation,
+        &snapshot.point,
+        snapshot.undo_floor,
+        &snapshot.undo,
+        &snapshot.entries,
+    ))
+    .map_err(crate::Error::cbor)?;
+
+    let tmp = format!("{}.tmp", path);
+
+    std::fs::write(&tmp, raw)
+        .and_then(|_| std::fs::rename(&tmp, path))
+        .map_err(crate::Error::storage)
+}
+
+fn journal_path(snapshot_path: &str) -> String {
+    format!("{}.journal", snapshot_path)
+}
+
+/// A journal record: the snapshot generation, the point the cache moved to and
+/// the changes of the block, if it was a roll forward
+type JournalRecord = (u64, SavedPoint, Option<Vec<Op>>);
+
+/// Reads the journal records of a snapshot generation, ignoring a record
+/// truncated by a crash while it was being written
+fn read_journal(snapshot_path: &str, generation: u64) -> Result<Vec<JournalRecord>, crate::Error> {
+    let file = match File::open(journal_path(snapshot_path)) {
+        Ok(x) => x,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(crate::Error::storage(err)),
+    };
+
+    let mut reader = BufReader::new(file);
+    let mut records = Vec::new();
+
+    loop {
+        let mut len = [0u8; 4];
+
+        match reader.read_exact(&mut len) {
+            Ok(_) => (),
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(crate::Error::storage(err)),
+        };
+
+        let mut raw = vec![0u8; u32::from_be_bytes(len) as usize];
+
+        match reader.read_exact(&mut raw) {
+            Ok(_) => (),
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(crate::Error::storage(err)),
+        };
+
+        let record: JournalRecord = minicbor::decode(&raw).map_err(crate::Error::cbor)?;
+
+        if record.0 == generation {
+            records.push(record);
+        }
+    }
+
+    Ok(records)
+}
+
+/// Starts an empty journal, dropping the records of previous generations
+fn create_journal(snapshot_path: &str) -> Result<BufWriter<File>, crate::Error> {
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(journal_path(snapshot_path))
+        .map_err(crate::Error::storage)?;
+
+    Ok(BufWriter::new(file))
+}
+
+/// A bounded map that evicts the least recently used entries first
+struct LruCache {
+    max_entries: usize,
+    tick: u64,
+    entries: HashMap<String, (u64, Entry)>,
+    usage: BTreeMap<u64, String>,
+}
+
+impl LruCache {
+    fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            tick: 0,
+            entries: HashMap::new(),
+            usage: BTreeMap::new(),
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn get(&mut self, key: &str) -> Option<Entry> {
+        let tick = self.next_tick();
+        let (last_used, entry) = self.entries.get_mut(key)?;
+
+        self.usage.remove(last_used);
+        self.usage.insert(tick, key.to_string());
+        *last_used = tick;
+
+        Some(entry.clone())
+    }
+
+    fn insert(&mut self, key: String, entry: Entry) {
+        self.remove(&key);
+
+        let tick = self.next_tick();
+        self.usage.insert(tick, key.clone());
+        self.entries.insert(key, (tick, entry));
+
+        while self.entries.len() > self.max_entries {
+            let oldest = match self.usage.keys().next() {
+                Some(x) => *x,
+                None => break,
+            };
+
+            if let Some(key) = self.usage.remove(&oldest) {
+                self.entries.remove(&key);
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let (last_used, entry) = self.entries.remove(key)?;
+        self.usage.remove(&last_used);
+
+        Some(entry)
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Lists the entries from least to most recently used
+    fn dump(&self) -> Vec<(String, u16, Vec<u8>)> {
+        self.usage
+            .values()
+            .filter_map(|key| {
+                self.entries
+                    .get(key)
+                    .map(|(_, (era, cbor))| (key.clone(), *era, cbor.clone()))
+            })
+            .collect()
+    }
+
+    /// Inserts the entries of a dump, from least to most recently used
+    fn load(&mut self, entries: Vec<(String, u16, Vec<u8>)>) {
+        for (key, era, cbor) in entries {
+            self.insert(key, (era, cbor));
+        }
+    }
+}
+
+/// Amount of blocks for which we keep undo data of the utxo set, matching the
+/// security parameter of the chain
+const MAX_UNDO_BLOCKS: usize = 2160;
+
+const DEFAULT_SNAPSHOT_INTERVAL: u64 = 1000;
+
+/// Changes applied by a block to the utxo set
+struct BlockUndo {
+    slot: u64,
+    spent: Vec<(String, Entry)>,
+    created: Vec<String>,
+}
+
+/// Keeps the data of recent txs in memory
+///
+/// When a snapshot path is set, the cache is persisted as a snapshot plus a
+/// journal of the changes of every block since. On restart, the cache is
+/// brought to the point of the storage cursor, rolling back the blocks that
+/// were enriched but never committed by the storage stage.
+pub struct Worker {
+    config: Config,
+    cache: LruCache,
+    /// Point persisted by storage, where the cache resumes from
+    cursor: crosscut::Cursor,
+    /// Point of the last block applied to the cache, if known
+    point: Option<Point>,
+    undo: VecDeque<BlockUndo>,
+    /// Slot of the latest block evicted from the undo data, rollbacks can't go
+    /// further back than this point
+    undo_floor: Option<u64>,
+    generation: u64,
+    journal: Option<BufWriter<File>>,
+    /// Changes of the block being applied, kept only while journaling
+    ops: Vec<Op>,
+    blocks_since_snapshot: u64,
+    input: InputPort,
+    output: OutputPort,
+    hit_count: gasket::metrics::Counter,
+    miss_count: gasket::metrics::Counter,
+    entries: gasket::metrics::Gauge,
+}
+
+impl Worker {
+    fn lookup(&mut self, key: &str, remove: bool) -> Option<Entry> {
+        let found = match remove {
+            true => self.remove(key),
+            false => self.cache.get(key),
+        };
+
+        match found {
+            Some(_) => self.hit_count.inc(1),
+            None => self.miss_count.inc(1),
+        };
+
+        found
+    }
+
+    fn insert(&mut self, key: String, entry: Entry) {
+        if self.journal.is_some() {
+            self.ops.push((key.clone(), Some(entry.clone())));
+        }
+
+        self.cache.insert(key, entry);
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        if self.journal.is_some() {
+            self.ops.push((key.to_string(), None));
+        }
+
+        self.cache.remove(key)
+    }
+
+    fn track_block_txs(
+        &mut self,
+        block: &MultiEraBlock,
+        raw_txs: Option<&super::raw::BlockTxs>,
+    ) -> Result<BlockContext, crate::Error> {
+        let mut ctx = BlockContext::default();
+
+        for (tx_idx, tx) in block.txs().iter().enumerate() {
+            let era = tx.era().into();
+            let body = super::tx_cbor(raw_txs, tx_idx, tx)?;
+            self.insert(tx.hash().to_string(), (era, body));
+
+            for output_ref in crosscut::utxos::all_inputs(tx) {
+                let key = output_ref.tx_id().to_string();
+
+                if let Some((era, cbor)) = self.lookup(&key, false) {
+                    let era = era.try_into().map_err(crate::Error::storage)?;
+                    super::import_ref_tx(&mut ctx, &output_ref, era, cbor)?;
+                }
+            }
+        }
+
+        Ok(ctx)
+    }
+
+    fn track_block_utxos(
+        &mut self,
+        block: &MultiEraBlock,
+        raw_txs: Option<&super::raw::BlockTxs>,
+    ) -> Result<BlockContext, crate::Error> {
+        let mut ctx = BlockContext::default();
+
+        let mut undo = BlockUndo {
+            slot: block.slot(),
+            spent: Vec::new(),
+            created: Vec::new(),
+        };
+
+        let mut created = HashSet::new();
+
+        for (tx_idx, tx) in block.txs().iter().enumerate() {
+            for output_ref in crosscut::utxos::read_only_inputs(tx) {
+                let key = format!("{}#{}", output_ref.tx_id(), output_ref.tx_index());
+
+                if let Some((era, cbor)) = self.lookup(&key, false) {
+                    let era = era.try_into().map_err(crate::Error::storage)?;
+                    ctx.import_ref_output(&output_ref, era, cbor);
+                }
+            }
+
+            for output_ref in crosscut::utxos::consumed_inputs(tx) {
+                let key = format!("{}#{}", output_ref.tx_id(), output_ref.tx_index());
+
+                if let Some((era, cbor)) = self.lookup(&key, true) {
+                    // outputs created within the same block don't need to be restored
+                    if !created.contains(&key) {
+                        undo.spent.push((key, (era, cbor.clone())));
+                    }
+
+                    let era = era.try_into().map_err(crate::Error::storage)?;
+                    ctx.import_ref_output(&output_ref, era, cbor);
+                }
+            }
+
+            let tx_hash = tx.hash();
+            let era: u16 = tx.era().into();
+
+            for (idx, output) in crosscut::utxos::produced_outputs(tx) {
+                let key = format!("{}#{}", tx_hash, idx);
+                let body = super::output_cbor(raw_txs, tx_idx, idx, &output)?;
+                self.insert(key.clone(), (era, body));
+                created.insert(key.clone());
+                undo.created.push(key);
+            }
+        }
+
+        self.push_undo(undo);
+
+        Ok(ctx)
+    }
+
+    fn push_undo(&mut self, undo: BlockUndo) {
+        self.undo.push_back(undo);
+
+        while self.undo.len() > MAX_UNDO_BLOCKS {
+            self.undo_floor = self.undo.pop_front().map(|x| x.slot);
+        }
+    }
+
+    /// Applies the changes of a journaled block, the same way they were applied
+    /// when the block was first enriched
+    fn replay_ops(&mut self, slot: u64, ops: Vec<Op>) {
+        let mut undo = BlockUndo {
+            slot,
+            spent: Vec::new(),
+            created: Vec::new(),
+        };
+
+        let mut created = HashSet::new();
+
+        for (key, op) in ops {
+            match op {
+                Some(entry) => {
+                    self.cache.insert(key.clone(), entry);
+                    created.insert(key.clone());
+                    undo.created.push(key);
+                }
+                None => {
+                    if let Some(entry) = self.cache.remove(&key) {
+                        if !created.contains(&key) {
+                            undo.spent.push((key, entry));
+                        }
+                    }
+                }
+            }
+        }
+
+        if let super::Mode::Utxos = self.config.mode.unwrap_or_default() {
+            self.push_undo(undo);
+        }
+    }
+
+    /// Reverts the changes of every block after the specified point
+    ///
+    /// Fails if the rollback goes beyond the blocks for which we keep undo
+    /// data, since the cache would be left in an inconsistent state.
+    fn undo_block_utxos(&mut self, point: &Point) -> Result<(), crate::Error> {
+        let target = match point {
+            Point::Origin => None,
+            Point::Specific(slot, _) => Some(*slot),
+        };
+
+        if self.undo_floor.is_some() && target < self.undo_floor {
+            return Err(crate::Error::storage(format!(
+                "rollback to {:?} goes beyond the available undo data",
+                point
+            )));
+        }
+
+        while let Some(undo) = self.undo.back() {
+            if Some(undo.slot) <= target {
+                break;
+            }
+
+            let undo = self.undo.pop_back().unwrap();
+
+            for key in undo.created {
+                self.cache.remove(&key);
+            }
+
+            for (key, entry) in undo.spent {
+                self.cache.insert(key, entry);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Loads the snapshot and replays its journal, then rolls the cache back to
+    /// the storage cursor, since blocks enriched after it never got committed
+    fn restore(&mut self, path: &str) -> Result<(), crate::Error> {
+        let snapshot = match load_snapshot(path)? {
+            Some(x) => x,
+            None => {
+                log::info!("no enrich snapshot found at {}, starting cold", path);
+                return Ok(());
+            }
+        };
+
+        log::info!(
+            "warming up enrich cache with {} entries",
+            snapshot.entries.len()
+        );
+
+        self.generation = snapshot.generation;
+        self.point = snapshot.point.map(restore_point);
+        self.undo_floor = snapshot.undo_floor;
+        self.cache.load(snapshot.entries);
+
+        for (slot, spent, created) in snapshot.undo {
+            self.undo.push_back(BlockUndo {
+                slot,
+                spent,
+                created,
+            });
+        }
+
+        let records = read_journal(path, self.generation)?;
+        log::info!("replaying {} enrich journal records", records.len());
+
+        for (_, point, ops) in records {
+            let point = restore_point(point);
+
+            match (ops, &point) {
+                (Some(ops), Point::Specific(slot, _)) => self.replay_ops(*slot, ops),
+                (None, _) => {
+                    if let super::Mode::Utxos = self.config.mode.unwrap_or_default() {
+                        self.undo_block_utxos(&point)?;
+                    }
+                }
+                (Some(_), Point::Origin) => (),
+            }
+
+            self.point = Some(point);
+        }
+
+        self.align_to_cursor()
+    }
+
+    /// Only the utxo set needs to match the cursor exactly, extra txs are
+    /// harmless when keeping every tx
+    fn align_to_cursor(&mut self) -> Result<(), crate::Error> {
+        let cursor: Point = match self.cursor.clone() {
+            Some(x) => x.try_into()?,
+            None => return Ok(()),
+        };
+
+        let (slot, target) = match (&self.point, &cursor) {
+            (Some(Point::Specific(slot, _)), Point::Specific(target, _)) => (*slot, *target),
+            _ => return Ok(()),
+        };
+
+        if let super::Mode::Txs = self.config.mode.unwrap_or_default() {
+            return Ok(());
+        }
+
+        if slot < target {
+            return Err(crate::Error::storage(format!(
+                "enrich snapshot at slot {} is behind the storage cursor {:?}",
+                slot, cursor
+            )));
+        }
+
+        if slot > target {
+            log::info!(
+                "rolling enrich cache back to the storage cursor {:?}",
+                cursor
+            );
+            self.undo_block_utxos(&cursor)?;
+            self.point = Some(cursor);
+        }
+
+        Ok(())
+    }
+
+    /// Appends the changes of the latest block or rollback to the journal,
+    /// before anything downstream gets to see it
+    fn journal_point(&mut self, point: &Point, forward: bool) -> Result<(), crate::Error> {
+        self.point = Some(point.clone());
+
+        let journal = match &mut self.journal {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        let ops = match forward {
+            true => Some(std::mem::take(&mut self.ops)),
+            false => None,
+        };
+
+        let record: JournalRecord = (self.generation, save_point(point), ops);
+        let raw = minicbor::to_vec(&record).map_err(crate::Error::cbor)?;
+
+        journal
+            .write_all(&(raw.len() as u32).to_be_bytes())
+            .and_then(|_| journal.write_all(&raw))
+            .and_then(|_| journal.flush())
+            .map_err(crate::Error::storage)
+    }
+
+    /// Saves a new generation of the snapshot and starts an empty journal on
+    /// top of it
+    fn save_snapshot(&mut self) -> Result<(), crate::Error> {
+        let path = match &self.config.snapshot_path {
+            Some(x) => x.clone(),
+            None => return Ok(()),
+        };
+
+        self.generation += 1;
+
+        let snapshot = Snapshot {
+            generation: self.generation,
+            point: self.point.as_ref().map(save_point),
+            undo_floor: self.undo_floor,
+            undo: self
+                .undo
+                .iter()
+                .map(|x| (x.slot, x.spent.clone(), x.created.clone()))
+                .collect(),
+            entries: self.cache.dump(),
+        };
+
+        save_snapshot(&path, &snapshot)?;
+        self.journal = Some(create_journal(&path)?);
+        self.blocks_since_snapshot = 0;
+
+        Ok(())
+    }
+
+    fn maybe_save_snapshot(&mut self) -> Result<(), crate::Error> {
+        if self.journal.is_none() {
+            return Ok(());
+        }
+
+        self.blocks_since_snapshot += 1;
+
+        let interval = self
+            .config
+            .snapshot_interval
+            .unwrap_or(DEFAULT_SNAPSHOT_INTERVAL);
+
+        if self.blocks_since_snapshot >= interval {
+            self.save_snapshot()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl gasket::runtime::Worker for Worker {
+    fn metrics(&self) -> gasket::metrics::Registry {
+        gasket::metrics::Builder::new()
+            .with_counter("hit_count", &self.hit_count)
+            .with_counter("miss_count", &self.miss_count)
+            .with_gauge("entries", &self.entries)
+            .build()
+    }
+
+    fn work(&mut self) -> gasket::runtime::WorkResult {
+        let msg = match self.input.recv() {
+            Ok(x) => x,
+            // upstream stages are gone (eg: finalized sync), nothing else will arrive
+            Err(gasket::error::Error::RecvError) => return Ok(WorkOutcome::Done),
+            Err(err) => return Err(err),
+        };
+
+        match msg.payload {
+            model::RawBlockPayload::RollForward(raw) => {
+                let block = raw.decode().or_work_err()?;
+                let raw_txs = super::split_block(&raw).or_work_err()?;
+
+                let mut ctx = match self.config.mode.unwrap_or_default() {
+                    super::Mode::Txs => self.track_block_txs(&block, raw_txs.as_ref()),
+                    super::Mode::Utxos => self.track_block_utxos(&block, raw_txs.as_ref()),
+                }
+                .or_work_err()?;
+
+                super::import_witnesses(&mut ctx, raw_txs.as_ref()).or_work_err()?;
+
+                self.entries.set(self.cache.len() as i64);
+                self.journal_point(&raw.point, true).or_work_err()?;
+                self.maybe_save_snapshot().or_work_err()?;
+
+                self.output
+                    .send(model::EnrichedBlockPayload::roll_forward(raw.clone(), ctx))?;
+            }
+            model::RawBlockPayload::RollBack(x) => {
+                if let super::Mode::Utxos = self.config.mode.unwrap_or_default() {
+                    self.undo_block_utxos(&x).or_work_err()?;
+                    self.entries.set(self.cache.len() as i64);
+                }
+
+                self.journal_point(&x, false).or_work_err()?;
+
+                self.output
+                    .send(model::EnrichedBlockPayload::roll_back(x))?;
+            }
+        };
+
+        Ok(WorkOutcome::Partial)
+    }
+
+    fn bootstrap(&mut self) -> Result<(), gasket::error::Error> {
+        if let Some(path) = self.config.snapshot_path.clone() {
+            self.restore(&path).or_work_err()?;
+
+            // compacting right away drops journal records past the cursor
+            self.save_snapshot().or_work_err()?;
+        }
+
+        Ok(())
+    }
+
+    fn teardown(&mut self) -> Result<(), gasket::error::Error> {
+        // a block that failed halfway through left the cache ahead of the
+        // journal, which is the one to trust on the next start
+        if !self.ops.is_empty() {
+            log::warn!("skipping enrich snapshot, last block was partially applied");
+            return Ok(());
+        }
+
+        self.save_snapshot().or_work_err()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(x: u8) -> Entry {
+        (1, vec![x])
+    }
+
+    #[test]
+    fn lru_evicts_least_recently_used() {
+        let mut cache = LruCache::new(2);
+
+        cache.insert("a".into(), entry(1));
+        cache.insert("b".into(), entry(2));
+
+        // touching "a" leaves "b" as the oldest one
+        assert_eq!(cache.get("a"), Some(entry(1)));
+
+        cache.insert("c".into(), entry(3));
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("a"), Some(entry(1)));
+        assert_eq!(cache.get("c"), Some(entry(3)));
+    }
+
+    #[test]
+    fn lru_reinsert_replaces_entry() {
+        let mut cache = LruCache::new(2);
+
+        cache.insert("a".into(), entry(1));
+        cache.insert("a".into(), entry(2));
+
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.remove("a"), Some(entry(2)));
+        assert_eq!(cache.remove("a"), None);
+        assert!(cache.dump().is_empty());
+    }
+
+    #[test]
+    fn lru_snapshot_roundtrip() {
+        let path = std::env::temp_dir().join(format!("scrolls-lru-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut cache = LruCache::new(3);
+        cache.insert("a".into(), entry(1));
+        cache.insert("b".into(), entry(2));
+        cache.get("a");
+
+        let snapshot = Snapshot {
+            entries: cache.dump(),
+            ..Default::default()
+        };
+
+        save_snapshot(path, &snapshot).unwrap();
+        let loaded = load_snapshot(path).unwrap().unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        // usage order survives the roundtrip
+        assert_eq!(loaded.entries, cache.dump());
+        assert_eq!(loaded.entries[0].0, "b");
+    }
+
+    fn worker(path: &str, cursor: crosscut::Cursor) -> Worker {
+        let config = Config {
+            max_entries: 10,
+            mode: Some(super::super::Mode::Utxos),
+            snapshot_path: Some(path.to_string()),
+            snapshot_interval: None,
+        };
+
+        Worker {
+            cache: LruCache::new(config.max_entries),
+            config,
+            cursor,
+            point: None,
+            undo: Default::default(),
+            undo_floor: None,
+            generation: 0,
+            journal: None,
+            ops: Vec::new(),
+            blocks_since_snapshot: 0,
+            input: Default::default(),
+            output: Default::default(),
+            hit_count: Default::default(),
+            miss_count: Default::default(),
+            entries: Default::default(),
+        }
+    }
+
+    #[test]
+    fn restore_rolls_back_to_cursor() {
+        let path = std::env::temp_dir().join(format!("scrolls-journal-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let first = Point::Specific(10, vec![1]);
+        let second = Point::Specific(20, vec![2]);
+
+        let mut before = worker(path, None);
+        before.save_snapshot().unwrap();
+
+        before.insert("a#0".into(), entry(1));
+        before.journal_point(&first, true).unwrap();
+
+        before.remove("a#0");
+        before.insert("b#0".into(), entry(2));
+        before.journal_point(&second, true).unwrap();
+
+        // storage only committed the first block before the crash
+        let mut after = worker(path, Some(first.clone().into()));
+        after.restore(path).unwrap();
+
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(journal_path(path)).unwrap();
+
+        assert_eq!(after.point, Some(first));
+        assert_eq!(after.cache.get("a#0"), Some(entry(1)));
+        assert_eq!(after.cache.get("b#0"), None);
+    }
+}
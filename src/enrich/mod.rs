@@ -1,44 +1,238 @@
+pub mod import;
+pub mod memory;
+pub mod raw;
+pub mod report;
+pub mod rocksdb;
+pub mod skip;
 pub mod sled;
 
 use gasket::messaging::{InputPort, OutputPort};
+use pallas::ledger::traverse::{Era, MultiEraOutput, MultiEraTx, OutputRef};
 use serde::Deserialize;
 
-use crate::{bootstrap, model};
+use crate::{bootstrap, crosscut, model};
 
-#[derive(Deserialize)]
+/// Defines what the enrich stage keeps track of
+#[derive(Deserialize, Clone, Copy)]
+pub enum Mode {
+    /// Keeps the cbor of every tx ever seen, keyed by hash
+    Txs,
+    /// Keeps only the unspent outputs, keyed by `txhash#index`
+    Utxos,
+}
+
+impl Default for Mode {
+    fn default() -> Self {
+        Mode::Txs
+    }
+}
+
+/// Imports a tx referenced by an input into the context, along with the
+/// specific output that the input consumes
+pub(crate) fn import_ref_tx(
+    ctx: &mut model::BlockContext,
+    output_ref: &OutputRef,
+    era: Era,
+    cbor: Vec<u8>,
+) -> Result<(), crate::Error> {
+    let index = output_ref.tx_index() as usize;
+
+    let output = match raw::tx_output(&cbor, index).ok().flatten() {
+        Some(original) => Some(original.to_vec()),
+        // the tx is only decoded when its original bytes can't be walked
+        None => {
+            let tx = MultiEraTx::decode(era, &cbor).map_err(crate::Error::cbor)?;
+
+            match crosscut::utxos::output_at(&tx, index) {
+                Some(output) => Some(output.encode().map_err(crate::Error::cbor)?),
+                None => None,
+            }
+        }
+    };
+
+    if let Some(body) = output {
+        ctx.import_ref_output(output_ref, era, body);
+    }
+
+    ctx.import_ref_tx(output_ref.tx_id(), era, cbor);
+
+    Ok(())
+}
+
+/// Indexes by hash the datums and scripts available to the block, either from
+/// the witness sets, the outputs of the block or the outputs it references.
+/// Hashes are computed over the original bytes of each item.
+pub(crate) fn import_witnesses(
+    ctx: &mut model::BlockContext,
+    block: Option<&raw::BlockTxs>,
+) -> Result<(), crate::Error> {
+    let mut witnesses = raw::Witnesses::default();
+
+    if let Some(block) = block {
+        for witness_set in block.witness_sets.iter() {
+            witnesses
+                .collect_witness_set(witness_set)
+                .map_err(crate::Error::cbor)?;
+        }
+
+        for body in block.bodies.iter() {
+            for output in raw::body_outputs(body).map_err(crate::Error::cbor)? {
+                witnesses
+                    .collect_output(output)
+                    .map_err(crate::Error::cbor)?;
+            }
+        }
+    }
+
+    for output in ctx.ref_output_cbors() {
+        witnesses
+            .collect_output(output)
+            .map_err(crate::Error::cbor)?;
+    }
+
+    for datum in witnesses.datums {
+        ctx.import_datum(datum);
+    }
+
+    for script in witnesses.scripts {
+        ctx.import_script(script);
+    }
+
+    Ok(())
+}
+
+/// Splits the block into the original bytes of its txs, `None` for eras where
+/// we can't (eg: Byron), which don't carry datums nor scripts anyway
+pub(crate) fn split_block(raw: &model::RawBlock) -> Result<Option<raw::BlockTxs>, crate::Error> {
+    raw::block_txs(&raw.cbor).map_err(crate::Error::cbor)
+}
+
+/// The original bytes of the tx at `index` of the block, re-encoded only when
+/// the block couldn't be split
+pub(crate) fn tx_cbor(
+    block: Option<&raw::BlockTxs>,
+    index: usize,
+    tx: &MultiEraTx,
+) -> Result<Vec<u8>, crate::Error> {
+    match block.and_then(|x| x.tx_cbor(index)) {
+        Some(cbor) => Ok(cbor),
+        None => tx.encode().map_err(crate::Error::cbor),
+    }
+}
+
+/// The original bytes of an output produced by the tx at `tx_index` of the
+/// block, re-encoded only when the block couldn't be split
+pub(crate) fn output_cbor(
+    block: Option<&raw::BlockTxs>,
+    tx_index: usize,
+    output_index: usize,
+    output: &MultiEraOutput,
+) -> Result<Vec<u8>, crate::Error> {
+    let original = match block {
+        Some(block) => block
+            .output(tx_index, output_index)
+            .map_err(crate::Error::cbor)?,
+        None => None,
+    };
+
+    match original {
+        Some(cbor) => Ok(cbor.to_vec()),
+        None => output.encode().map_err(crate::Error::cbor),
+    }
+}
+
+#[derive(Deserialize, Clone)]
 #[serde(tag = "type")]
 pub enum Config {
     Sled(sled::Config),
+    Memory(memory::Config),
+    RocksDb(rocksdb::Config),
 }
 
 impl Config {
     pub fn bootstrapper(self) -> Bootstrapper {
         match self {
             Config::Sled(c) => Bootstrapper::Sled(c.boostrapper()),
+            Config::Memory(c) => Bootstrapper::Memory(c.boostrapper()),
+            Config::RocksDb(c) => Bootstrapper::RocksDb(c.boostrapper()),
+        }
+    }
+
+    /// Checks that the backend can be shared by the ranges of a swarm, which
+    /// crawl the history in parallel and in arbitrary order
+    ///
+    /// Only the persistent backends can be shared, and only while keeping every
+    /// tx, since tracking utxos depends on applying blocks in chain order.
+    pub fn check_swarm(&self) -> Result<(), crate::Error> {
+        let mode = match self {
+            Config::Sled(c) => c.mode,
+            Config::RocksDb(c) => c.mode,
+            Config::Memory(_) => {
+                return Err(crate::Error::config(
+                    "the Memory enrich backend can't be shared by swarm ranges",
+                ))
+            }
+        };
+
+        match mode.unwrap_or_default() {
+            Mode::Txs => Ok(()),
+            Mode::Utxos => Err(crate::Error::config(
+                "swarm mode needs the enrich backend to run with `mode = \"Txs\"`",
+            )),
+        }
+    }
+
+    /// Loads a utxo dump into the backend, only backends that track utxos can
+    /// make use of it
+    pub fn import_utxos(self, entries: import::DumpEntries) -> Result<u64, crate::Error> {
+        match self {
+            Config::Sled(c) => c.import_utxos(entries),
+            Config::Memory(c) => c.import_utxos(entries),
+            Config::RocksDb(_) => Err(crate::Error::config(
+                "utxo dumps can't be imported into a RocksDb backend",
+            )),
         }
     }
 }
 
 pub enum Bootstrapper {
     Sled(sled::Bootstrapper),
+    Memory(memory::Bootstrapper),
+    RocksDb(rocksdb::Bootstrapper),
+    Skip(skip::Bootstrapper),
 }
 
 impl Bootstrapper {
+    /// A stage that doesn't resolve any data, used when no enrich backend is
+    /// configured
+    pub fn skip() -> Self {
+        Bootstrapper::Skip(Default::default())
+    }
+
     pub fn borrow_input_port(&mut self) -> &'_ mut InputPort<model::RawBlockPayload> {
         match self {
             Bootstrapper::Sled(x) => x.borrow_input_port(),
+            Bootstrapper::Memory(x) => x.borrow_input_port(),
+            Bootstrapper::RocksDb(x) => x.borrow_input_port(),
+            Bootstrapper::Skip(x) => x.borrow_input_port(),
         }
     }
 
     pub fn borrow_output_port(&mut self) -> &'_ mut OutputPort<model::EnrichedBlockPayload> {
         match self {
             Bootstrapper::Sled(x) => x.borrow_output_port(),
+            Bootstrapper::Memory(x) => x.borrow_output_port(),
+            Bootstrapper::RocksDb(x) => x.borrow_output_port(),
+            Bootstrapper::Skip(x) => x.borrow_output_port(),
         }
     }
 
-    pub fn spawn_stages(self, pipeline: &mut bootstrap::Pipeline) {
+    pub fn spawn_stages(self, pipeline: &mut bootstrap::Pipeline, cursor: &crosscut::Cursor) {
         match self {
             Bootstrapper::Sled(x) => x.spawn_stages(pipeline),
+            Bootstrapper::Memory(x) => x.spawn_stages(pipeline, cursor),
+            Bootstrapper::RocksDb(x) => x.spawn_stages(pipeline),
+            Bootstrapper::Skip(x) => x.spawn_stages(pipeline),
         }
     }
 }
@@ -0,0 +1,444 @@
+use std::collections::{HashMap, HashSet};
+
+use pallas::codec::minicbor::{data::Type, decode, Decoder};
+
+use crate::model;
+
+// Hashes of datums and scripts are computed over the bytes as they were
+// submitted, which a decode / encode roundtrip doesn't always reproduce. These
+// helpers walk the cbor of blocks, txs and outputs to find the original bytes
+// of the parts that we need to hash or store.
+
+type DecodeResult<T> = Result<T, decode::Error>;
+
+/// Consumes the next item, returning its original bytes
+fn raw_item<'b>(input: &'b [u8], d: &mut Decoder<'b>) -> DecodeResult<&'b [u8]> {
+    let start = d.position();
+    d.skip()?;
+
+    Ok(&input[start..d.position()])
+}
+
+fn skip_break(d: &mut Decoder) -> DecodeResult<()> {
+    d.set_position(d.position() + 1);
+    Ok(())
+}
+
+/// Visits every item of an array, either of definite or indefinite length
+fn for_each_item<'b>(
+    d: &mut Decoder<'b>,
+    mut visit: impl FnMut(&mut Decoder<'b>) -> DecodeResult<()>,
+) -> DecodeResult<()> {
+    match d.array()? {
+        Some(len) => {
+            for _ in 0..len {
+                visit(d)?;
+            }
+        }
+        None => {
+            while d.datatype()? != Type::Break {
+                visit(d)?;
+            }
+
+            skip_break(d)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Visits every entry of a map with unsigned keys, the visitor needs to
+/// consume the value
+fn for_each_entry<'b>(
+    d: &mut Decoder<'b>,
+    mut visit: impl FnMut(u64, &mut Decoder<'b>) -> DecodeResult<()>,
+) -> DecodeResult<()> {
+    match d.map()? {
+        Some(len) => {
+            for _ in 0..len {
+                let key = d.u64()?;
+                visit(key, d)?;
+            }
+        }
+        None => {
+            while d.datatype()? != Type::Break {
+                let key = d.u64()?;
+                visit(key, d)?;
+            }
+
+            skip_break(d)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn is_map(d: &Decoder) -> DecodeResult<bool> {
+    Ok(matches!(d.datatype()?, Type::Map | Type::MapIndef))
+}
+
+/// Original bytes of the txs of a block, in block order
+pub struct BlockTxs<'b> {
+    pub bodies: Vec<&'b [u8]>,
+    pub witness_sets: Vec<&'b [u8]>,
+    auxiliary_data: HashMap<usize, &'b [u8]>,
+    invalid_txs: HashSet<usize>,
+}
+
+impl<'b> BlockTxs<'b> {
+    /// Assembles a standalone tx out of the original bytes of its parts, the
+    /// same layout that a decoded tx would be encoded into
+    ///
+    /// Txs before Alonzo don't carry a validity flag on chain, but pallas
+    /// decodes them with the Alonzo layout, so they're always assembled as
+    /// 4-element arrays flagged as valid.
+    pub fn tx_cbor(&self, index: usize) -> Option<Vec<u8>> {
+        let body = self.bodies.get(index)?;
+        let witness_set = self.witness_sets.get(index)?;
+
+        let mut cbor = vec![0x84];
+        cbor.extend_from_slice(body);
+        cbor.extend_from_slice(witness_set);
+
+        match self.invalid_txs.contains(&index) {
+            true => cbor.push(0xf4),
+            false => cbor.push(0xf5),
+        }
+
+        match self.auxiliary_data.get(&index) {
+            Some(aux) => cbor.extend_from_slice(aux),
+            None => cbor.push(0xf6),
+        }
+
+        Some(cbor)
+    }
+
+    /// Finds the original bytes of an output of a tx of the block
+    pub fn output(&self, tx_index: usize, output_index: usize) -> DecodeResult<Option<&'b [u8]>> {
+        match self.bodies.get(tx_index) {
+            Some(body) => body_output(body, output_index),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Splits a block into the original bytes of its txs, Byron blocks aren't
+/// supported since their txs have a different layout
+pub fn block_txs(cbor: &[u8]) -> DecodeResult<Option<BlockTxs>> {
+    let mut d = Decoder::new(cbor);
+
+    d.array()?;
+
+    // eras 0 and 1 are the Byron EBB and main blocks
+    let era = d.u16()?;
+
+    if era < 2 {
+        return Ok(None);
+    }
+
+    let len = d.array()?;
+
+    // header
+    d.skip()?;
+
+    let mut bodies = Vec::new();
+    for_each_item(&mut d, |d| {
+        bodies.push(raw_item(cbor, d)?);
+        Ok(())
+    })?;
+
+    let mut witness_sets = Vec::new();
+    for_each_item(&mut d, |d| {
+        witness_sets.push(raw_item(cbor, d)?);
+        Ok(())
+    })?;
+
+    let mut auxiliary_data = HashMap::new();
+    for_each_entry(&mut d, |key, d| {
+        auxiliary_data.insert(key as usize, raw_item(cbor, d)?);
+        Ok(())
+    })?;
+
+    let mut invalid_txs = HashSet::new();
+
+    // the list of invalid txs was introduced in Alonzo
+    if len != Some(4) && d.datatype()? != Type::Break {
+        for_each_item(&mut d, |d| {
+            invalid_txs.insert(d.u64()? as usize);
+            Ok(())
+        })?;
+    }
+
+    Ok(Some(BlockTxs {
+        bodies,
+        witness_sets,
+        auxiliary_data,
+        invalid_txs,
+    }))
+}
+
+/// Lists the original bytes of the outputs of a tx body, the collateral
+/// return sits right after the regular outputs. Byron bodies aren't supported.
+pub fn body_outputs(body: &[u8]) -> DecodeResult<Vec<&[u8]>> {
+    let mut d = Decoder::new(body);
+
+    // Byron tx bodies are arrays
+    if !is_map(&d)? {
+        return Ok(vec![]);
+    }
+
+    let mut outputs = Vec::new();
+    let mut collateral_return = None;
+
+    for_each_entry(&mut d, |key, d| {
+        match key {
+            1 => for_each_item(d, |d| {
+                outputs.push(raw_item(body, d)?);
+                Ok(())
+            })?,
+            16 => collateral_return = Some(raw_item(body, d)?),
+            _ => d.skip()?,
+        };
+
+        Ok(())
+    })?;
+
+    outputs.extend(collateral_return);
+
+    Ok(outputs)
+}
+
+/// Finds the original bytes of an output of a tx body by index
+pub fn body_output(body: &[u8], index: usize) -> DecodeResult<Option<&[u8]>> {
+    Ok(body_outputs(body)?.get(index).copied())
+}
+
+/// Finds the original bytes of an output of a standalone tx, encoded as an
+/// array that starts with the tx body
+pub fn tx_output(tx: &[u8], index: usize) -> DecodeResult<Option<&[u8]>> {
+    let mut d = Decoder::new(tx);
+
+    d.array()?;
+    let body = raw_item(tx, &mut d)?;
+
+    body_output(body, index)
+}
+
+/// Datums and scripts found in the original bytes of witness sets or outputs
+#[derive(Default)]
+pub struct Witnesses {
+    pub datums: Vec<Vec<u8>>,
+    pub scripts: Vec<model::Script>,
+}
+
+impl Witnesses {
+    pub fn collect_witness_set(&mut self, witness_set: &[u8]) -> DecodeResult<()> {
+        let mut d = Decoder::new(witness_set);
+
+        for_each_entry(&mut d, |key, d| {
+            match key {
+                1 => for_each_item(d, |d| {
+                    let raw = raw_item(witness_set, d)?;
+                    self.scripts.push(model::Script::Native(raw.to_vec()));
+                    Ok(())
+                })?,
+                3 => for_each_item(d, |d| {
+                    self.scripts
+                        .push(model::Script::PlutusV1(d.bytes()?.to_vec()));
+                    Ok(())
+                })?,
+                4 => for_each_item(d, |d| {
+                    self.datums.push(raw_item(witness_set, d)?.to_vec());
+                    Ok(())
+                })?,
+                6 => for_each_item(d, |d| {
+                    self.scripts
+                        .push(model::Script::PlutusV2(d.bytes()?.to_vec()));
+                    Ok(())
+                })?,
+                _ => d.skip()?,
+            };
+
+            Ok(())
+        })
+    }
+
+    /// Inline datums and reference scripts are wrapped as cbor-in-cbor, so
+    /// their original bytes are the content of the wrapping byte string
+    pub fn collect_output(&mut self, output: &[u8]) -> DecodeResult<()> {
+        let mut d = Decoder::new(output);
+
+        // legacy outputs are arrays, they can only hold a datum hash
+        if !is_map(&d)? {
+            return Ok(());
+        }
+
+        for_each_entry(&mut d, |key, d| {
+            match key {
+                2 => {
+                    d.array()?;
+
+                    match d.u8()? {
+                        1 => {
+                            d.tag()?;
+                            self.datums.push(d.bytes()?.to_vec());
+                        }
+                        _ => d.skip()?,
+                    }
+                }
+                3 => {
+                    d.tag()?;
+
+                    if let Some(script) = script_ref(d.bytes()?)? {
+                        self.scripts.push(script);
+                    }
+                }
+                _ => d.skip()?,
+            };
+
+            Ok(())
+        })
+    }
+}
+
+fn script_ref(cbor: &[u8]) -> DecodeResult<Option<model::Script>> {
+    let mut d = Decoder::new(cbor);
+
+    d.array()?;
+
+    let script = match d.u8()? {
+        0 => Some(model::Script::Native(raw_item(cbor, &mut d)?.to_vec())),
+        1 => Some(model::Script::PlutusV1(d.bytes()?.to_vec())),
+        2 => Some(model::Script::PlutusV2(d.bytes()?.to_vec())),
+        _ => None,
+    };
+
+    Ok(script)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pallas::codec::minicbor::Encoder;
+
+    #[test]
+    fn finds_outputs_and_collateral_return() {
+        let mut e = Encoder::new(Vec::new());
+
+        // body with two outputs, one of them a non-canonical indefinite array
+        e.map(2).unwrap();
+        e.u8(1).unwrap().array(2).unwrap();
+        e.array(2).unwrap().bytes(&[1]).unwrap().u8(5).unwrap();
+        e.begin_array().unwrap().bytes(&[2]).unwrap().u8(6).unwrap();
+        e.end().unwrap();
+        e.u8(16).unwrap();
+        e.array(2).unwrap().bytes(&[3]).unwrap().u8(7).unwrap();
+
+        let body = e.into_writer();
+
+        assert_eq!(
+            body_output(&body, 0).unwrap(),
+            Some(&[0x82, 0x41, 0x01, 0x05][..])
+        );
+
+        assert_eq!(
+            body_output(&body, 1).unwrap(),
+            Some(&[0x9f, 0x41, 0x02, 0x06, 0xff][..])
+        );
+
+        assert_eq!(
+            body_output(&body, 2).unwrap(),
+            Some(&[0x82, 0x41, 0x03, 0x07][..])
+        );
+
+        assert_eq!(body_output(&body, 3).unwrap(), None);
+    }
+
+    #[test]
+    fn assembles_txs_from_block_parts() {
+        let mut e = Encoder::new(Vec::new());
+
+        // an Alonzo block with a single tx, flagged as invalid
+        e.array(2).unwrap().u16(5).unwrap();
+        e.array(5).unwrap();
+        e.array(0).unwrap();
+        e.array(1).unwrap().map(0).unwrap();
+        e.array(1).unwrap().begin_map().unwrap().end().unwrap();
+        e.map(1).unwrap().u8(0).unwrap().u8(42).unwrap();
+        e.array(1).unwrap().u8(0).unwrap();
+
+        let block = e.into_writer();
+        let txs = block_txs(&block).unwrap().unwrap();
+
+        assert_eq!(
+            txs.tx_cbor(0).unwrap(),
+            vec![0x84, 0xa0, 0xbf, 0xff, 0xf4, 0x18, 0x2a]
+        );
+
+        assert_eq!(txs.tx_cbor(1), None);
+    }
+
+    #[test]
+    fn assembled_mary_txs_decode() {
+        let mut e = Encoder::new(Vec::new());
+
+        // a Mary block with a single tx spending one input into one output
+        e.array(2).unwrap().u16(4).unwrap();
+        e.array(4).unwrap();
+        e.array(0).unwrap();
+        e.array(1).unwrap().map(3).unwrap();
+        e.u8(0).unwrap().array(1).unwrap();
+        e.array(2).unwrap().bytes(&[0; 32]).unwrap().u8(0).unwrap();
+        e.u8(1).unwrap().array(1).unwrap();
+        e.array(2).unwrap().bytes(&[0x61; 29]).unwrap();
+        e.u32(1000000).unwrap();
+        e.u8(2).unwrap().u32(170000).unwrap();
+        e.array(1).unwrap().map(0).unwrap();
+        e.map(0).unwrap();
+
+        let block = e.into_writer();
+        let txs = block_txs(&block).unwrap().unwrap();
+
+        let cbor = txs.tx_cbor(0).unwrap();
+        assert_eq!(cbor[0], 0x84);
+
+        let tx = pallas::ledger::traverse::MultiEraTx::decode(
+            pallas::ledger::traverse::Era::Mary,
+            &cbor,
+        )
+        .unwrap();
+
+        assert_eq!(tx.outputs().len(), 1);
+        assert_eq!(tx_output(&cbor, 0).unwrap(), txs.output(0, 0).unwrap());
+    }
+
+    #[test]
+    fn skips_byron_blocks() {
+        let mut e = Encoder::new(Vec::new());
+        e.array(2).unwrap().u16(1).unwrap().array(0).unwrap();
+
+        assert!(block_txs(&e.into_writer()).unwrap().is_none());
+    }
+
+    #[test]
+    fn keeps_original_datum_bytes() {
+        // an inline datum encoded with a non-canonical indefinite list
+        let datum = vec![0x9f, 0x01, 0xff];
+
+        let mut e = Encoder::new(Vec::new());
+        e.map(2).unwrap();
+        e.u8(0).unwrap().bytes(&[0xaa]).unwrap();
+        e.u8(2).unwrap().array(2).unwrap().u8(1).unwrap();
+        e.tag(pallas::codec::minicbor::data::Tag::Cbor).unwrap();
+        e.bytes(&datum).unwrap();
+
+        let output = e.into_writer();
+
+        let mut witnesses = Witnesses::default();
+        witnesses.collect_output(&output).unwrap();
+
+        assert_eq!(witnesses.datums, vec![datum]);
+        assert!(witnesses.scripts.is_empty());
+    }
+}
@@ -0,0 +1,170 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    time::Duration,
+};
+
+use pallas::{crypto::hash::Hash, ledger::traverse::OutputRef};
+use redis::Commands;
+use serde::{Deserialize, Serialize};
+
+/// Where to report the inputs that the enrich stage couldn't resolve
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type")]
+pub enum Config {
+    /// Appends one json line per missing input to a local file
+    File { path: String },
+    /// Pushes one json entry per missing input into a Redis list
+    Redis {
+        connection_params: String,
+        key: String,
+    },
+}
+
+impl Config {
+    pub fn open(&self) -> Result<Report, crate::Error> {
+        match self {
+            Config::File { path } => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(crate::Error::storage)?;
+
+                Ok(Report::File(BufWriter::new(file)))
+            }
+            Config::Redis {
+                connection_params,
+                key,
+            } => {
+                let connection = redis::Client::open(connection_params.clone())
+                    .and_then(|x| x.get_connection())
+                    .map_err(crate::Error::storage)?;
+
+                Ok(Report::Redis(connection, key.clone()))
+            }
+        }
+    }
+}
+
+/// An input that couldn't be resolved, along with the tx that consumes it
+#[derive(Serialize)]
+pub struct MissingInput {
+    pub slot: u64,
+    pub tx: String,
+    pub input: String,
+}
+
+impl MissingInput {
+    pub fn new(slot: u64, tx: &Hash<32>, input: &OutputRef) -> Self {
+        Self {
+            slot,
+            tx: tx.to_string(),
+            input: format!("{}#{}", input.tx_id(), input.tx_index()),
+        }
+    }
+}
+
+pub enum Report {
+    File(BufWriter<File>),
+    Redis(redis::Connection, String),
+}
+
+impl Report {
+    pub fn write(&mut self, entries: &[MissingInput]) -> Result<(), crate::Error> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let lines = entries
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| crate::Error::message(err.to_string()))?;
+
+        match self {
+            Report::File(writer) => {
+                for line in lines {
+                    writeln!(writer, "{}", line).map_err(crate::Error::storage)?;
+                }
+
+                writer.flush().map_err(crate::Error::storage)
+            }
+            Report::Redis(connection, key) => connection
+                .rpush(key.as_str(), lines)
+                .map_err(crate::Error::storage),
+        }
+    }
+}
+
+/// Distribution of db operation latencies
+///
+/// Gasket metrics only support counters and gauges, so the histogram is
+/// exposed as one counter per bucket.
+pub struct LatencyHistogram {
+    names: [&'static str; 4],
+    under_1ms: gasket::metrics::Counter,
+    under_10ms: gasket::metrics::Counter,
+    under_100ms: gasket::metrics::Counter,
+    over_100ms: gasket::metrics::Counter,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new([
+            "db_latency_under_1ms",
+            "db_latency_under_10ms",
+            "db_latency_under_100ms",
+            "db_latency_over_100ms",
+        ])
+    }
+}
+
+impl LatencyHistogram {
+    fn new(names: [&'static str; 4]) -> Self {
+        Self {
+            names,
+            under_1ms: Default::default(),
+            under_10ms: Default::default(),
+            under_100ms: Default::default(),
+            over_100ms: Default::default(),
+        }
+    }
+
+    /// Latencies of the db reads that resolve inputs
+    pub fn lookups() -> Self {
+        Self::new([
+            "db_lookup_latency_under_1ms",
+            "db_lookup_latency_under_10ms",
+            "db_lookup_latency_under_100ms",
+            "db_lookup_latency_over_100ms",
+        ])
+    }
+
+    /// Latencies of the db writes that store new data
+    pub fn writes() -> Self {
+        Self::new([
+            "db_write_latency_under_1ms",
+            "db_write_latency_under_10ms",
+            "db_write_latency_under_100ms",
+            "db_write_latency_over_100ms",
+        ])
+    }
+
+    pub fn observe(&self, elapsed: Duration) {
+        match elapsed.as_millis() {
+            0 => self.under_1ms.inc(1),
+            1..=9 => self.under_10ms.inc(1),
+            10..=99 => self.under_100ms.inc(1),
+            _ => self.over_100ms.inc(1),
+        }
+    }
+
+    pub fn register(&self, builder: gasket::metrics::Builder) -> gasket::metrics::Builder {
+        builder
+            .with_counter(self.names[0], &self.under_1ms)
+            .with_counter(self.names[1], &self.under_10ms)
+            .with_counter(self.names[2], &self.under_100ms)
+            .with_counter(self.names[3], &self.over_100ms)
+    }
+}
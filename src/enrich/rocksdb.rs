@@ -0,0 +1,235 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use gasket::{error::AsWorkError, runtime::WorkOutcome};
+
+use pallas::{
+    codec::minicbor,
+    ledger::traverse::{MultiEraBlock, OutputRef},
+};
+use rocksdb::{WriteBatch, DB};
+use serde::Deserialize;
+
+use crate::{
+    bootstrap, crosscut,
+    model::{self, BlockContext},
+};
+
+use super::report::LatencyHistogram;
+
+/// Databases opened by this process, keyed by path
+///
+/// RocksDB holds an exclusive lock over its files, so stages running
+/// concurrently (eg: swarm mode) need to share the same handle.
+static OPEN_DBS: Mutex<Vec<(String, Arc<DB>)>> = Mutex::new(Vec::new());
+
+fn open_shared_db(path: &str) -> Result<Arc<DB>, rocksdb::Error> {
+    let mut dbs = OPEN_DBS.lock().expect("open dbs lock");
+
+    if let Some((_, db)) = dbs.iter().find(|(x, _)| x == path) {
+        return Ok(db.clone());
+    }
+
+    let db = Arc::new(DB::open_default(path)?);
+    dbs.push((path.to_string(), db.clone()));
+
+    Ok(db)
+}
+
+type InputPort = gasket::messaging::InputPort<model::RawBlockPayload>;
+type OutputPort = gasket::messaging::OutputPort<model::EnrichedBlockPayload>;
+
+#[derive(Deserialize, Clone)]
+pub struct Config {
+    pub db_path: String,
+    /// Only `Txs` is supported, the backend doesn't keep undo data for utxos
+    pub mode: Option<super::Mode>,
+}
+
+impl Config {
+    pub fn boostrapper(self) -> Bootstrapper {
+        Bootstrapper {
+            config: self,
+            input: Default::default(),
+            output: Default::default(),
+        }
+    }
+}
+
+pub struct Bootstrapper {
+    config: Config,
+    input: InputPort,
+    output: OutputPort,
+}
+
+impl Bootstrapper {
+    pub fn borrow_input_port(&mut self) -> &'_ mut InputPort {
+        &mut self.input
+    }
+
+    pub fn borrow_output_port(&mut self) -> &'_ mut OutputPort {
+        &mut self.output
+    }
+
+    pub fn spawn_stages(self, pipeline: &mut bootstrap::Pipeline) {
+        let worker = Worker {
+            config: self.config,
+            db: None,
+            input: self.input,
+            output: self.output,
+            hit_count: Default::default(),
+            miss_count: Default::default(),
+            lookup_latency: LatencyHistogram::lookups(),
+            write_latency: LatencyHistogram::writes(),
+        };
+
+        pipeline.register_stage("enrich-rocksdb", worker, Default::default());
+    }
+}
+
+pub struct Worker {
+    config: Config,
+    db: Option<Arc<DB>>,
+    input: InputPort,
+    output: OutputPort,
+    hit_count: gasket::metrics::Counter,
+    miss_count: gasket::metrics::Counter,
+    lookup_latency: LatencyHistogram,
+    write_latency: LatencyHistogram,
+}
+
+fn encode_value(era: u16, body: Vec<u8>) -> Result<Vec<u8>, crate::Error> {
+    minicbor::to_vec((era, body)).map_err(crate::Error::cbor)
+}
+
+fn decode_value(value: &[u8]) -> Result<(u16, Vec<u8>), crate::Error> {
+    minicbor::decode(value).map_err(crate::Error::cbor)
+}
+
+impl Worker {
+    /// Stores every tx of the block with a single write batch and resolves all
+    /// of the inputs of the block with a single `multi_get`
+    fn track_block_txs(
+        &self,
+        block: &MultiEraBlock,
+        raw_txs: Option<&super::raw::BlockTxs>,
+    ) -> Result<BlockContext, crate::Error> {
+        let db = self.db.as_ref().unwrap();
+        let mut ctx = BlockContext::default();
+
+        let txs = block.txs();
+
+        let mut batch = WriteBatch::default();
+        let mut produced = HashMap::new();
+
+        for (tx_idx, tx) in txs.iter().enumerate() {
+            let era: u16 = tx.era().into();
+            let body = super::tx_cbor(raw_txs, tx_idx, tx)?;
+            batch.put(tx.hash(), encode_value(era, body.clone())?);
+            produced.insert(tx.hash(), (era, body));
+        }
+
+        // inputs referencing txs of this same block don't need to hit the db
+        let (local, remote): (Vec<OutputRef>, Vec<OutputRef>) = txs
+            .iter()
+            .flat_map(crosscut::utxos::all_inputs)
+            .partition(|output_ref| produced.contains_key(output_ref.tx_id()));
+
+        for output_ref in local {
+            let (era, cbor) = produced.get(output_ref.tx_id()).unwrap().clone();
+            let era = era.try_into().map_err(crate::Error::storage)?;
+            super::import_ref_tx(&mut ctx, &output_ref, era, cbor)?;
+        }
+
+        let start = Instant::now();
+        let found = db.multi_get(remote.iter().map(|x| x.tx_id()));
+        self.lookup_latency.observe(start.elapsed());
+
+        for (output_ref, value) in remote.iter().zip(found) {
+            match value.map_err(crate::Error::storage)? {
+                Some(value) => {
+                    let (era, cbor) = decode_value(&value)?;
+                    let era = era.try_into().map_err(crate::Error::storage)?;
+                    super::import_ref_tx(&mut ctx, output_ref, era, cbor)?;
+                    self.hit_count.inc(1);
+                }
+                None => self.miss_count.inc(1),
+            }
+        }
+
+        let start = Instant::now();
+        db.write(batch).map_err(crate::Error::storage)?;
+        self.write_latency.observe(start.elapsed());
+
+        Ok(ctx)
+    }
+}
+
+impl gasket::runtime::Worker for Worker {
+    fn metrics(&self) -> gasket::metrics::Registry {
+        let builder = gasket::metrics::Builder::new()
+            .with_counter("hit_count", &self.hit_count)
+            .with_counter("miss_count", &self.miss_count);
+
+        let builder = self.lookup_latency.register(builder);
+        self.write_latency.register(builder).build()
+    }
+
+    fn work(&mut self) -> gasket::runtime::WorkResult {
+        let msg = match self.input.recv() {
+            Ok(x) => x,
+            // upstream stages are gone (eg: finalized sync), nothing else will arrive
+            Err(gasket::error::Error::RecvError) => return Ok(WorkOutcome::Done),
+            Err(err) => return Err(err),
+        };
+
+        match msg.payload {
+            model::RawBlockPayload::RollForward(raw) => {
+                let block = raw.decode().or_work_err()?;
+                let raw_txs = super::split_block(&raw).or_work_err()?;
+
+                let mut ctx = self
+                    .track_block_txs(&block, raw_txs.as_ref())
+                    .or_work_err()?;
+
+                super::import_witnesses(&mut ctx, raw_txs.as_ref()).or_work_err()?;
+
+                self.output
+                    .send(model::EnrichedBlockPayload::roll_forward(raw.clone(), ctx))?;
+            }
+            model::RawBlockPayload::RollBack(x) => {
+                self.output
+                    .send(model::EnrichedBlockPayload::roll_back(x))?;
+            }
+        };
+
+        Ok(WorkOutcome::Partial)
+    }
+
+    fn bootstrap(&mut self) -> Result<(), gasket::error::Error> {
+        if let Some(super::Mode::Utxos) = self.config.mode {
+            return Err(crate::Error::config(
+                "RocksDb enrich only supports the Txs mode",
+            ))
+            .or_work_err();
+        }
+
+        let db = open_shared_db(&self.config.db_path).or_work_err()?;
+        self.db = Some(db);
+
+        Ok(())
+    }
+
+    fn teardown(&mut self) -> Result<(), gasket::error::Error> {
+        match &self.db {
+            Some(db) => {
+                db.flush().or_work_err()?;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use gasket::runtime::WorkOutcome;
+
+use crate::{
+    bootstrap,
+    model::{self, BlockContext},
+};
+
+type InputPort = gasket::messaging::InputPort<model::RawBlockPayload>;
+type OutputPort = gasket::messaging::OutputPort<model::EnrichedBlockPayload>;
+
+#[derive(Default)]
+pub struct Bootstrapper {
+    input: InputPort,
+    output: OutputPort,
+}
+
+impl Bootstrapper {
+    pub fn borrow_input_port(&mut self) -> &'_ mut InputPort {
+        &mut self.input
+    }
+
+    pub fn borrow_output_port(&mut self) -> &'_ mut OutputPort {
+        &mut self.output
+    }
+
+    pub fn spawn_stages(self, pipeline: &mut bootstrap::Pipeline) {
+        let worker = Worker {
+            input: self.input,
+            output: self.output,
+            block_count: Default::default(),
+        };
+
+        pipeline.register_stage("enrich-skip", worker, Default::default());
+    }
+}
+
+/// Forwards blocks with an empty context, for pipelines in which no reducer
+/// needs to resolve inputs
+pub struct Worker {
+    input: InputPort,
+    output: OutputPort,
+    block_count: gasket::metrics::Counter,
+}
+
+impl gasket::runtime::Worker for Worker {
+    fn metrics(&self) -> gasket::metrics::Registry {
+        gasket::metrics::Builder::new()
+            .with_counter("block_count", &self.block_count)
+            .build()
+    }
+
+    fn work(&mut self) -> gasket::runtime::WorkResult {
+        let msg = match self.input.recv() {
+            Ok(x) => x,
+            // upstream stages are gone (eg: finalized sync), nothing else will arrive
+            Err(gasket::error::Error::RecvError) => return Ok(WorkOutcome::Done),
+            Err(err) => return Err(err),
+        };
+
+        match msg.payload {
+            model::RawBlockPayload::RollForward(block) => {
+                self.output.send(model::EnrichedBlockPayload::roll_forward(
+                    block,
+                    BlockContext::default(),
+                ))?;
+
+                self.block_count.inc(1);
+            }
+            model::RawBlockPayload::RollBack(x) => {
+                self.output
+                    .send(model::EnrichedBlockPayload::roll_back(x))?;
+            }
+        };
+
+        Ok(WorkOutcome::Partial)
+    }
+}
@@ -1,23 +1,52 @@
-use gasket::{
-    error::AsWorkError,
-    runtime::{spawn_stage, WorkOutcome},
-};
+use std::{collections::HashMap, sync::Mutex, time::Instant};
+
+use gasket::{error::AsWorkError, runtime::WorkOutcome};
 
-use pallas::{codec::minicbor, ledger::traverse::MultiEraBlock};
+use pallas::{
+    codec::minicbor,
+    ledger::traverse::MultiEraBlock,
+    network::miniprotocols::Point,
+};
 use serde::Deserialize;
-use sled::IVec;
+use sled::{
+    transaction::{ConflictableTransactionError, TransactionError},
+    IVec, Transactional,
+};
 
 use crate::{
-    bootstrap,
+    bootstrap, crosscut,
     model::{self, BlockContext},
 };
 
+use super::report::{LatencyHistogram, MissingInput, Report};
+
+/// Databases opened by this process, keyed by path
+///
+/// Sled holds an exclusive lock over its files, so stages running concurrently
+/// (eg: swarm mode) need to share the same handle.
+static OPEN_DBS: Mutex<Vec<(String, sled::Db)>> = Mutex::new(Vec::new());
+
+fn open_shared_db(path: &str) -> sled::Result<sled::Db> {
+    let mut dbs = OPEN_DBS.lock().expect("open dbs lock");
+
+    if let Some((_, db)) = dbs.iter().find(|(x, _)| x == path) {
+        return Ok(db.clone());
+    }
+
+    let db = sled::open(path)?;
+    dbs.push((path.to_string(), db.clone()));
+
+    Ok(db)
+}
+
 type InputPort = gasket::messaging::InputPort<model::RawBlockPayload>;
 type OutputPort = gasket::messaging::OutputPort<model::EnrichedBlockPayload>;
 
 #[derive(Deserialize, Clone)]
 pub struct Config {
     pub db_path: String,
+    pub mode: Option<super::Mode>,
+    pub missing_report: Option<super::report::Config>,
 }
 
 impl Config {
@@ -28,8 +57,44 @@ impl Config {
             output: Default::default(),
         }
     }
+
+    pub fn import_utxos(self, entries: super::import::DumpEntries) -> Result<u64, crate::Error> {
+        if !matches!(self.mode.unwrap_or_default(), super::Mode::Utxos) {
+            return Err(crate::Error::config(
+                "utxo dumps can only be imported in Utxos mode",
+            ));
+        }
+
+        let db = open_shared_db(&self.db_path).map_err(crate::Error::storage)?;
+
+        let mut batch = sled::Batch::default();
+        let mut pending = 0;
+        let mut count = 0;
+
+        for entry in entries {
+            let entry = entry?;
+            let value: IVec = SledTxValue(entry.era, entry.cbor).try_into()?;
+            batch.insert(entry.key().as_bytes(), value);
+
+            pending += 1;
+            count += 1;
+
+            if pending == IMPORT_BATCH_SIZE {
+                db.apply_batch(std::mem::take(&mut batch))
+                    .map_err(crate::Error::storage)?;
+                pending = 0;
+            }
+        }
+
+        db.apply_batch(batch).map_err(crate::Error::storage)?;
+        db.flush().map_err(crate::Error::storage)?;
+
+        Ok(count)
+    }
 }
 
+const IMPORT_BATCH_SIZE: usize = 10_000;
+
 pub struct Bootstrapper {
     config: Config,
     input: InputPort,
@@ -49,19 +114,29 @@ impl Bootstrapper {
         let worker = Worker {
             config: self.config.clone(),
             db: None,
+            report: None,
             input: self.input,
             output: self.output,
+            txs_stored: Default::default(),
+            inputs_resolved: Default::default(),
+            inputs_missing: Default::default(),
+            db_latency: Default::default(),
         };
 
-        pipeline.register_stage("enrich-sled", spawn_stage(worker, Default::default()));
+        pipeline.register_stage("enrich-sled", worker, Default::default());
     }
 }
 
 pub struct Worker {
     config: Config,
     db: Option<sled::Db>,
+    report: Option<Report>,
     input: InputPort,
     output: OutputPort,
+    txs_stored: gasket::metrics::Counter,
+    inputs_resolved: gasket::metrics::Counter,
+    inputs_missing: gasket::metrics::Counter,
+    db_latency: LatencyHistogram,
 }
 
 struct SledTxValue(u16, Vec<u8>);
@@ -87,57 +162,315 @@ impl TryFrom<IVec> for SledTxValue {
     }
 }
 
+/// Amount of slots for which we keep undo data of the utxo set, well past the
+/// point where a rollback is possible
+const UNDO_HORIZON_SLOTS: u64 = 129600;
+
+type UndoData = (Vec<(Vec<u8>, Vec<u8>)>, Vec<Vec<u8>>);
+
+fn transaction_error<E: std::fmt::Debug>(err: TransactionError<E>) -> crate::Error {
+    crate::Error::storage(format!("{:?}", err))
+}
+
+/// Changes applied by a block to the utxo set, along with the data required to
+/// undo them
+#[derive(Default)]
+struct UtxoChanges {
+    created: HashMap<String, IVec>,
+    spent: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl UtxoChanges {
+    fn create(&mut self, key: String, value: IVec) {
+        self.created.insert(key, value);
+    }
+
+    fn get_created(&self, key: &str) -> Option<IVec> {
+        self.created.get(key).cloned()
+    }
+
+    /// Consumes an output created earlier in the same block. It never reaches
+    /// the db, so there's nothing to restore if the block is undone.
+    fn spend_created(&mut self, key: &str) -> Option<IVec> {
+        self.created.remove(key)
+    }
+
+    fn spend_stored(&mut self, key: String, value: &IVec) {
+        self.spent.push((key.into_bytes(), value.to_vec()));
+    }
+
+    /// Applies the changes and stores the undo data of the block in a single
+    /// transaction
+    fn commit(self, db: &sled::Db, slot: u64) -> Result<(), crate::Error> {
+        let undo_tree = db.open_tree("undo").map_err(crate::Error::storage)?;
+
+        let mut batch = sled::Batch::default();
+        let mut produced = Vec::new();
+
+        for (key, _) in self.spent.iter() {
+            batch.remove(key.as_slice());
+        }
+
+        for (key, value) in self.created {
+            batch.insert(key.as_bytes(), value);
+            produced.push(key.into_bytes());
+        }
+
+        let undo: UndoData = (self.spent, produced);
+        let undo = minicbor::to_vec(undo).map_err(crate::Error::cbor)?;
+
+        let utxo_tree: &sled::Tree = db;
+
+        (utxo_tree, &undo_tree)
+            .transaction(|(utxos, undos)| {
+                utxos.apply_batch(&batch)?;
+                undos.insert(slot.to_be_bytes().to_vec(), undo.clone())?;
+                Ok::<_, ConflictableTransactionError<()>>(())
+            })
+            .map_err(transaction_error)?;
+
+        let horizon = slot.saturating_sub(UNDO_HORIZON_SLOTS);
+
+        for key in undo_tree.range(..horizon.to_be_bytes()).keys() {
+            let key = key.map_err(crate::Error::storage)?;
+            undo_tree.remove(key).map_err(crate::Error::storage)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reverts the changes of every block after the specified point, one
+/// transaction per block
+fn undo_utxos(db: &sled::Db, point: &Point) -> Result<(), crate::Error> {
+    let undo_tree = db.open_tree("undo").map_err(crate::Error::storage)?;
+
+    let from_slot = match point {
+        Point::Origin => 0,
+        Point::Specific(slot, _) => slot + 1,
+    };
+
+    let entries: Vec<_> = undo_tree
+        .range(from_slot.to_be_bytes()..)
+        .collect::<Result<_, _>>()
+        .map_err(crate::Error::storage)?;
+
+    let utxo_tree: &sled::Tree = db;
+
+    for (slot, value) in entries.into_iter().rev() {
+        let (spent, produced): UndoData = minicbor::decode(&value).map_err(crate::Error::cbor)?;
+
+        let mut batch = sled::Batch::default();
+
+        for key in produced {
+            batch.remove(key);
+        }
+
+        for (key, value) in spent {
+            batch.insert(key, value);
+        }
+
+        (utxo_tree, &undo_tree)
+            .transaction(|(utxos, undos)| {
+                utxos.apply_batch(&batch)?;
+                undos.remove(slot.clone())?;
+                Ok::<_, ConflictableTransactionError<()>>(())
+            })
+            .map_err(transaction_error)?;
+    }
+
+    Ok(())
+}
+
 impl Worker {
-    fn track_block_txs(&self, block: &MultiEraBlock) -> Result<BlockContext, crate::Error> {
-        let db = self.db.as_ref().unwrap();
+    fn db_get(&self, key: impl AsRef<[u8]>) -> Result<Option<IVec>, crate::Error> {
+        let start = Instant::now();
+        let value = self.db.as_ref().unwrap().get(key);
+        self.db_latency.observe(start.elapsed());
+
+        value.map_err(crate::Error::storage)
+    }
+
+    fn db_insert(&self, key: impl AsRef<[u8]>, value: IVec) -> Result<(), crate::Error> {
+        let start = Instant::now();
+        let result = self.db.as_ref().unwrap().insert(key, value);
+        self.db_latency.observe(start.elapsed());
+
+        result.map(|_| ()).map_err(crate::Error::storage)
+    }
+
+    fn track_resolution(&self, found: bool) {
+        match found {
+            true => self.inputs_resolved.inc(1),
+            false => self.inputs_missing.inc(1),
+        };
+    }
+
+    fn track_block_txs(
+        &self,
+        block: &MultiEraBlock,
+        raw_txs: Option<&super::raw::BlockTxs>,
+        missing: &mut Vec<MissingInput>,
+    ) -> Result<BlockContext, crate::Error> {
         let mut ctx = BlockContext::default();
 
-        for tx in &block.txs() {
+        for (tx_idx, tx) in block.txs().iter().enumerate() {
             let hash = tx.hash();
 
             let era = tx.era().into();
-            let body = tx.encode().map_err(crate::Error::cbor)?;
+            let body = super::tx_cbor(raw_txs, tx_idx, tx)?;
             let value: IVec = SledTxValue(era, body).try_into()?;
-            db.insert(hash, value).map_err(crate::Error::storage)?;
+            self.db_insert(hash, value)?;
+            self.txs_stored.inc(1);
 
-            for input in tx.inputs() {
-                if let Some(tx_ref) = input.output_ref() {
-                    let tx_id = tx_ref.tx_id();
+            for output_ref in crosscut::utxos::all_inputs(tx) {
+                let value = self.db_get(output_ref.tx_id())?;
+                self.track_resolution(value.is_some());
 
-                    if let Some(ivec) = db.get(tx_id).map_err(crate::Error::storage)? {
-                        let SledTxValue(era, cbor) =
-                            ivec.try_into().map_err(crate::Error::storage)?;
+                match value {
+                    Some(ivec) => {
+                        let SledTxValue(era, cbor) = ivec.try_into()?;
                         let era = era.try_into().map_err(crate::Error::storage)?;
-                        ctx.import_ref_tx(tx_id, era, cbor);
+                        super::import_ref_tx(&mut ctx, &output_ref, era, cbor)?;
                     }
+                    None => missing.push(MissingInput::new(block.slot(), &hash, &output_ref)),
                 }
             }
         }
 
         Ok(ctx)
     }
+
+    /// Tracks only unspent outputs, spent ones are removed from the db as soon
+    /// as they are consumed. The removed entries are kept in a separate tree
+    /// for a while so that we can undo the changes if a rollback happens.
+    fn track_block_utxos(
+        &self,
+        block: &MultiEraBlock,
+        raw_txs: Option<&super::raw::BlockTxs>,
+        missing: &mut Vec<MissingInput>,
+    ) -> Result<BlockContext, crate::Error> {
+        let mut ctx = BlockContext::default();
+        let mut changes = UtxoChanges::default();
+
+        for (tx_idx, tx) in block.txs().iter().enumerate() {
+            let tx_hash = tx.hash();
+
+            for output_ref in crosscut::utxos::read_only_inputs(tx) {
+                let key = format!("{}#{}", output_ref.tx_id(), output_ref.tx_index());
+
+                let value = match changes.get_created(&key) {
+                    Some(x) => Some(x),
+                    None => self.db_get(&key)?,
+                };
+
+                self.track_resolution(value.is_some());
+
+                match value {
+                    Some(ivec) => {
+                        let SledTxValue(era, cbor) = ivec.try_into()?;
+                        let era = era.try_into().map_err(crate::Error::storage)?;
+                        ctx.import_ref_output(&output_ref, era, cbor);
+                    }
+                    None => missing.push(MissingInput::new(block.slot(), &tx_hash, &output_ref)),
+                }
+            }
+
+            for output_ref in crosscut::utxos::consumed_inputs(tx) {
+                let key = format!("{}#{}", output_ref.tx_id(), output_ref.tx_index());
+
+                // outputs might be consumed within the same block they were created
+                let value = match changes.spend_created(&key) {
+                    Some(x) => Some(x),
+                    None => {
+                        let stored = self.db_get(&key)?;
+
+                        if let Some(ivec) = &stored {
+                            changes.spend_stored(key, ivec);
+                        }
+
+                        stored
+                    }
+                };
+
+                self.track_resolution(value.is_some());
+
+                match value {
+                    Some(ivec) => {
+                        let SledTxValue(era, cbor) = ivec.try_into()?;
+                        let era = era.try_into().map_err(crate::Error::storage)?;
+                        ctx.import_ref_output(&output_ref, era, cbor);
+                    }
+                    None => missing.push(MissingInput::new(block.slot(), &tx_hash, &output_ref)),
+                }
+            }
+
+            let era: u16 = tx.era().into();
+
+            for (idx, output) in crosscut::utxos::produced_outputs(tx) {
+                let body = super::output_cbor(raw_txs, tx_idx, idx, &output)?;
+                let value: IVec = SledTxValue(era, body).try_into()?;
+                changes.create(format!("{}#{}", tx_hash, idx), value);
+            }
+        }
+
+        let start = Instant::now();
+        let result = changes.commit(self.db.as_ref().unwrap(), block.slot());
+        self.db_latency.observe(start.elapsed());
+
+        result.map(|_| ctx)
+    }
 }
 
 impl gasket::runtime::Worker for Worker {
     fn metrics(&self) -> gasket::metrics::Registry {
-        gasket::metrics::Builder::new().build()
+        let builder = gasket::metrics::Builder::new()
+            .with_counter("txs_stored", &self.txs_stored)
+            .with_counter("inputs_resolved", &self.inputs_resolved)
+            .with_counter("inputs_missing", &self.inputs_missing);
+
+        self.db_latency.register(builder).build()
     }
 
     fn work(&mut self) -> gasket::runtime::WorkResult {
-        let msg = self.input.recv()?;
+        let msg = match self.input.recv() {
+            Ok(x) => x,
+            // upstream stages are gone (eg: finalized sync), nothing else will arrive
+            Err(gasket::error::Error::RecvError) => return Ok(WorkOutcome::Done),
+            Err(err) => return Err(err),
+        };
 
         match msg.payload {
-            model::RawBlockPayload::RollForward(cbor) => {
-                let block = MultiEraBlock::decode(&cbor)
-                    .map_err(crate::Error::cbor)
-                    .or_work_err()?;
+            model::RawBlockPayload::RollForward(raw) => {
+                let block = raw.decode().or_work_err()?;
+                let raw_txs = super::split_block(&raw).or_work_err()?;
+
+                let mut missing = Vec::new();
 
-                let ctx = self.track_block_txs(&block).or_work_err()?;
+                let mut ctx = match self.config.mode.unwrap_or_default() {
+                    super::Mode::Txs => {
+                        self.track_block_txs(&block, raw_txs.as_ref(), &mut missing)
+                    }
+                    super::Mode::Utxos => {
+                        self.track_block_utxos(&block, raw_txs.as_ref(), &mut missing)
+                    }
+                }
+                .or_work_err()?;
+
+                if let Some(report) = &mut self.report {
+                    report.write(&missing).or_work_err()?;
+                }
+
+                super::import_witnesses(&mut ctx, raw_txs.as_ref()).or_work_err()?;
 
                 self.output
-                    .send(model::EnrichedBlockPayload::roll_forward(cbor, ctx))?;
+                    .send(model::EnrichedBlockPayload::roll_forward(raw.clone(), ctx))?;
             }
             model::RawBlockPayload::RollBack(x) => {
+                if let super::Mode::Utxos = self.config.mode.unwrap_or_default() {
+                    undo_utxos(self.db.as_ref().unwrap(), &x).or_work_err()?;
+                }
+
                 self.output
                     .send(model::EnrichedBlockPayload::roll_back(x))?;
             }
@@ -147,9 +480,13 @@ impl gasket::runtime::Worker for Worker {
     }
 
     fn bootstrap(&mut self) -> Result<(), gasket::error::Error> {
-        let db = sled::open(&self.config.db_path).or_work_err()?;
+        let db = open_shared_db(&self.config.db_path).or_work_err()?;
         self.db = Some(db);
 
+        if let Some(report) = &self.config.missing_report {
+            self.report = Some(report.open().or_work_err()?);
+        }
+
         Ok(())
     }
 
@@ -163,3 +500,65 @@ impl gasket::runtime::Worker for Worker {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_db() -> sled::Db {
+        sled::Config::new().temporary(true).open().unwrap()
+    }
+
+    fn value(x: u8) -> IVec {
+        IVec::from(vec![x])
+    }
+
+    fn point(slot: u64) -> Point {
+        Point::Specific(slot, vec![0u8; 32])
+    }
+
+    #[test]
+    fn undo_restores_spent_and_removes_created() {
+        let db = temp_db();
+
+        let mut changes = UtxoChanges::default();
+        changes.create("a#0".into(), value(1));
+        changes.commit(&db, 10).unwrap();
+
+        let mut changes = UtxoChanges::default();
+        let stored = db.get("a#0").unwrap().unwrap();
+        changes.spend_stored("a#0".into(), &stored);
+        changes.create("b#0".into(), value(2));
+        changes.commit(&db, 20).unwrap();
+
+        assert!(db.get("a#0").unwrap().is_none());
+        assert_eq!(db.get("b#0").unwrap(), Some(value(2)));
+
+        undo_utxos(&db, &point(10)).unwrap();
+
+        assert_eq!(db.get("a#0").unwrap(), Some(value(1)));
+        assert!(db.get("b#0").unwrap().is_none());
+
+        undo_utxos(&db, &Point::Origin).unwrap();
+
+        assert!(db.get("a#0").unwrap().is_none());
+        assert!(db.open_tree("undo").unwrap().is_empty());
+    }
+
+    #[test]
+    fn undo_ignores_outputs_spent_within_the_block() {
+        let db = temp_db();
+
+        let mut changes = UtxoChanges::default();
+        changes.create("a#0".into(), value(1));
+        assert_eq!(changes.spend_created("a#0"), Some(value(1)));
+        changes.commit(&db, 10).unwrap();
+
+        assert!(db.get("a#0").unwrap().is_none());
+
+        undo_utxos(&db, &Point::Origin).unwrap();
+
+        // the output never existed outside of the block, it shouldn't be re-created
+        assert!(db.get("a#0").unwrap().is_none());
+    }
+}
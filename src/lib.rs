@@ -1,4 +1,5 @@
 pub mod bootstrap;
+pub mod broadcast;
 pub mod crosscut;
 pub mod enrich;
 pub mod model;
@@ -1,23 +1,77 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 use pallas::{
-    crypto::hash::Hash,
-    ledger::traverse::{Era, MultiEraBlock, MultiEraTx},
+    codec::minicbor,
+    crypto::hash::{Hash, Hasher},
+    ledger::{
+        primitives::{
+            alonzo::{self, NativeScript, PlutusData},
+            babbage,
+        },
+        traverse::{Era, MultiEraBlock, MultiEraOutput, MultiEraTx, OutputRef},
+    },
     network::miniprotocols::Point,
 };
 
 use crate::Error;
 
+/// A block as received by the source along with its point
+///
+/// Only the point is computed once and shared by every stage. Stages that need
+/// a typed view of the block (eg: enrich, reducers) still decode it on their
+/// own, since `MultiEraBlock` borrows from the cbor and can't be sent across
+/// stages.
+#[derive(Debug)]
+pub struct RawBlock {
+    pub point: Point,
+    pub cbor: Vec<u8>,
+}
+
+impl RawBlock {
+    pub fn new(point: Point, cbor: Vec<u8>) -> Self {
+        Self { point, cbor }
+    }
+
+    /// Builds the block from its cbor alone, decoding it to find its point
+    pub fn from_cbor(cbor: Vec<u8>) -> Result<Self, Error> {
+        let point = {
+            let block = MultiEraBlock::decode(&cbor).map_err(crate::Error::cbor)?;
+            Point::Specific(block.slot(), block.hash().to_vec())
+        };
+
+        Ok(Self { point, cbor })
+    }
+
+    pub fn slot(&self) -> u64 {
+        match &self.point {
+            Point::Origin => 0,
+            Point::Specific(slot, _) => *slot,
+        }
+    }
+
+    /// Decodes the block, each call parses the cbor again
+    pub fn decode(&self) -> Result<MultiEraBlock, Error> {
+        MultiEraBlock::decode(&self.cbor).map_err(crate::Error::cbor)
+    }
+}
+
+/// Blocks are shared between stages instead of being copied, but not their
+/// decoded form
+pub type SharedBlock = Arc<RawBlock>;
+
 #[derive(Debug, Clone)]
 pub enum RawBlockPayload {
-    RollForward(Vec<u8>),
+    RollForward(SharedBlock),
     RollBack(Point),
 }
 
 impl RawBlockPayload {
-    pub fn roll_forward(block: Vec<u8>) -> gasket::messaging::Message<Self> {
+    pub fn roll_forward(block: RawBlock) -> gasket::messaging::Message<Self> {
         gasket::messaging::Message {
-            payload: Self::RollForward(block),
+            payload: Self::RollForward(Arc::new(block)),
         }
     }
 
@@ -28,9 +82,114 @@ impl RawBlockPayload {
     }
 }
 
+/// A script found either in a witness set or as the reference script of an
+/// output. Native scripts are kept as their original cbor, since it's what
+/// their hash is computed over.
+#[derive(Debug, Clone)]
+pub enum Script {
+    Native(Vec<u8>),
+    PlutusV1(Vec<u8>),
+    PlutusV2(Vec<u8>),
+}
+
+impl Script {
+    pub fn hash(&self) -> Hash<28> {
+        let (tag, bytes) = match self {
+            Script::Native(x) => (0u8, x),
+            Script::PlutusV1(x) => (1u8, x),
+            Script::PlutusV2(x) => (2u8, x),
+        };
+
+        let mut hasher = Hasher::<224>::new();
+        hasher.input(&[tag]);
+        hasher.input(bytes);
+
+        hasher.finalize()
+    }
+
+    pub fn as_native(&self) -> Option<Result<NativeScript, Error>> {
+        match self {
+            Script::Native(x) => Some(minicbor::decode(x).map_err(crate::Error::cbor)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ResolvedDatum {
+    Hash(Hash<32>),
+    Inline(PlutusData),
+}
+
+/// A typed view of an output referenced by the block, decoded only once and
+/// shared by every reducer
+#[derive(Debug, Clone)]
+pub struct ResolvedOutput {
+    pub address: String,
+    pub lovelace: u64,
+    pub assets: Vec<(Hash<28>, Vec<u8>, u64)>,
+    pub datum: Option<ResolvedDatum>,
+}
+
+fn value_assets(value: &alonzo::Value) -> Vec<(Hash<28>, Vec<u8>, u64)> {
+    match value {
+        alonzo::Value::Coin(_) => vec![],
+        alonzo::Value::Multiasset(_, policies) => policies
+            .iter()
+            .flat_map(|(policy, assets)| {
+                assets
+                    .iter()
+                    .map(move |(name, amount)| (*policy, name.to_vec(), *amount))
+            })
+            .collect(),
+    }
+}
+
+/// Lists the native assets of an output as (policy, asset name, amount)
+pub fn output_assets(output: &MultiEraOutput) -> Vec<(Hash<28>, Vec<u8>, u64)> {
+    match (output.as_alonzo(), output.as_babbage()) {
+        (Some(x), _) => value_assets(&x.amount),
+        (_, Some(babbage::TransactionOutput::Legacy(x))) => value_assets(&x.amount),
+        (_, Some(babbage::TransactionOutput::PostAlonzo(x))) => value_assets(&x.value),
+        _ => vec![],
+    }
+}
+
+fn output_datum(output: &MultiEraOutput) -> Option<ResolvedDatum> {
+    match (output.as_alonzo(), output.as_babbage()) {
+        (Some(x), _) => x.datum_hash.map(ResolvedDatum::Hash),
+        (_, Some(babbage::TransactionOutput::Legacy(x))) => x.datum_hash.map(ResolvedDatum::Hash),
+        (_, Some(babbage::TransactionOutput::PostAlonzo(x))) => match &x.datum_option {
+            Some(babbage::DatumOption::Hash(x)) => Some(ResolvedDatum::Hash(*x)),
+            Some(babbage::DatumOption::Data(x)) => Some(ResolvedDatum::Inline(x.0.clone())),
+            None => None,
+        },
+        _ => None,
+    }
+}
+
+impl ResolvedOutput {
+    pub fn new(output: &MultiEraOutput, address_hrp: &str) -> Self {
+        Self {
+            address: output.address(address_hrp),
+            lovelace: output.ada_amount(),
+            assets: output_assets(output),
+            datum: output_datum(output),
+        }
+    }
+}
+
 #[derive(Default, Debug, Clone)]
 pub struct BlockContext {
     ref_txs: HashMap<String, (Era, Vec<u8>)>,
+    ref_outputs: HashMap<String, (Era, Vec<u8>)>,
+    resolved_outputs: HashMap<String, ResolvedOutput>,
+    datums: HashMap<String, Vec<u8>>,
+    scripts: HashMap<String, Script>,
+}
+
+fn output_ref_key(output_ref: &OutputRef) -> String {
+    format!("{}#{}", output_ref.tx_id(), output_ref.tx_index())
 }
 
 impl BlockContext {
@@ -46,16 +205,100 @@ impl BlockContext {
 
         MultiEraTx::decode(*era, cbor).map_err(crate::Error::cbor)
     }
+
+    pub fn import_ref_output(&mut self, output_ref: &OutputRef, era: Era, cbor: Vec<u8>) {
+        self.ref_outputs
+            .insert(output_ref_key(output_ref), (era, cbor));
+    }
+
+    /// Decodes every referenced output into its typed view, outputs that fail
+    /// to decode are left out and treated as missing
+    pub fn resolve_outputs(&mut self, address_hrp: &str) {
+        for (key, (era, cbor)) in self.ref_outputs.iter() {
+            if self.resolved_outputs.contains_key(key) {
+                continue;
+            }
+
+            match MultiEraOutput::decode(*era, cbor) {
+                Ok(output) => {
+                    let resolved = ResolvedOutput::new(&output, address_hrp);
+                    self.resolved_outputs.insert(key.clone(), resolved);
+                }
+                Err(err) => log::warn!("can't decode referenced output {}: {}", key, err),
+            }
+        }
+    }
+
+    /// Finds the output pointed by an input, collateral or reference input,
+    /// regardless of the enrich mode
+    pub fn find_utxo(&self, output_ref: &OutputRef) -> Result<&ResolvedOutput, Error> {
+        self.resolved_outputs
+            .get(&output_ref_key(output_ref))
+            .ok_or_else(|| Error::missing_tx(output_ref_key(output_ref)))
+    }
+
+    /// The cbor of every output pointed by the inputs of the block
+    pub fn ref_output_cbors(&self) -> impl Iterator<Item = &[u8]> {
+        self.ref_outputs.values().map(|(_, cbor)| cbor.as_slice())
+    }
+
+    /// Indexes a datum by the hash of its original cbor
+    pub fn import_datum(&mut self, cbor: Vec<u8>) {
+        let hash = Hasher::<256>::hash(&cbor);
+        self.datums.insert(hash.to_string(), cbor);
+    }
+
+    pub fn find_datum(&self, hash: &Hash<32>) -> Result<PlutusData, Error> {
+        let cbor = self
+            .datums
+            .get(&hash.to_string())
+            .ok_or_else(|| Error::missing_tx(format!("datum {}", hash)))?;
+
+        minicbor::decode(cbor).map_err(crate::Error::cbor)
+    }
+
+    pub fn import_script(&mut self, script: Script) {
+        let hash = script.hash();
+        self.scripts.insert(hash.to_string(), script);
+    }
+
+    pub fn find_script(&self, hash: &Hash<28>) -> Result<&Script, Error> {
+        self.scripts
+            .get(&hash.to_string())
+            .ok_or_else(|| Error::missing_tx(format!("script {}", hash)))
+    }
+}
+
+/// What reducers need to revert the effects of a block on rollback, kept
+/// around instead of the whole block
+#[derive(Debug, Clone)]
+pub struct UndoRecord {
+    pub slot: u64,
+    pub hash: Hash<32>,
+    pub tx_hashes: Vec<Hash<32>>,
+}
+
+impl UndoRecord {
+    pub fn new(block: &MultiEraBlock) -> Self {
+        Self {
+            slot: block.slot(),
+            hash: block.hash(),
+            tx_hashes: block.txs().iter().map(|x| x.hash()).collect(),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum EnrichedBlockPayload {
-    RollForward(Vec<u8>, BlockContext),
+    RollForward(SharedBlock, BlockContext),
     RollBack(Point),
 }
 
 impl EnrichedBlockPayload {
-    pub fn roll_forward(block: Vec<u8>, ctx: BlockContext) -> gasket::messaging::Message<Self> {
+    pub fn roll_forward(
+        block: SharedBlock,
+        ctx: BlockContext,
+    ) -> gasket::messaging::Message<Self> {
         gasket::messaging::Message {
             payload: Self::RollForward(block, ctx),
         }
@@ -75,7 +318,15 @@ pub type Value = String;
 pub type Delta = i64;
 pub type Timestamp = u64;
 
-#[derive(Debug)]
+/// Timestamp of a write done by the tx at `tx_idx` of the block at `slot`, so
+/// that writes within the same slot follow the order of the block. Redis keeps
+/// scores as doubles, so the result needs to stay below 2^53.
+pub fn tx_timestamp(slot: u64, tx_idx: usize) -> Timestamp {
+    (slot << 16) | (tx_idx as u64 & 0xffff)
+}
+pub type Score = i64;
+
+#[derive(Debug, Clone)]
 #[non_exhaustive]
 pub enum CRDTCommand {
     BlockStarting(Point),
@@ -84,19 +335,22 @@ pub enum CRDTCommand {
     TwoPhaseSetAdd(Set, Member),
     TwoPhaseSetRemove(Set, Member),
     GrowOnlySetAdd(Set, Member),
+    SortedSetAdd(Set, Member, Score),
+    SortedSetRemove(Set, Member),
     LastWriteWins(Key, Value, Timestamp),
     AnyWriteWins(Key, Value),
+    AnyWriteWinsBytes(Key, Vec<u8>),
+    /// Removes the members of a sorted set scoring below the threshold, along
+    /// with the `{prefix}.{member}` keys that the set indexes
+    SortedSetPrune(Set, Key, Score),
     // TODO make sure Value is a generic not stringly typed
     PNCounter(Key, Delta),
     BlockFinished(Point),
 }
 
 impl CRDTCommand {
-    pub fn block_starting(block: &MultiEraBlock) -> CRDTCommand {
-        let hash = block.hash();
-        let slot = block.slot();
-        let point = Point::Specific(slot, hash.to_vec());
-        CRDTCommand::BlockStarting(point)
+    pub fn block_starting(block: &RawBlock) -> CRDTCommand {
+        CRDTCommand::BlockStarting(block.point.clone())
     }
 
     pub fn set_add(prefix: Option<&str>, key: &str, member: String) -> CRDTCommand {
@@ -117,11 +371,33 @@ impl CRDTCommand {
         CRDTCommand::SetRemove(key, member)
     }
 
-    pub fn block_finished(block: &MultiEraBlock) -> CRDTCommand {
-        let hash = block.hash();
-        let slot = block.slot();
-        let point = Point::Specific(slot, hash.to_vec());
-        CRDTCommand::BlockFinished(point)
+    /// Adds (or subtracts, if `sign` is negative) an unsigned amount to a
+    /// counter. Storage counters are i64, so amounts beyond its range (eg:
+    /// native assets) are split into several deltas. The accumulated value
+    /// still needs to fit the counter.
+    pub fn pn_counter_deltas(key: Key, amount: u64, sign: i64) -> Vec<CRDTCommand> {
+        let mut remaining = amount;
+        let mut commands = vec![];
+
+        loop {
+            let chunk = remaining.min(i64::MAX as u64);
+            remaining -= chunk;
+
+            let delta = match sign < 0 {
+                true => -(chunk as i64),
+                false => chunk as i64,
+            };
+
+            commands.push(CRDTCommand::PNCounter(key.clone(), delta));
+
+            if remaining == 0 {
+                return commands;
+            }
+        }
+    }
+
+    pub fn block_finished(block: &RawBlock) -> CRDTCommand {
+        CRDTCommand::BlockFinished(block.point.clone())
     }
 }
 
@@ -154,3 +430,36 @@ impl From<Option<HashSet<Value>>> for StateData {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deltas(commands: Vec<CRDTCommand>) -> Vec<i64> {
+        commands
+            .into_iter()
+            .map(|x| match x {
+                CRDTCommand::PNCounter(_, delta) => delta,
+                _ => panic!("unexpected command"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn small_amounts_are_sent_as_a_single_delta() {
+        let commands = CRDTCommand::pn_counter_deltas("x".into(), 5, -1);
+        assert_eq!(deltas(commands), vec![-5]);
+
+        let commands = CRDTCommand::pn_counter_deltas("x".into(), 0, 1);
+        assert_eq!(deltas(commands), vec![0]);
+    }
+
+    #[test]
+    fn large_amounts_are_split_within_i64() {
+        let commands = CRDTCommand::pn_counter_deltas("x".into(), u64::MAX, 1);
+        assert_eq!(deltas(commands), vec![i64::MAX, i64::MAX, 1]);
+
+        let commands = CRDTCommand::pn_counter_deltas("x".into(), u64::MAX, -1);
+        assert_eq!(deltas(commands), vec![-i64::MAX, -i64::MAX, -1]);
+    }
+}
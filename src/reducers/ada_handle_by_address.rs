@@ -0,0 +1,115 @@
+use crosscut::policies::*;
+use gasket::error::AsWorkError;
+use pallas::crypto::hash::Hash;
+use pallas::ledger::traverse::{MultiEraBlock, MultiEraOutput, OutputRef};
+use serde::Deserialize;
+
+use crate::{crosscut, model};
+
+#[derive(Deserialize, Clone)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+    pub policy: Option<ReducerPolicy>,
+}
+
+pub struct Reducer {
+    config: Config,
+    policy_id: String,
+    address_hrp: String,
+}
+
+impl Reducer {
+    fn is_handle_policy(&self, policy: &Hash<28>) -> bool {
+        policy.to_string() == self.policy_id
+    }
+
+    fn process_inbound_txo(
+        &mut self,
+        ctx: &model::BlockContext,
+        input: &OutputRef,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let utxo = ctx
+            .find_utxo(input)
+            .apply_policy(&self.config.policy)
+            .or_work_err()?;
+
+        let utxo = match utxo {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        for (policy, asset_name, _) in utxo.assets.iter() {
+            if !self.is_handle_policy(policy) {
+                continue;
+            }
+
+            if let Some(handle) = crosscut::adahandles::handle_name(asset_name) {
+                let crdt = model::CRDTCommand::set_remove(
+                    self.config.key_prefix.as_deref(),
+                    &utxo.address,
+                    handle,
+                );
+
+                output.send(crdt.into())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn process_outbound_txo(
+        &mut self,
+        tx_output: &MultiEraOutput,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (policy, asset_name, _) in model::output_assets(tx_output) {
+            if !self.is_handle_policy(&policy) {
+                continue;
+            }
+
+            if let Some(handle) = crosscut::adahandles::handle_name(&asset_name) {
+                let crdt = model::CRDTCommand::set_add(
+                    self.config.key_prefix.as_deref(),
+                    &tx_output.address(&self.address_hrp),
+                    handle,
+                );
+
+                output.send(crdt.into())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        ctx: &model::BlockContext,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for tx in block.txs().into_iter() {
+            for input in crosscut::utxos::consumed_inputs(&tx) {
+                self.process_inbound_txo(ctx, &input, output)?;
+            }
+
+            for (_, tx_output) in crosscut::utxos::produced_outputs(&tx) {
+                self.process_outbound_txo(&tx_output, output)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Reducer {
+        let reducer = Reducer {
+            config: self,
+            policy_id: chain.adahandle_policy.clone(),
+            address_hrp: chain.address_hrp.clone(),
+        };
+
+        super::Reducer::AdaHandleByAddress(reducer)
+    }
+}
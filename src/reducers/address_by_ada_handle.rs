@@ -0,0 +1,67 @@
+use pallas::ledger::traverse::MultiEraBlock;
+use serde::Deserialize;
+
+use crate::{crosscut, model};
+
+#[derive(Deserialize, Clone)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+}
+
+pub struct Reducer {
+    config: Config,
+    policy_id: String,
+    address_hrp: String,
+}
+
+impl Reducer {
+    pub fn reduce_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (tx_idx, tx) in block.txs().iter().enumerate() {
+            let timestamp = model::tx_timestamp(block.slot(), tx_idx);
+
+            for (_, tx_output) in crosscut::utxos::produced_outputs(tx) {
+                let handles: Vec<_> = model::output_assets(&tx_output)
+                    .into_iter()
+                    .filter(|(policy, _, _)| policy.to_string() == self.policy_id)
+                    .filter_map(|(_, name, _)| crosscut::adahandles::handle_name(&name))
+                    .collect();
+
+                if handles.is_empty() {
+                    continue;
+                }
+
+                let address = tx_output.address(&self.address_hrp);
+
+                // the latest tx wins, so handles follow their transfers even
+                // within the same block
+                for handle in handles {
+                    let key = match &self.config.key_prefix {
+                        Some(prefix) => format!("{}.{}", prefix, handle),
+                        None => format!("address_by_ada_handle.{}", handle),
+                    };
+
+                    let crdt = model::CRDTCommand::LastWriteWins(key, address.clone(), timestamp);
+                    output.send(gasket::messaging::Message::from(crdt))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Reducer {
+        let reducer = Reducer {
+            config: self,
+            policy_id: chain.adahandle_policy.clone(),
+            address_hrp: chain.address_hrp.clone(),
+        };
+
+        super::Reducer::AddressByAdaHandle(reducer)
+    }
+}
@@ -4,7 +4,7 @@ use serde::Deserialize;
 
 use crate::{crosscut, model};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct Config {
     pub key_prefix: Option<String>,
     pub filter: Option<Vec<String>>,
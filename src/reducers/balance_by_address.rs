@@ -1,12 +1,13 @@
 use crosscut::policies::*;
 use gasket::error::AsWorkError;
+use pallas::crypto::hash::Hash;
 use pallas::ledger::traverse::MultiEraOutput;
 use pallas::ledger::traverse::{MultiEraBlock, OutputRef};
 use serde::Deserialize;
 
 use crate::{crosscut, model};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct Config {
     pub key_prefix: Option<String>,
     pub filter: Option<Vec<String>>,
@@ -19,51 +20,75 @@ pub struct Reducer {
 }
 
 impl Reducer {
+    fn is_address_tracked(&self, address: &str) -> bool {
+        match &self.config.filter {
+            Some(addresses) => addresses
+                .binary_search_by(|x| x.as_str().cmp(address))
+                .is_ok(),
+            None => true,
+        }
+    }
+
+    /// Sends the lovelace delta of the address plus one delta per asset, keyed
+    /// as `prefix.address` and `prefix.address.policy.asset`
+    fn send_deltas(
+        &mut self,
+        address: &str,
+        lovelace: u64,
+        assets: &[(Hash<28>, Vec<u8>, u64)],
+        sign: i64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let prefix = match &self.config.key_prefix {
+            Some(prefix) => prefix.as_str(),
+            None => "balance_by_address",
+        };
+
+        let key = format!("{}.{}", prefix, address);
+
+        for crdt in model::CRDTCommand::pn_counter_deltas(key, lovelace, sign) {
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        for (policy, asset_name, amount) in assets {
+            let key = format!(
+                "{}.{}.{}.{}",
+                prefix,
+                address,
+                policy,
+                hex::encode(asset_name)
+            );
+
+            for crdt in model::CRDTCommand::pn_counter_deltas(key, *amount, sign) {
+                output.send(gasket::messaging::Message::from(crdt))?;
+            }
+        }
+
+        Ok(())
+    }
+
     fn process_inbound_txo(
         &mut self,
         ctx: &model::BlockContext,
         input: &OutputRef,
         output: &mut super::OutputPort,
     ) -> Result<(), gasket::error::Error> {
-        let inbound_tx = ctx
-            .find_ref_tx(input.tx_id())
+        let utxo = ctx
+            .find_utxo(input)
             .apply_policy(&self.config.policy)
             .or_work_err()?;
 
-        let inbound_tx = match inbound_tx {
+        let utxo = match utxo {
             Some(x) => x,
             None => return Ok(()),
         };
 
-        let output_tx = inbound_tx
-            .output_at(input.tx_index() as usize)
-            .ok_or(crate::Error::ledger("output index not found in tx"))
-            .apply_policy(&self.config.policy)
-            .or_work_err()?;
-
-        let output_tx = match output_tx {
-            Some(x) => x,
-            None => return Ok(()),
-        };
-
-        let address = output_tx.address(&self.address_hrp);
-
-        if let Some(addresses) = &self.config.filter {
-            if let Err(_) = addresses.binary_search(&address.to_string()) {
-                return Ok(());
-            }
+        if !self.is_address_tracked(&utxo.address) {
+            return Ok(());
         }
 
-        let key = match &self.config.key_prefix {
-            Some(prefix) => prefix.to_string(),
-            None => "balance_by_address".to_string(),
-        };
-
-        let crdt = model::CRDTCommand::PNCounter(key, output_tx.ada_amount() as i64);
-
-        output.send(gasket::messaging::Message::from(crdt))?;
-
-        Ok(())
+        // spent outputs leave the balance of the address
+        self.send_deltas(&utxo.address, utxo.lovelace, &utxo.assets, -1, output)
     }
 
     fn process_outbound_txo(
@@ -73,22 +98,13 @@ impl Reducer {
     ) -> Result<(), gasket::error::Error> {
         let address = tx_output.address(&self.address_hrp);
 
-        if let Some(addresses) = &self.config.filter {
-            if let Err(_) = addresses.binary_search(&address.to_string()) {
-                return Ok(());
-            }
+        if !self.is_address_tracked(&address) {
+            return Ok(());
         }
 
-        let key = match &self.config.key_prefix {
-            Some(prefix) => prefix.to_string(),
-            None => "balance_by_address".to_string(),
-        };
-
-        let crdt = model::CRDTCommand::PNCounter(key, (-1) * tx_output.ada_amount() as i64);
-
-        output.send(gasket::messaging::Message::from(crdt))?;
+        let assets = model::output_assets(tx_output);
 
-        Ok(())
+        self.send_deltas(&address, tx_output.ada_amount(), &assets, 1, output)
     }
 
     pub fn reduce_block<'b>(
@@ -98,14 +114,12 @@ impl Reducer {
         output: &mut super::OutputPort,
     ) -> Result<(), gasket::error::Error> {
         for tx in block.txs().into_iter() {
-
-            for input in tx.inputs().iter().filter_map(|i| i.output_ref()) {
-                self.process_inbound_txo(&ctx, &input, output)
-                    .or_work_err()?;
+            for input in crosscut::utxos::consumed_inputs(&tx) {
+                self.process_inbound_txo(ctx, &input, output)?;
             }
 
-            for (_idx, tx_output) in tx.outputs().iter().enumerate() {
-                self.process_outbound_txo(tx_output, output)?;
+            for (_idx, tx_output) in crosscut::utxos::produced_outputs(&tx) {
+                self.process_outbound_txo(&tx_output, output)?;
             }
         }
 
@@ -114,7 +128,12 @@ impl Reducer {
 }
 
 impl Config {
-    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Reducer {
+    pub fn plugin(mut self, chain: &crosscut::ChainWellKnownInfo) -> super::Reducer {
+        // the filter is sorted once so that lookups can use a binary search
+        if let Some(addresses) = self.filter.as_mut() {
+            addresses.sort();
+        }
+
         let reducer = Reducer {
             config: self,
             address_hrp: chain.address_hrp.clone(),
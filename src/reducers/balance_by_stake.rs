@@ -0,0 +1,293 @@
+use std::collections::BTreeMap;
+
+use crosscut::addresses::StakePart;
+use crosscut::policies::*;
+use gasket::error::AsWorkError;
+use pallas::crypto::hash::Hash;
+use pallas::ledger::primitives::alonzo::{self, StakeCredential};
+use pallas::ledger::traverse::{MultiEraBlock, MultiEraOutput, OutputRef};
+use serde::Deserialize;
+
+use crate::{crosscut, model, storage};
+
+/// (slot, tx index, cert index) of a stake registration cert
+type Pointer = (u64, u64, u64);
+
+#[derive(Deserialize, Clone, Copy)]
+pub enum KeyFormat {
+    /// Hex of the stake key or script hash
+    Credential,
+    /// Bech32 reward address
+    StakeAddress,
+}
+
+impl Default for KeyFormat {
+    fn default() -> Self {
+        KeyFormat::StakeAddress
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+    pub key_format: Option<KeyFormat>,
+    pub policy: Option<ReducerPolicy>,
+}
+
+pub struct Reducer {
+    config: Config,
+    address_hrp: String,
+    /// Reward address bytes of every registered credential by the pointer of
+    /// its registration cert, with the network left unset
+    pointers: BTreeMap<Pointer, Vec<u8>>,
+}
+
+/// Members of the persisted index are `slot.tx.cert:reward_hex`
+fn pointer_member((slot, tx, cert): Pointer, reward: &[u8]) -> String {
+    format!("{}.{}.{}:{}", slot, tx, cert, hex::encode(reward))
+}
+
+fn parse_pointer_member(member: &str) -> Option<(Pointer, Vec<u8>)> {
+    let (pointer, reward) = member.split_once(':')?;
+
+    let mut parts = pointer.split('.').map(|x| x.parse::<u64>().ok());
+    let pointer = (parts.next()??, parts.next()??, parts.next()??);
+
+    Some((pointer, hex::decode(reward).ok()?))
+}
+
+impl Reducer {
+    fn prefix(&self) -> &str {
+        self.config
+            .key_prefix
+            .as_deref()
+            .unwrap_or("balance_by_stake")
+    }
+
+    /// Key of the set that persists the index of stake registrations
+    fn pointers_key(&self) -> String {
+        format!("{}._pointers", self.prefix())
+    }
+
+    /// Indexes the stake registrations of the block, so that pointer addresses
+    /// can be resolved to the registered credential
+    fn index_registrations(
+        &mut self,
+        block: &MultiEraBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let slot = block.slot();
+
+        for (tx_idx, tx) in block.txs().iter().enumerate() {
+            // certs of txs that failed phase-2 validation don't take effect
+            if !tx.is_valid() {
+                continue;
+            }
+
+            for (cert_idx, cert) in tx.certs().iter().enumerate() {
+                let credential = match cert.as_alonzo() {
+                    Some(alonzo::Certificate::StakeRegistration(x)) => x,
+                    _ => continue,
+                };
+
+                // the network is taken from the pointer address when resolving
+                let (reward_type, hash) = match credential {
+                    StakeCredential::AddrKeyhash(x) => (0xe0, x),
+                    StakeCredential::Scripthash(x) => (0xf0, x),
+                };
+
+                let mut reward = vec![reward_type];
+                reward.extend_from_slice(hash.as_ref());
+
+                let pointer = (slot, tx_idx as u64, cert_idx as u64);
+
+                let crdt = model::CRDTCommand::SetAdd(
+                    self.pointers_key(),
+                    pointer_member(pointer, &reward),
+                );
+                output.send(gasket::messaging::Message::from(crdt))?;
+
+                self.pointers.insert(pointer, reward);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn stake_key(&self, reward: &[u8]) -> Option<String> {
+        match self.config.key_format {
+            Some(KeyFormat::Credential) => Some(hex::encode(&reward[1..])),
+            _ => crosscut::addresses::reward_address(&self.address_hrp, reward),
+        }
+    }
+
+    /// Finds the bucket in which the balance of an address is accumulated
+    ///
+    /// Pointer addresses are resolved through the index of stake registrations.
+    /// Pointers that don't match any registration seen so far are kept in a
+    /// `pointer` bucket keyed by the pointer itself. Addresses that can't be
+    /// delegated are kept in a separate `enterprise` bucket, one per address.
+    fn balance_key(&self, address: &str) -> String {
+        let bytes = crosscut::addresses::bech32_to_bytes(address);
+
+        let stake = bytes
+            .as_deref()
+            .map(crosscut::addresses::stake_part)
+            .unwrap_or(StakePart::None);
+
+        let key = match stake {
+            StakePart::Credential { header, hash } => {
+                self.stake_key(&crosscut::addresses::reward_bytes(header, &hash))
+            }
+            StakePart::Pointer(slot, tx, cert) => match self.pointers.get(&(slot, tx, cert)) {
+                Some(reward) => {
+                    let mut reward = reward.clone();
+                    let network = bytes.as_deref().map(|x| x[0] & 0x0f).unwrap_or_default();
+                    reward[0] |= network;
+                    self.stake_key(&reward)
+                }
+                None => {
+                    log::debug!(
+                        "no stake registration found for pointer address {}",
+                        address
+                    );
+                    Some(format!("pointer.{}.{}.{}", slot, tx, cert))
+                }
+            },
+            StakePart::None => None,
+        };
+
+        let key = key.unwrap_or_else(|| format!("enterprise.{}", address));
+
+        format!("{}.{}", self.prefix(), key)
+    }
+
+    /// Sends the lovelace delta of the bucket plus one delta per asset, keyed
+    /// as `key` and `key.policy.asset`
+    fn send_deltas(
+        &mut self,
+        address: &str,
+        lovelace: u64,
+        assets: &[(Hash<28>, Vec<u8>, u64)],
+        sign: i64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let key = self.balance_key(address);
+
+        for (policy, asset_name, amount) in assets {
+            let key = format!("{}.{}.{}", key, policy, hex::encode(asset_name));
+
+            for crdt in model::CRDTCommand::pn_counter_deltas(key, *amount, sign) {
+                output.send(gasket::messaging::Message::from(crdt))?;
+            }
+        }
+
+        for crdt in model::CRDTCommand::pn_counter_deltas(key, lovelace, sign) {
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    fn process_inbound_txo(
+        &mut self,
+        ctx: &model::BlockContext,
+        input: &OutputRef,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let utxo = ctx
+            .find_utxo(input)
+            .apply_policy(&self.config.policy)
+            .or_work_err()?;
+
+        let utxo = match utxo {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        self.send_deltas(&utxo.address, utxo.lovelace, &utxo.assets, -1, output)
+    }
+
+    fn process_outbound_txo(
+        &mut self,
+        tx_output: &MultiEraOutput,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let address = tx_output.address(&self.address_hrp);
+        let assets = model::output_assets(tx_output);
+
+        self.send_deltas(&address, tx_output.ada_amount(), &assets, 1, output)
+    }
+
+    pub fn reduce_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        ctx: &model::BlockContext,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        self.index_registrations(block, output)?;
+
+        for tx in block.txs().into_iter() {
+            for input in crosscut::utxos::consumed_inputs(&tx) {
+                self.process_inbound_txo(ctx, &input, output)?;
+            }
+
+            for (_, tx_output) in crosscut::utxos::produced_outputs(&tx) {
+                self.process_outbound_txo(&tx_output, output)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn load_state(&mut self, storage: &mut storage::Bootstrapper) -> Result<(), crate::Error> {
+        let query = model::StateQuery::SetMembers(self.pointers_key());
+
+        if let model::StateData::SetMembers(members) = storage.read_state(&query)? {
+            for member in members {
+                match parse_pointer_member(&member) {
+                    Some((pointer, reward)) => {
+                        self.pointers.insert(pointer, reward);
+                    }
+                    None => log::warn!(
+                        "skipping malformed stake registration index entry {}",
+                        member
+                    ),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops the registrations of a rolled back block from the index
+    pub fn undo_block(
+        &mut self,
+        record: &model::UndoRecord,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let undone = self.pointers.split_off(&(record.slot, 0, 0));
+
+        for (pointer, reward) in undone {
+            let crdt = model::CRDTCommand::SetRemove(
+                self.pointers_key(),
+                pointer_member(pointer, &reward),
+            );
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Reducer {
+        let reducer = Reducer {
+            config: self,
+            address_hrp: chain.address_hrp.clone(),
+            pointers: Default::default(),
+        };
+
+        super::Reducer::BalanceByStake(reducer)
+    }
+}
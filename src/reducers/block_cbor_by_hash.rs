@@ -0,0 +1,83 @@
+use pallas::ledger::traverse::MultiEraBlock;
+use serde::Deserialize;
+
+use super::tx_cbor_by_hash::Encoding;
+use crate::{crosscut, model};
+
+#[derive(Deserialize, Clone)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+    pub encoding: Option<Encoding>,
+    /// Only keep the blocks of the last N epochs, counted in slots from the
+    /// latest block. Older ones are dropped.
+    pub max_epochs: Option<u64>,
+}
+
+pub struct Reducer {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+}
+
+impl Reducer {
+    fn key_prefix(&self) -> &str {
+        self.config
+            .key_prefix
+            .as_deref()
+            .unwrap_or("block_cbor_by_hash")
+    }
+
+    /// Slots covered by the window, `None` if there's no window at all
+    fn window_slots(&self) -> Option<u64> {
+        let epochs = self.config.max_epochs?;
+        Some(epochs * self.chain.shelley_epoch_length as u64)
+    }
+
+    pub fn reduce_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        raw: &model::RawBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let prefix = self.key_prefix();
+        let key = format!("{}.{}", prefix, block.hash());
+
+        let crdt = match self.config.encoding.unwrap_or_default() {
+            Encoding::Hex => model::CRDTCommand::AnyWriteWins(key, hex::encode(&raw.cbor)),
+            Encoding::Raw => model::CRDTCommand::AnyWriteWinsBytes(key, raw.cbor.clone()),
+        };
+
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        if let Some(window) = self.window_slots() {
+            // blocks are indexed by slot so that the ones that fall out of the
+            // window can be found and dropped as the chain moves forward
+            let index = format!("{}.by_slot", prefix);
+
+            let crdt = model::CRDTCommand::SortedSetAdd(
+                index.clone(),
+                block.hash().to_string(),
+                block.slot() as i64,
+            );
+
+            output.send(gasket::messaging::Message::from(crdt))?;
+
+            let floor = block.slot().saturating_sub(window);
+
+            let crdt = model::CRDTCommand::SortedSetPrune(index, prefix.to_string(), floor as i64);
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Reducer {
+        let reducer = Reducer {
+            config: self,
+            chain: chain.clone(),
+        };
+
+        super::Reducer::BlockCborByHash(reducer)
+    }
+}
@@ -0,0 +1,68 @@
+use pallas::ledger::traverse::MultiEraBlock;
+use serde::Deserialize;
+
+use crate::{crosscut, model};
+
+#[derive(Deserialize, Clone)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+}
+
+pub struct Reducer {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+}
+
+impl Reducer {
+    fn epoch_key(&self, slot: u64) -> String {
+        let epoch = crosscut::epochs::slot_epoch(&self.chain, slot);
+
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}", prefix, epoch),
+            None => format!("block_hashes_by_epoch.{}", epoch),
+        }
+    }
+
+    pub fn reduce_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let member = format!("{},{}", block.slot(), block.hash());
+
+        // points are scored by slot so that the set keeps chain order
+        let crdt = model::CRDTCommand::SortedSetAdd(
+            self.epoch_key(block.slot()),
+            member,
+            block.slot() as i64,
+        );
+
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        Ok(())
+    }
+
+    pub fn undo_block(
+        &mut self,
+        record: &model::UndoRecord,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let member = format!("{},{}", record.slot, record.hash);
+        let crdt = model::CRDTCommand::SortedSetRemove(self.epoch_key(record.slot), member);
+
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Reducer {
+        let reducer = Reducer {
+            config: self,
+            chain: chain.clone(),
+        };
+
+        super::Reducer::BlockHashesByEpoch(reducer)
+    }
+}
@@ -0,0 +1,120 @@
+use gasket::error::AsWorkError;
+use pallas::{
+    codec::minicbor,
+    crypto::hash::Hasher,
+    ledger::traverse::{Era, MultiEraBlock},
+};
+use serde::{Deserialize, Serialize};
+
+use super::tx_cbor_by_hash::Encoding;
+use crate::model;
+
+#[derive(Deserialize, Clone)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+    pub encoding: Option<Encoding>,
+}
+
+pub struct Reducer {
+    config: Config,
+}
+
+/// Decoded summary of a block, stored next to its header
+#[derive(Serialize)]
+struct Summary {
+    slot: u64,
+    height: u64,
+    /// Pool id of the block producer, not available for Byron blocks
+    issuer: Option<String>,
+    size: usize,
+    tx_count: usize,
+}
+
+/// Slices the header out of the block cbor, which comes wrapped as
+/// `[era, block]` where the header is always the first item of the block
+fn header_cbor(block_cbor: &[u8]) -> Result<&[u8], minicbor::decode::Error> {
+    let mut decoder = minicbor::Decoder::new(block_cbor);
+
+    decoder.array()?;
+    decoder.u16()?;
+    decoder.array()?;
+
+    let start = decoder.position();
+    decoder.skip()?;
+    let end = decoder.position();
+
+    Ok(&block_cbor[start..end])
+}
+
+/// Reads the issuer vkey of a post-Byron header, `[[number, slot, prev_hash,
+/// issuer_vkey, ...], signature]`, and hashes it into its pool id
+fn header_issuer(header_cbor: &[u8]) -> Result<String, minicbor::decode::Error> {
+    let mut decoder = minicbor::Decoder::new(header_cbor);
+
+    decoder.array()?;
+    decoder.array()?;
+    decoder.u64()?;
+    decoder.u64()?;
+    decoder.skip()?;
+    let vkey = decoder.bytes()?;
+
+    Ok(Hasher::<224>::hash(vkey).to_string())
+}
+
+impl Reducer {
+    pub fn reduce_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        raw: &model::RawBlock,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let header = header_cbor(&raw.cbor)
+            .map_err(crate::Error::cbor)
+            .or_work_err()?;
+
+        let issuer = match block.era() {
+            Era::Byron => None,
+            _ => Some(
+                header_issuer(header)
+                    .map_err(crate::Error::cbor)
+                    .or_work_err()?,
+            ),
+        };
+
+        let summary = Summary {
+            slot: block.slot(),
+            height: block.number(),
+            issuer,
+            size: raw.cbor.len(),
+            tx_count: block.txs().len(),
+        };
+
+        let summary = serde_json::to_string(&summary)
+            .map_err(|err| crate::Error::message(err.to_string()))
+            .or_work_err()?;
+
+        let key = match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}", prefix, block.hash()),
+            None => format!("block_header_by_hash.{}", block.hash()),
+        };
+
+        let crdt = match self.config.encoding.unwrap_or_default() {
+            Encoding::Hex => model::CRDTCommand::AnyWriteWins(key.clone(), hex::encode(header)),
+            Encoding::Raw => model::CRDTCommand::AnyWriteWinsBytes(key.clone(), header.to_vec()),
+        };
+
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        let crdt = model::CRDTCommand::AnyWriteWins(format!("{}.summary", key), summary);
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self) -> super::Reducer {
+        let reducer = Reducer { config: self };
+        super::Reducer::BlockHeaderByHash(reducer)
+    }
+}
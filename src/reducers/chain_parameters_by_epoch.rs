@@ -0,0 +1,597 @@
+use std::collections::HashMap;
+
+use gasket::error::AsWorkError;
+use pallas::ledger::primitives::{alonzo, babbage};
+use pallas::ledger::traverse::{Era, MultiEraBlock, MultiEraTx};
+use serde::{Deserialize, Serialize};
+
+use crate::{crosscut, model, storage};
+
+#[derive(Deserialize, Clone)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct ExUnits {
+    pub mem: u64,
+    pub steps: u64,
+}
+
+/// Protocol parameters, also used to describe a proposed update in which only
+/// the changed values are present
+///
+/// Rational values are expressed as `numerator/denominator` strings. Cost
+/// models aren't tracked.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct ProtocolParams {
+    pub min_fee_a: Option<u64>,
+    pub min_fee_b: Option<u64>,
+    pub max_block_body_size: Option<u64>,
+    pub max_tx_size: Option<u64>,
+    pub max_block_header_size: Option<u64>,
+    pub key_deposit: Option<u64>,
+    pub pool_deposit: Option<u64>,
+    pub max_epoch: Option<u64>,
+    pub n_opt: Option<u64>,
+    pub pool_pledge_influence: Option<String>,
+    pub monetary_expansion_rate: Option<String>,
+    pub treasury_growth_rate: Option<String>,
+    pub decentralisation_param: Option<String>,
+    pub protocol_version: Option<(u64, u64)>,
+    pub min_utxo_value: Option<u64>,
+    pub min_pool_cost: Option<u64>,
+    /// Alonzo min utxo value, in lovelace per word of the output
+    pub coins_per_utxo_word: Option<u64>,
+    /// Babbage onwards min utxo value, in lovelace per byte of the output
+    pub ada_per_utxo_byte: Option<u64>,
+    pub price_mem: Option<String>,
+    pub price_steps: Option<String>,
+    pub max_tx_ex_units: Option<ExUnits>,
+    pub max_block_ex_units: Option<ExUnits>,
+    pub max_value_size: Option<u64>,
+    pub collateral_percentage: Option<u64>,
+    pub max_collateral_inputs: Option<u64>,
+}
+
+fn rational(x: &alonzo::RationalNumber) -> String {
+    format!("{}/{}", x.numerator, x.denominator)
+}
+
+/// Maps the fields shared by the Alonzo and Babbage versions of an update
+macro_rules! common_update {
+    ($x:expr) => {
+        ProtocolParams {
+            min_fee_a: $x.minfee_a.map(u64::from),
+            min_fee_b: $x.minfee_b.map(u64::from),
+            max_block_body_size: $x.max_block_body_size.map(u64::from),
+            max_tx_size: $x.max_transaction_size.map(u64::from),
+            max_block_header_size: $x.max_block_header_size.map(u64::from),
+            key_deposit: $x.key_deposit,
+            pool_deposit: $x.pool_deposit,
+            max_epoch: $x.maximum_epoch,
+            n_opt: $x.desired_number_of_stake_pools.map(u64::from),
+            pool_pledge_influence: $x.pool_pledge_influence.as_ref().map(rational),
+            monetary_expansion_rate: $x.expansion_rate.as_ref().map(rational),
+            treasury_growth_rate: $x.treasury_growth_rate.as_ref().map(rational),
+            protocol_version: $x
+                .protocol_version
+                .map(|(major, minor)| (u64::from(major), u64::from(minor))),
+            min_pool_cost: $x.min_pool_cost,
+            price_mem: $x.execution_costs.as_ref().map(|x| rational(&x.mem_price)),
+            price_steps: $x.execution_costs.as_ref().map(|x| rational(&x.step_price)),
+            max_tx_ex_units: $x.max_tx_ex_units.as_ref().map(|x| ExUnits {
+                mem: u64::from(x.mem),
+                steps: u64::from(x.steps),
+            }),
+            max_block_ex_units: $x.max_block_ex_units.as_ref().map(|x| ExUnits {
+                mem: u64::from(x.mem),
+                steps: u64::from(x.steps),
+            }),
+            max_value_size: $x.max_value_size.map(u64::from),
+            collateral_percentage: $x.collateral_percentage.map(u64::from),
+            max_collateral_inputs: $x.max_collateral_inputs.map(u64::from),
+            ..Default::default()
+        }
+    };
+}
+
+impl From<&alonzo::ProtocolParamUpdate> for ProtocolParams {
+    fn from(other: &alonzo::ProtocolParamUpdate) -> Self {
+        ProtocolParams {
+            decentralisation_param: other.decentralization_constant.as_ref().map(rational),
+            // named after bytes in the cddl, but Alonzo counts words
+            coins_per_utxo_word: other.ada_per_utxo_byte,
+            ..common_update!(other)
+        }
+    }
+}
+
+impl From<&babbage::ProtocolParamUpdate> for ProtocolParams {
+    fn from(other: &babbage::ProtocolParamUpdate) -> Self {
+        ProtocolParams {
+            ada_per_utxo_byte: other.ada_per_utxo_byte,
+            ..common_update!(other)
+        }
+    }
+}
+
+impl From<&crosscut::ShelleyGenesisValues> for ProtocolParams {
+    fn from(other: &crosscut::ShelleyGenesisValues) -> Self {
+        ProtocolParams {
+            min_fee_a: Some(other.min_fee_a),
+            min_fee_b: Some(other.min_fee_b),
+            max_block_body_size: Some(other.max_block_body_size),
+            max_tx_size: Some(other.max_tx_size),
+            max_block_header_size: Some(other.max_block_header_size),
+            key_deposit: Some(other.key_deposit),
+            pool_deposit: Some(other.pool_deposit),
+            max_epoch: Some(other.max_epoch),
+            n_opt: Some(other.n_opt),
+            pool_pledge_influence: Some(other.pool_pledge_influence.clone()),
+            monetary_expansion_rate: Some(other.monetary_expansion_rate.clone()),
+            treasury_growth_rate: Some(other.treasury_growth_rate.clone()),
+            decentralisation_param: Some(other.decentralisation_param.clone()),
+            protocol_version: Some(other.protocol_version),
+            min_utxo_value: Some(other.min_utxo_value),
+            min_pool_cost: Some(other.min_pool_cost),
+            ..Default::default()
+        }
+    }
+}
+
+macro_rules! apply_fields {
+    ($target:expr, $update:expr, $($field:ident),*) => {
+        $(
+            if let Some(x) = &$update.$field {
+                $target.$field = Some(x.clone());
+            }
+        )*
+    };
+}
+
+impl ProtocolParams {
+    fn apply(&mut self, update: &ProtocolParams) {
+        apply_fields!(
+            self,
+            update,
+            min_fee_a,
+            min_fee_b,
+            max_block_body_size,
+            max_tx_size,
+            max_block_header_size,
+            key_deposit,
+            pool_deposit,
+            max_epoch,
+            n_opt,
+            pool_pledge_influence,
+            monetary_expansion_rate,
+            treasury_growth_rate,
+            decentralisation_param,
+            protocol_version,
+            min_utxo_value,
+            min_pool_cost,
+            coins_per_utxo_word,
+            ada_per_utxo_byte,
+            price_mem,
+            price_steps,
+            max_tx_ex_units,
+            max_block_ex_units,
+            max_value_size,
+            collateral_percentage,
+            max_collateral_inputs
+        );
+    }
+}
+
+/// Lists the updates proposed by a tx as (target epoch, genesis key, update)
+fn proposed_updates(tx: &MultiEraTx) -> Vec<(u64, String, ProtocolParams)> {
+    if let Some(update) = tx
+        .as_alonzo()
+        .and_then(|x| x.transaction_body.update.as_ref())
+    {
+        return update
+            .proposed_protocol_parameter_updates
+            .iter()
+            .map(|(key, x)| (update.epoch, key.to_string(), ProtocolParams::from(x)))
+            .collect();
+    }
+
+    if let Some(update) = tx
+        .as_babbage()
+        .and_then(|x| x.transaction_body.update.as_ref())
+    {
+        return update
+            .proposed_protocol_parameter_updates
+            .iter()
+            .map(|(key, x)| (update.epoch, key.to_string(), ProtocolParams::from(x)))
+            .collect();
+    }
+
+    vec![]
+}
+
+/// An update proposed by a genesis key, along with the slot of its block so
+/// that it can be undone on rollback
+#[derive(Serialize, Deserialize, Clone)]
+struct Proposal {
+    slot: u64,
+    genesis_key: String,
+    update: ProtocolParams,
+}
+
+/// State persisted in storage, so that the reducer can resume mid-chain
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct State {
+    current: ProtocolParams,
+    epoch: Option<u64>,
+    /// Proposals by target epoch, in chain order. A later proposal of a
+    /// genesis key replaces its previous one.
+    proposals: HashMap<u64, Vec<Proposal>>,
+    /// The state right before the last epoch transition, along with the slot
+    /// of the block that triggered it, kept to undo the transition
+    before_transition: Option<(u64, Box<State>)>,
+}
+
+/// Follows the update proposals of the chain to know the protocol parameters
+/// in effect, shared by the reducers that depend on them
+///
+/// The state is seeded from the Shelley genesis values and persisted by the
+/// owning reducer along with its output, so it can only start from scratch at
+/// the beginning of the Shelley era.
+pub struct ParamsTracker {
+    chain: crosscut::ChainWellKnownInfo,
+    update_quorum: usize,
+    stability_window: u64,
+    state: State,
+    /// There was no persisted state, the first block needs to be at the start
+    /// of the Shelley era
+    fresh: bool,
+}
+
+/// Picks the update proposed for an epoch, as long as a quorum of genesis keys
+/// agreed on the exact same values
+fn agreed_update(proposals: &[Proposal], quorum: usize) -> Option<&ProtocolParams> {
+    let mut latest: HashMap<&str, &ProtocolParams> = HashMap::new();
+
+    for proposal in proposals {
+        latest.insert(&proposal.genesis_key, &proposal.update);
+    }
+
+    let mut votes: Vec<(&ProtocolParams, usize)> = vec![];
+
+    for update in latest.into_values() {
+        match votes.iter_mut().find(|(x, _)| *x == update) {
+            Some((_, count)) => *count += 1,
+            None => votes.push((update, 1)),
+        }
+    }
+
+    votes
+        .into_iter()
+        .find(|(_, count)| *count >= quorum)
+        .map(|(x, _)| x)
+}
+
+impl ParamsTracker {
+    pub fn new(chain: &crosscut::ChainWellKnownInfo) -> Self {
+        let genesis = chain.shelley_genesis.as_ref();
+
+        let state = State {
+            current: genesis.map(ProtocolParams::from).unwrap_or_default(),
+            ..Default::default()
+        };
+
+        Self {
+            chain: chain.clone(),
+            update_quorum: genesis.map(|x| x.update_quorum as usize).unwrap_or(5),
+            stability_window: genesis.map(|x| x.stability_window).unwrap_or(129600),
+            state,
+            fresh: false,
+        }
+    }
+
+    /// The parameters in effect for the last block rolled forward
+    pub fn current(&self) -> &ProtocolParams {
+        &self.state.current
+    }
+
+    pub fn epoch(&self) -> Option<u64> {
+        self.state.epoch
+    }
+
+    /// Adopts the update proposed for the ending epoch, if it reached quorum.
+    /// Proposals submitted within the stability window at the end of the
+    /// epoch are moved to the next one.
+    fn adopt_proposals(&mut self, epoch: u64) {
+        let proposals = match self.state.proposals.remove(&epoch) {
+            Some(x) => x,
+            None => return,
+        };
+
+        let too_late = crosscut::epochs::epoch_first_slot(&self.chain, epoch + 1)
+            .saturating_sub(self.stability_window);
+
+        let (proposals, late): (Vec<_>, Vec<_>) =
+            proposals.into_iter().partition(|x| x.slot < too_late);
+
+        if !late.is_empty() {
+            let next = self.state.proposals.entry(epoch + 1).or_default();
+            next.extend(late);
+            next.sort_by_key(|x| x.slot);
+        }
+
+        if proposals.is_empty() {
+            return;
+        }
+
+        match agreed_update(&proposals, self.update_quorum) {
+            Some(update) => self.state.current.apply(update),
+            None => log::warn!("update proposals for epoch {} didn't reach quorum", epoch),
+        }
+    }
+
+    fn start_epoch(&mut self, epoch: u64, slot: u64) {
+        let mut snapshot = self.state.clone();
+        snapshot.before_transition = None;
+
+        if let Some(previous) = self.state.epoch {
+            self.adopt_proposals(previous);
+        }
+
+        self.state.epoch = Some(epoch);
+        self.state.before_transition = Some((slot, Box::new(snapshot)));
+    }
+
+    pub fn state_command(&self, key: &str) -> Result<model::CRDTCommand, crate::Error> {
+        super::state_command(key, &self.state)
+    }
+
+    pub fn load_state(
+        &mut self,
+        storage: &mut storage::Bootstrapper,
+        key: &str,
+    ) -> Result<(), crate::Error> {
+        match super::read_state(storage, key)? {
+            Some(state) => self.state = state,
+            None => self.fresh = true,
+        }
+
+        Ok(())
+    }
+
+    /// Moves to the epoch of the block and collects its update proposals,
+    /// returns whether the state needs to be persisted
+    pub fn roll_forward(&mut self, block: &MultiEraBlock) -> Result<bool, crate::Error> {
+        // Byron has its own update mechanism, parameters start with Shelley
+        if let Era::Byron = block.era() {
+            return Ok(false);
+        }
+
+        let epoch = crosscut::epochs::block_epoch(&self.chain, block);
+
+        if std::mem::take(&mut self.fresh) {
+            // without genesis values every parameter would silently stay unset
+            if self.chain.shelley_genesis.is_none() {
+                return Err(crate::Error::config(
+                    "chain parameters need the Shelley genesis values, set `shelley_genesis` in the chain config",
+                ));
+            }
+
+            let shelley_epoch =
+                crosscut::epochs::slot_epoch(&self.chain, self.chain.shelley_known_slot);
+
+            if epoch != shelley_epoch {
+                return Err(crate::Error::config(format!(
+                    "chain parameters need to be computed from the start of Shelley (epoch {}), but there's no persisted state and the pipeline starts at epoch {}",
+                    shelley_epoch, epoch
+                )));
+            }
+        }
+
+        let mut changed = false;
+
+        if self.state.epoch != Some(epoch) {
+            self.start_epoch(epoch, block.slot());
+            changed = true;
+        }
+
+        for tx in block.txs() {
+            if !tx.is_valid() {
+                continue;
+            }
+
+            for (target, genesis_key, update) in proposed_updates(&tx) {
+                let proposal = Proposal {
+                    slot: block.slot(),
+                    genesis_key,
+                    update,
+                };
+
+                self.state
+                    .proposals
+                    .entry(target)
+                    .or_default()
+                    .push(proposal);
+                changed = true;
+            }
+        }
+
+        Ok(changed)
+    }
+
+    /// Drops the proposals of a rolled back block and reverts the epoch
+    /// transition that it triggered, if any
+    pub fn undo_block(&mut self, record: &model::UndoRecord) {
+        if matches!(&self.state.before_transition, Some((slot, _)) if *slot >= record.slot) {
+            let (_, previous) = self.state.before_transition.take().unwrap();
+            self.state = *previous;
+        }
+
+        for proposals in self.state.proposals.values_mut() {
+            proposals.retain(|x| x.slot < record.slot);
+        }
+
+        self.state.proposals.retain(|_, x| !x.is_empty());
+    }
+}
+
+/// Computes the effective protocol parameters of each epoch
+pub struct Reducer {
+    config: Config,
+    params: ParamsTracker,
+}
+
+impl Reducer {
+    fn key_prefix(&self) -> &str {
+        self.config
+            .key_prefix
+            .as_deref()
+            .unwrap_or("chain_parameters_by_epoch")
+    }
+
+    fn state_key(&self) -> String {
+        format!("{}._state", self.key_prefix())
+    }
+
+    fn send_params(
+        &mut self,
+        epoch: u64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let key = format!("{}.{}", self.key_prefix(), epoch);
+
+        let value = serde_json::to_string(self.params.current())
+            .map_err(|err| crate::Error::message(err.to_string()))
+            .or_work_err()?;
+
+        let crdt = model::CRDTCommand::AnyWriteWins(key, value);
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        Ok(())
+    }
+
+    fn send_state(&self, output: &mut super::OutputPort) -> Result<(), gasket::error::Error> {
+        let crdt = self.params.state_command(&self.state_key()).or_work_err()?;
+        output.send(gasket::messaging::Message::from(crdt))
+    }
+
+    pub fn load_state(&mut self, storage: &mut storage::Bootstrapper) -> Result<(), crate::Error> {
+        let key = self.state_key();
+        self.params.load_state(storage, &key)
+    }
+
+    pub fn reduce_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let previous_epoch = self.params.epoch();
+
+        if !self.params.roll_forward(block).or_work_err()? {
+            return Ok(());
+        }
+
+        if let Some(epoch) = self.params.epoch().filter(|x| Some(*x) != previous_epoch) {
+            self.send_params(epoch, output)?;
+        }
+
+        self.send_state(output)
+    }
+
+    pub fn undo_block(
+        &mut self,
+        record: &model::UndoRecord,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        self.params.undo_block(record);
+        self.send_state(output)
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Reducer {
+        let reducer = Reducer {
+            config: self,
+            params: ParamsTracker::new(chain),
+        };
+
+        super::Reducer::ChainParametersByEpoch(reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proposal(genesis_key: &str, min_fee_a: u64) -> Proposal {
+        Proposal {
+            slot: 0,
+            genesis_key: genesis_key.to_string(),
+            update: ProtocolParams {
+                min_fee_a: Some(min_fee_a),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn update_is_adopted_with_quorum() {
+        let proposals: Vec<_> = ["a", "b", "c"].iter().map(|x| proposal(x, 44)).collect();
+        let update = agreed_update(&proposals, 3).unwrap();
+
+        assert_eq!(update.min_fee_a, Some(44));
+    }
+
+    #[test]
+    fn update_is_ignored_without_quorum() {
+        let proposals = vec![proposal("a", 44), proposal("b", 44), proposal("c", 45)];
+
+        assert!(agreed_update(&proposals, 3).is_none());
+    }
+
+    #[test]
+    fn later_proposal_of_a_key_replaces_the_previous_one() {
+        // "a" changes its mind, so only two keys agree on 44
+        let proposals = vec![
+            proposal("a", 44),
+            proposal("b", 44),
+            proposal("c", 44),
+            proposal("a", 45),
+        ];
+
+        assert!(agreed_update(&proposals, 3).is_none());
+
+        // the same key voting twice doesn't count as two votes
+        let proposals = vec![proposal("a", 44), proposal("a", 44), proposal("b", 44)];
+
+        assert!(agreed_update(&proposals, 3).is_none());
+        assert!(agreed_update(&proposals, 2).is_some());
+    }
+
+    #[test]
+    fn late_proposals_move_to_the_next_epoch() {
+        let chain = crosscut::ChainWellKnownInfo::mainnet();
+        let mut tracker = ParamsTracker::new(&chain);
+
+        let too_late = crosscut::epochs::epoch_first_slot(&chain, 301) - 129600;
+
+        let late: Vec<_> = ["a", "b", "c", "d", "e"]
+            .iter()
+            .map(|x| Proposal {
+                slot: too_late,
+                ..proposal(x, 50)
+            })
+            .collect();
+
+        tracker.state.proposals.insert(300, late);
+        tracker.adopt_proposals(300);
+
+        assert_eq!(tracker.current().min_fee_a, Some(44));
+        assert_eq!(tracker.state.proposals[&301].len(), 5);
+
+        tracker.adopt_proposals(301);
+
+        assert_eq!(tracker.current().min_fee_a, Some(50));
+    }
+}
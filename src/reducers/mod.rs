@@ -1,8 +1,7 @@
-use gasket::runtime::spawn_stage;
 use pallas::ledger::traverse::MultiEraBlock;
 use serde::Deserialize;
 
-use crate::{bootstrap, crosscut, model};
+use crate::{bootstrap, crosscut, model, storage};
 
 type InputPort = gasket::messaging::InputPort<model::EnrichedBlockPayload>;
 type OutputPort = gasket::messaging::OutputPort<model::CRDTCommand>;
@@ -26,8 +25,32 @@ pub mod transactions_count_by_address_by_epoch;
 pub mod transactions_count_by_epoch;
 #[cfg(feature = "unstable")]
 pub mod balance_by_address;
+#[cfg(feature = "unstable")]
+pub mod utxos_by_asset;
+#[cfg(feature = "unstable")]
+pub mod tx_cbor_by_hash;
+#[cfg(feature = "unstable")]
+pub mod block_cbor_by_hash;
+#[cfg(feature = "unstable")]
+pub mod block_header_by_hash;
+#[cfg(feature = "unstable")]
+pub mod tx_hashes_by_block_hash;
+#[cfg(feature = "unstable")]
+pub mod block_hashes_by_epoch;
+#[cfg(feature = "unstable")]
+pub mod address_by_ada_handle;
+#[cfg(feature = "unstable")]
+pub mod ada_handle_by_address;
+#[cfg(feature = "unstable")]
+pub mod pool_metadata_by_pool_id;
+#[cfg(feature = "unstable")]
+pub mod chain_parameters_by_epoch;
+#[cfg(feature = "unstable")]
+pub mod balance_by_stake;
+#[cfg(feature = "unstable")]
+pub mod stake_registration;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 #[serde(tag = "type")]
 pub enum Config {
     UtxoByAddress(utxo_by_address::Config),
@@ -54,9 +77,85 @@ pub enum Config {
     BalanceByAddress(
         balance_by_address::Config,
     ),
+    #[cfg(feature = "unstable")]
+    UtxosByAsset(utxos_by_asset::Config),
+    #[cfg(feature = "unstable")]
+    TxCborByHash(tx_cbor_by_hash::Config),
+    #[cfg(feature = "unstable")]
+    BlockCborByHash(block_cbor_by_hash::Config),
+    #[cfg(feature = "unstable")]
+    BlockHeaderByHash(block_header_by_hash::Config),
+    #[cfg(feature = "unstable")]
+    TxHashesByBlockHash(tx_hashes_by_block_hash::Config),
+    #[cfg(feature = "unstable")]
+    BlockHashesByEpoch(block_hashes_by_epoch::Config),
+    #[cfg(feature = "unstable")]
+    AddressByAdaHandle(address_by_ada_handle::Config),
+    #[cfg(feature = "unstable")]
+    AdaHandleByAddress(ada_handle_by_address::Config),
+    #[cfg(feature = "unstable")]
+    PoolMetadataByPoolId(pool_metadata_by_pool_id::Config),
+    #[cfg(feature = "unstable")]
+    ChainParametersByEpoch(chain_parameters_by_epoch::Config),
+    #[cfg(feature = "unstable")]
+    BalanceByStake(balance_by_stake::Config),
+    #[cfg(feature = "unstable")]
+    StakeRegistration(stake_registration::Config),
 }
 
 impl Config {
+    /// Tells if the reducer needs an enrich stage to resolve the inputs of each
+    /// tx, it won't be able to produce its data from an empty `BlockContext`
+    ///
+    /// Some reducers only need resolved inputs depending on their filters, so
+    /// the answer might depend on the config of the reducer.
+    pub fn requires_enrich(&self) -> bool {
+        match self {
+            Config::UtxoByAddress(_) => true,
+            Config::PointByTx(_) => false,
+            Config::PoolByStake(_) => false,
+
+            #[cfg(feature = "unstable")]
+            Config::AddressByTxo(_) => false,
+            #[cfg(feature = "unstable")]
+            Config::TotalTransactionsCount(_) => false,
+            #[cfg(feature = "unstable")]
+            Config::TransactionsCountByEpoch(_) => false,
+            #[cfg(feature = "unstable")]
+            Config::TransactionsCountByAddress(_) => true,
+            #[cfg(feature = "unstable")]
+            Config::TransactionsCountByAddressByEpoch(_) => true,
+            #[cfg(feature = "unstable")]
+            Config::TotalTransactionsCountByAddresses(_) => false,
+            #[cfg(feature = "unstable")]
+            Config::BalanceByAddress(_) => true,
+            #[cfg(feature = "unstable")]
+            Config::UtxosByAsset(_) => true,
+            #[cfg(feature = "unstable")]
+            Config::TxCborByHash(c) => c.addresses.is_some(),
+            #[cfg(feature = "unstable")]
+            Config::BlockCborByHash(_) => false,
+            #[cfg(feature = "unstable")]
+            Config::BlockHeaderByHash(_) => false,
+            #[cfg(feature = "unstable")]
+            Config::TxHashesByBlockHash(_) => false,
+            #[cfg(feature = "unstable")]
+            Config::BlockHashesByEpoch(_) => false,
+            #[cfg(feature = "unstable")]
+            Config::AddressByAdaHandle(_) => false,
+            #[cfg(feature = "unstable")]
+            Config::AdaHandleByAddress(_) => true,
+            #[cfg(feature = "unstable")]
+            Config::PoolMetadataByPoolId(_) => false,
+            #[cfg(feature = "unstable")]
+            Config::ChainParametersByEpoch(_) => false,
+            #[cfg(feature = "unstable")]
+            Config::BalanceByStake(_) => true,
+            #[cfg(feature = "unstable")]
+            Config::StakeRegistration(_) => false,
+        }
+    }
+
     fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> Reducer {
         match self {
             Config::UtxoByAddress(c) => c.plugin(chain),
@@ -77,6 +176,30 @@ impl Config {
             Config::TotalTransactionsCountByAddresses(c) => c.plugin(),
             #[cfg(feature = "unstable")]
             Config::BalanceByAddress(c) => c.plugin(chain),
+            #[cfg(feature = "unstable")]
+            Config::UtxosByAsset(c) => c.plugin(),
+            #[cfg(feature = "unstable")]
+            Config::TxCborByHash(c) => c.plugin(chain),
+            #[cfg(feature = "unstable")]
+            Config::BlockCborByHash(c) => c.plugin(chain),
+            #[cfg(feature = "unstable")]
+            Config::BlockHeaderByHash(c) => c.plugin(),
+            #[cfg(feature = "unstable")]
+            Config::TxHashesByBlockHash(c) => c.plugin(),
+            #[cfg(feature = "unstable")]
+            Config::BlockHashesByEpoch(c) => c.plugin(chain),
+            #[cfg(feature = "unstable")]
+            Config::AddressByAdaHandle(c) => c.plugin(chain),
+            #[cfg(feature = "unstable")]
+            Config::AdaHandleByAddress(c) => c.plugin(chain),
+            #[cfg(feature = "unstable")]
+            Config::PoolMetadataByPoolId(c) => c.plugin(chain),
+            #[cfg(feature = "unstable")]
+            Config::ChainParametersByEpoch(c) => c.plugin(chain),
+            #[cfg(feature = "unstable")]
+            Config::BalanceByStake(c) => c.plugin(chain),
+            #[cfg(feature = "unstable")]
+            Config::StakeRegistration(c) => c.plugin(chain),
         }
     }
 }
@@ -85,12 +208,14 @@ pub struct Bootstrapper {
     input: InputPort,
     output: OutputPort,
     reducers: Vec<Reducer>,
+    address_hrp: String,
 }
 
 impl Bootstrapper {
     pub fn new(configs: Vec<Config>, chain: &crosscut::ChainWellKnownInfo) -> Self {
         Self {
             reducers: configs.into_iter().map(|x| x.plugin(&chain)).collect(),
+            address_hrp: chain.address_hrp.clone(),
             input: Default::default(),
             output: Default::default(),
         }
@@ -104,9 +229,20 @@ impl Bootstrapper {
         &mut self.output
     }
 
+    /// Restores the in-memory state that some reducers persisted on a previous
+    /// run, needs to happen before the pipeline starts
+    pub fn load_state(&mut self, storage: &mut storage::Bootstrapper) -> Result<(), crate::Error> {
+        for reducer in self.reducers.iter_mut() {
+            reducer.load_state(storage)?;
+        }
+
+        Ok(())
+    }
+
     pub fn spawn_stages(self, pipeline: &mut bootstrap::Pipeline) {
-        let worker = worker::Worker::new(self.reducers, self.input, self.output);
-        pipeline.register_stage("reducers", spawn_stage(worker, Default::default()));
+        let worker =
+            worker::Worker::new(self.reducers, self.address_hrp, self.input, self.output);
+        pipeline.register_stage("reducers", worker, Default::default());
     }
 }
 
@@ -135,12 +271,39 @@ pub enum Reducer {
     BalanceByAddress(
         balance_by_address::Reducer,
     ),
+    #[cfg(feature = "unstable")]
+    UtxosByAsset(utxos_by_asset::Reducer),
+    #[cfg(feature = "unstable")]
+    TxCborByHash(tx_cbor_by_hash::Reducer),
+    #[cfg(feature = "unstable")]
+    BlockCborByHash(block_cbor_by_hash::Reducer),
+    #[cfg(feature = "unstable")]
+    BlockHeaderByHash(block_header_by_hash::Reducer),
+    #[cfg(feature = "unstable")]
+    TxHashesByBlockHash(tx_hashes_by_block_hash::Reducer),
+    #[cfg(feature = "unstable")]
+    BlockHashesByEpoch(block_hashes_by_epoch::Reducer),
+    #[cfg(feature = "unstable")]
+    AddressByAdaHandle(address_by_ada_handle::Reducer),
+    #[cfg(feature = "unstable")]
+    AdaHandleByAddress(ada_handle_by_address::Reducer),
+    #[cfg(feature = "unstable")]
+    PoolMetadataByPoolId(pool_metadata_by_pool_id::Reducer),
+    #[cfg(feature = "unstable")]
+    ChainParametersByEpoch(chain_parameters_by_epoch::Reducer),
+    #[cfg(feature = "unstable")]
+    BalanceByStake(balance_by_stake::Reducer),
+    #[cfg(feature = "unstable")]
+    StakeRegistration(stake_registration::Reducer),
 }
 
 impl Reducer {
+    // the raw block is only needed by some of the unstable reducers
+    #[cfg_attr(not(feature = "unstable"), allow(unused_variables))]
     pub fn reduce_block<'b>(
         &mut self,
         block: &'b MultiEraBlock<'b>,
+        raw: &model::RawBlock,
         ctx: &model::BlockContext,
         output: &mut OutputPort,
     ) -> Result<(), gasket::error::Error> {
@@ -163,6 +326,117 @@ impl Reducer {
             Reducer::TotalTransactionsCountByAddresses(x) => x.reduce_block(block, output),
             #[cfg(feature = "unstable")]
             Reducer::BalanceByAddress(x) => x.reduce_block(block, ctx, output),
+            #[cfg(feature = "unstable")]
+            Reducer::UtxosByAsset(x) => x.reduce_block(block, ctx, output),
+            #[cfg(feature = "unstable")]
+            Reducer::TxCborByHash(x) => x.reduce_block(block, raw, ctx, output),
+            #[cfg(feature = "unstable")]
+            Reducer::BlockCborByHash(x) => x.reduce_block(block, raw, output),
+            #[cfg(feature = "unstable")]
+            Reducer::BlockHeaderByHash(x) => x.reduce_block(block, raw, output),
+            #[cfg(feature = "unstable")]
+            Reducer::TxHashesByBlockHash(x) => x.reduce_block(block, output),
+            #[cfg(feature = "unstable")]
+            Reducer::BlockHashesByEpoch(x) => x.reduce_block(block, output),
+            #[cfg(feature = "unstable")]
+            Reducer::AddressByAdaHandle(x) => x.reduce_block(block, output),
+            #[cfg(feature = "unstable")]
+            Reducer::AdaHandleByAddress(x) => x.reduce_block(block, ctx, output),
+            #[cfg(feature = "unstable")]
+            Reducer::PoolMetadataByPoolId(x) => x.reduce_block(block, output),
+            #[cfg(feature = "unstable")]
+            Reducer::ChainParametersByEpoch(x) => x.reduce_block(block, output),
+            #[cfg(feature = "unstable")]
+            Reducer::BalanceByStake(x) => x.reduce_block(block, ctx, output),
+            #[cfg(feature = "unstable")]
+            Reducer::StakeRegistration(x) => x.reduce_block(block, output),
         }
     }
+
+    /// Reverts the effects of a block that was rolled back, only reducers
+    /// that keep ordered indexes of blocks need to do so
+    #[cfg_attr(not(feature = "unstable"), allow(unused_variables))]
+    pub fn undo_block(
+        &mut self,
+        record: &model::UndoRecord,
+        output: &mut OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        match self {
+            #[cfg(feature = "unstable")]
+            Reducer::TxHashesByBlockHash(x) => x.undo_block(record, output),
+            #[cfg(feature = "unstable")]
+            Reducer::BalanceByStake(x) => x.undo_block(record, output),
+            #[cfg(feature = "unstable")]
+            Reducer::BlockHashesByEpoch(x) => x.undo_block(record, output),
+            #[cfg(feature = "unstable")]
+            Reducer::PoolMetadataByPoolId(x) => x.undo_block(record, output),
+            #[cfg(feature = "unstable")]
+            Reducer::ChainParametersByEpoch(x) => x.undo_block(record, output),
+            #[cfg(feature = "unstable")]
+            Reducer::StakeRegistration(x) => x.undo_block(record, output),
+            _ => Ok(()),
+        }
+    }
+
+    #[cfg_attr(not(feature = "unstable"), allow(unused_variables))]
+    pub fn load_state(&mut self, storage: &mut storage::Bootstrapper) -> Result<(), crate::Error> {
+        match self {
+            #[cfg(feature = "unstable")]
+            Reducer::BalanceByStake(x) => x.load_state(storage),
+            #[cfg(feature = "unstable")]
+            Reducer::PoolMetadataByPoolId(x) => x.load_state(storage),
+            #[cfg(feature = "unstable")]
+            Reducer::ChainParametersByEpoch(x) => x.load_state(storage),
+            #[cfg(feature = "unstable")]
+            Reducer::StakeRegistration(x) => x.load_state(storage),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the reducer does anything on `undo_block`, the worker only
+    /// keeps track of recent blocks if some reducer needs them
+    pub fn can_undo(&self) -> bool {
+        match self {
+            #[cfg(feature = "unstable")]
+            Reducer::TxHashesByBlockHash(_) => true,
+            #[cfg(feature = "unstable")]
+            Reducer::BalanceByStake(_) => true,
+            #[cfg(feature = "unstable")]
+            Reducer::BlockHashesByEpoch(_) => true,
+            #[cfg(feature = "unstable")]
+            Reducer::PoolMetadataByPoolId(_) => true,
+            #[cfg(feature = "unstable")]
+            Reducer::ChainParametersByEpoch(_) => true,
+            #[cfg(feature = "unstable")]
+            Reducer::StakeRegistration(x) => x.can_undo(),
+            _ => false,
+        }
+    }
+}
+
+/// Reads the state that a reducer persisted with `state_command`
+#[cfg(feature = "unstable")]
+fn read_state<T: serde::de::DeserializeOwned>(
+    storage: &mut storage::Bootstrapper,
+    key: &str,
+) -> Result<Option<T>, crate::Error> {
+    match storage.read_state(&model::StateQuery::KeyValue(key.to_string()))? {
+        model::StateData::KeyValue(x) => serde_json::from_str(&x)
+            .map(Some)
+            .map_err(|err| crate::Error::message(err.to_string())),
+        _ => Ok(None),
+    }
+}
+
+/// Persists the in-memory state of a reducer along with the output of the
+/// block, so that it stays in sync with the cursor
+#[cfg(feature = "unstable")]
+fn state_command<T: serde::Serialize>(
+    key: &str,
+    state: &T,
+) -> Result<model::CRDTCommand, crate::Error> {
+    let value =
+        serde_json::to_string(state).map_err(|err| crate::Error::message(err.to_string()))?;
+
+    Ok(model::CRDTCommand::AnyWriteWins(key.to_string(), value))
 }
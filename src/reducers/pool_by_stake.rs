@@ -1,38 +1,44 @@
+use std::collections::HashMap;
+
 use pallas::ledger::primitives::alonzo;
-use pallas::ledger::primitives::alonzo::{PoolKeyhash, StakeCredential};
+use pallas::ledger::primitives::alonzo::StakeCredential;
 use pallas::ledger::traverse::MultiEraBlock;
 use serde::Deserialize;
 
 use crate::model;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct Config {
     pub key_prefix: Option<String>,
 }
 
+/// Keeps the pool each stake credential delegates to
+///
+/// Every delegation adds the pool id to a sorted set at `{prefix}.{credential}`
+/// (or just `{credential}` without a prefix), scored by slot, so the latest
+/// pool is the member with the highest score. Deregistering the credential
+/// adds an empty string as the latest member, which readers need to take as
+/// "not delegating".
 pub struct Reducer {
     config: Config,
 }
 
 impl Reducer {
+    /// Writes the pool a credential delegates to, an empty value means that the
+    /// credential isn't delegating anymore
     fn send_key_write(
         &mut self,
-        cred: &StakeCredential,
-        pool: &PoolKeyhash,
+        cred: &str,
+        pool: Option<String>,
         slot: u64,
         output: &mut super::OutputPort,
     ) -> Result<(), gasket::error::Error> {
-        let hash = match cred {
-            StakeCredential::AddrKeyhash(x) => x.to_string(),
-            StakeCredential::Scripthash(x) => x.to_string(),
-        };
-
         let key = match &self.config.key_prefix {
-            Some(prefix) => format!("{}.{}", prefix, hash),
-            None => hash.to_string(),
+            Some(prefix) => format!("{}.{}", prefix, cred),
+            None => cred.to_string(),
         };
 
-        let value = pool.to_string();
+        let value = pool.unwrap_or_default();
 
         let crdt = model::CRDTCommand::LastWriteWins(key, value, slot);
 
@@ -48,20 +54,45 @@ impl Reducer {
     ) -> Result<(), gasket::error::Error> {
         let slot = block.slot();
 
+        // writes of the same block share the same score, so only the last
+        // change of each credential is sent
+        let mut latest: HashMap<String, Option<String>> = HashMap::new();
+
         for tx in block.txs() {
+            // certs of txs that failed phase-2 validation don't take effect
+            if !tx.is_valid() {
+                continue;
+            }
+
             for cert in tx.certs() {
-                if let Some(cert) = cert.as_alonzo() {
-                    if let alonzo::Certificate::StakeDelegation(cred, pool) = cert {
-                        self.send_key_write(cred, pool, slot, output)?;
+                match cert.as_alonzo() {
+                    Some(alonzo::Certificate::StakeDelegation(cred, pool)) => {
+                        latest.insert(credential_hash(cred), Some(pool.to_string()));
                     }
+                    // deregistering a stake key also ends its delegation
+                    Some(alonzo::Certificate::StakeDeregistration(cred)) => {
+                        latest.insert(credential_hash(cred), None);
+                    }
+                    _ => (),
                 }
             }
         }
 
+        for (cred, pool) in latest {
+            self.send_key_write(&cred, pool, slot, output)?;
+        }
+
         Ok(())
     }
 }
 
+fn credential_hash(cred: &StakeCredential) -> String {
+    match cred {
+        StakeCredential::AddrKeyhash(x) => x.to_string(),
+        StakeCredential::Scripthash(x) => x.to_string(),
+    }
+}
+
 impl Config {
     pub fn plugin(self) -> super::Reducer {
         let reducer = Reducer { config: self };
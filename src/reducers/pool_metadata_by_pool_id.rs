@@ -0,0 +1,549 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+use gasket::error::AsWorkError;
+use pallas::ledger::primitives::alonzo::{self, PoolKeyhash};
+use pallas::ledger::traverse::MultiEraBlock;
+use serde::{Deserialize, Serialize};
+
+use crate::{crosscut, model, storage};
+
+#[derive(Deserialize, Clone)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type")]
+enum Relay {
+    SingleHostAddr {
+        port: Option<u32>,
+        ipv4: Option<String>,
+        ipv6: Option<String>,
+    },
+    SingleHostName {
+        port: Option<u32>,
+        dns_name: String,
+    },
+    MultiHostName {
+        dns_name: String,
+    },
+}
+
+fn ipv4_to_string(bytes: &[u8]) -> Option<String> {
+    let bytes: [u8; 4] = bytes.try_into().ok()?;
+    Some(Ipv4Addr::from(bytes).to_string())
+}
+
+/// The ledger encodes ipv6 addresses as four little-endian 32-bit words
+fn ipv6_to_string(bytes: &[u8]) -> Option<String> {
+    let mut bytes: [u8; 16] = bytes.try_into().ok()?;
+
+    for word in bytes.chunks_mut(4) {
+        word.reverse();
+    }
+
+    Some(Ipv6Addr::from(bytes).to_string())
+}
+
+impl From<&alonzo::Relay> for Relay {
+    fn from(other: &alonzo::Relay) -> Self {
+        match other {
+            alonzo::Relay::SingleHostAddr(port, ipv4, ipv6) => Relay::SingleHostAddr {
+                port: *port,
+                ipv4: ipv4.as_ref().and_then(|x| ipv4_to_string(x)),
+                ipv6: ipv6.as_ref().and_then(|x| ipv6_to_string(x)),
+            },
+            alonzo::Relay::SingleHostName(port, dns_name) => Relay::SingleHostName {
+                port: *port,
+                dns_name: dns_name.clone(),
+            },
+            alonzo::Relay::MultiHostName(dns_name) => Relay::MultiHostName {
+                dns_name: dns_name.clone(),
+            },
+        }
+    }
+}
+
+/// Latest parameters of a pool, as declared by its registration cert
+#[derive(Serialize)]
+struct PoolParams {
+    vrf_keyhash: String,
+    pledge: u64,
+    cost: u64,
+    margin: String,
+    /// bech32 stake address, same as the keys of `BalanceByStake`
+    reward_account: Option<String>,
+    owners: Vec<String>,
+    relays: Vec<Relay>,
+    metadata_url: Option<String>,
+    metadata_hash: Option<String>,
+}
+
+/// Params of a re-registration, which only take effect on the next epoch
+#[derive(Serialize, Deserialize, Clone)]
+struct PendingParams {
+    slot: u64,
+    epoch: u64,
+    /// params already serialized as json
+    params: String,
+}
+
+/// Value of the `.pending` key of a pool
+#[derive(Serialize)]
+struct PendingValue<'a> {
+    epoch: u64,
+    params: &'a PoolParams,
+}
+
+fn to_json(value: &impl Serialize) -> Result<String, gasket::error::Error> {
+    serde_json::to_string(value)
+        .map_err(|err| crate::Error::message(err.to_string()))
+        .or_work_err()
+}
+
+/// What the reducer needs to remember about a registered pool, persisted
+/// under a key per pool
+#[derive(Serialize, Deserialize, Clone)]
+struct PoolState {
+    /// Slot of the cert that first registered the pool
+    registered_at: u64,
+    /// Epoch at which the pool retires, along with the slot of the cert
+    retiring: Option<(u64, u64)>,
+    pending: Option<PendingParams>,
+}
+
+/// What the last epoch transition changed, kept to undo it on rollback
+#[derive(Serialize, Deserialize, Clone)]
+struct Transition {
+    /// Slot of the block that started the epoch
+    slot: u64,
+    previous_epoch: Option<u64>,
+    /// Pending params that took effect, by pool
+    adopted: Vec<(String, PendingParams)>,
+    /// Pools dropped because they retired
+    retired: Vec<(String, PoolState)>,
+}
+
+#[derive(Default)]
+struct State {
+    epoch: Option<u64>,
+    pools: HashMap<String, PoolState>,
+    transition: Option<Transition>,
+}
+
+/// Keeps the latest params of each pool
+///
+/// Pool state is persisted along with the output so that re-registrations can
+/// be told apart from new ones after a restart: a `{prefix}._pools` set lists
+/// the registered pools, each with its state at `{prefix}._pools.{pool}`,
+/// while the current epoch and its transition are kept at `{prefix}._epoch`.
+pub struct Reducer {
+    config: Config,
+    chain: crosscut::ChainWellKnownInfo,
+    state: State,
+    /// Pools whose state changed since it was last persisted
+    dirty: HashSet<String>,
+    /// There was no persisted state, pools registered before the first block
+    /// that we process are unknown
+    fresh: bool,
+}
+
+impl Reducer {
+    fn key_prefix(&self) -> &str {
+        self.config
+            .key_prefix
+            .as_deref()
+            .unwrap_or("pool_metadata_by_pool_id")
+    }
+
+    fn pool_key(&self, pool: &str) -> String {
+        format!("{}.{}", self.key_prefix(), pool)
+    }
+
+    fn pools_key(&self) -> String {
+        format!("{}._pools", self.key_prefix())
+    }
+
+    fn pool_state_key(&self, pool: &str) -> String {
+        format!("{}._pools.{}", self.key_prefix(), pool)
+    }
+
+    fn epoch_key(&self) -> String {
+        format!("{}._epoch", self.key_prefix())
+    }
+
+    fn send_lww(
+        &self,
+        key: String,
+        value: String,
+        timestamp: u64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let crdt = model::CRDTCommand::LastWriteWins(key, value, timestamp);
+        output.send(gasket::messaging::Message::from(crdt))
+    }
+
+    /// Applies the pending params that take effect on the new epoch and drops
+    /// the pools that retire on it
+    fn start_epoch(
+        &mut self,
+        epoch: u64,
+        slot: u64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let timestamp = model::tx_timestamp(slot, 0);
+
+        let mut adopted = vec![];
+
+        for (pool, state) in self.state.pools.iter_mut() {
+            if matches!(&state.pending, Some(x) if x.epoch <= epoch) {
+                adopted.push((pool.clone(), state.pending.take().unwrap()));
+            }
+        }
+
+        for (pool, pending) in adopted.iter() {
+            self.send_lww(
+                self.pool_key(pool),
+                pending.params.clone(),
+                timestamp,
+                output,
+            )?;
+
+            // an empty value means that there are no pending params
+            let key = format!("{}.pending", self.pool_key(pool));
+            self.send_lww(key, String::new(), timestamp, output)?;
+
+            self.dirty.insert(pool.clone());
+        }
+
+        let retiring: Vec<_> = self
+            .state
+            .pools
+            .iter()
+            .filter(|(_, x)| matches!(x.retiring, Some((retiring, _)) if retiring <= epoch))
+            .map(|(pool, _)| pool.clone())
+            .collect();
+
+        let mut retired = vec![];
+
+        for pool in retiring {
+            let state = self.state.pools.remove(&pool).unwrap();
+            self.dirty.insert(pool.clone());
+            retired.push((pool, state));
+        }
+
+        self.state.transition = Some(Transition {
+            slot,
+            previous_epoch: self.state.epoch,
+            adopted,
+            retired,
+        });
+
+        self.state.epoch = Some(epoch);
+
+        Ok(())
+    }
+
+    /// Reverts the changes of the last epoch transition, both in state and in
+    /// the output
+    fn undo_transition(
+        &mut self,
+        transition: Transition,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for (pool, pending) in transition.adopted {
+            let crdt =
+                model::CRDTCommand::SortedSetRemove(self.pool_key(&pool), pending.params.clone());
+            output.send(gasket::messaging::Message::from(crdt))?;
+
+            let key = format!("{}.pending", self.pool_key(&pool));
+            let crdt = model::CRDTCommand::SortedSetRemove(key, String::new());
+            output.send(gasket::messaging::Message::from(crdt))?;
+
+            if let Some(state) = self.state.pools.get_mut(&pool) {
+                state.pending = Some(pending);
+            }
+
+            self.dirty.insert(pool);
+        }
+
+        for (pool, state) in transition.retired {
+            self.state.pools.insert(pool.clone(), state);
+            self.dirty.insert(pool);
+        }
+
+        self.state.epoch = transition.previous_epoch;
+
+        Ok(())
+    }
+
+    fn register(
+        &mut self,
+        pool: &PoolKeyhash,
+        params: PoolParams,
+        slot: u64,
+        timestamp: u64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let pool = pool.to_string();
+        let next_epoch = crosscut::epochs::slot_epoch(&self.chain, slot) + 1;
+
+        let pending_value = to_json(&PendingValue {
+            epoch: next_epoch,
+            params: &params,
+        })?;
+
+        let params = to_json(&params)?;
+
+        match self.state.pools.get_mut(&pool) {
+            // params of a registered pool only change on the next epoch
+            Some(state) => {
+                state.pending = Some(PendingParams {
+                    slot,
+                    epoch: next_epoch,
+                    params,
+                });
+
+                state.retiring = None;
+
+                let key = format!("{}.pending", self.pool_key(&pool));
+                self.send_lww(key, pending_value, timestamp, output)?;
+            }
+            None => {
+                let state = PoolState {
+                    registered_at: slot,
+                    retiring: None,
+                    pending: None,
+                };
+
+                self.state.pools.insert(pool.clone(), state);
+                self.send_lww(self.pool_key(&pool), params, timestamp, output)?;
+            }
+        }
+
+        // re-registering a pool cancels any pending retirement, an empty value
+        // means that the pool isn't retiring
+        let key = format!("{}.retiring", self.pool_key(&pool));
+        self.send_lww(key, String::new(), timestamp, output)?;
+
+        self.dirty.insert(pool);
+
+        Ok(())
+    }
+
+    fn retire(
+        &mut self,
+        pool: &PoolKeyhash,
+        epoch: u64,
+        slot: u64,
+        timestamp: u64,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let pool = pool.to_string();
+
+        if let Some(state) = self.state.pools.get_mut(&pool) {
+            state.retiring = Some((epoch, slot));
+        }
+
+        let key = format!("{}.retiring", self.pool_key(&pool));
+        self.send_lww(key, epoch.to_string(), timestamp, output)?;
+
+        self.dirty.insert(pool);
+
+        Ok(())
+    }
+
+    /// Persists the state of the pools that changed, dropped pools are only
+    /// removed from the set of pools
+    fn send_pools(&mut self, output: &mut super::OutputPort) -> Result<(), gasket::error::Error> {
+        for pool in std::mem::take(&mut self.dirty) {
+            let crdt = match self.state.pools.get(&pool) {
+                Some(state) => {
+                    let crdt =
+                        super::state_command(&self.pool_state_key(&pool), state).or_work_err()?;
+                    output.send(gasket::messaging::Message::from(crdt))?;
+
+                    model::CRDTCommand::SetAdd(self.pools_key(), pool)
+                }
+                None => model::CRDTCommand::SetRemove(self.pools_key(), pool),
+            };
+
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    fn send_epoch(&self, output: &mut super::OutputPort) -> Result<(), gasket::error::Error> {
+        let value = EpochState {
+            epoch: self.state.epoch,
+            transition: self.state.transition.clone(),
+        };
+
+        let crdt = super::state_command(&self.epoch_key(), &value).or_work_err()?;
+        output.send(gasket::messaging::Message::from(crdt))
+    }
+
+    pub fn load_state(&mut self, storage: &mut storage::Bootstrapper) -> Result<(), crate::Error> {
+        let epoch: EpochState = match super::read_state(storage, &self.epoch_key())? {
+            Some(x) => x,
+            None => {
+                self.fresh = true;
+                return Ok(());
+            }
+        };
+
+        self.state.epoch = epoch.epoch;
+        self.state.transition = epoch.transition;
+
+        let pools = match storage.read_state(&model::StateQuery::SetMembers(self.pools_key()))? {
+            model::StateData::SetMembers(x) => x,
+            _ => Default::default(),
+        };
+
+        for pool in pools {
+            if let Some(state) = super::read_state(storage, &self.pool_state_key(&pool))? {
+                self.state.pools.insert(pool, state);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let slot = block.slot();
+        let epoch = crosscut::epochs::slot_epoch(&self.chain, slot);
+
+        if std::mem::take(&mut self.fresh) && slot > self.chain.shelley_known_slot {
+            log::warn!(
+                "no pool state found, re-registrations of pools registered before slot {} will be taken as new registrations",
+                slot
+            );
+        }
+
+        if self.state.epoch != Some(epoch) {
+            self.start_epoch(epoch, slot, output)?;
+            self.send_epoch(output)?;
+        }
+
+        for (tx_idx, tx) in block.txs().iter().enumerate() {
+            // certs of txs that failed phase-2 validation don't take effect
+            if !tx.is_valid() {
+                continue;
+            }
+
+            let timestamp = model::tx_timestamp(slot, tx_idx);
+
+            for cert in tx.certs() {
+                match cert.as_alonzo() {
+                    Some(alonzo::Certificate::PoolRegistration {
+                        operator,
+                        vrf_keyhash,
+                        pledge,
+                        cost,
+                        margin,
+                        reward_account,
+                        pool_owners,
+                        relays,
+                        pool_metadata,
+                    }) => {
+                        let params = PoolParams {
+                            vrf_keyhash: vrf_keyhash.to_string(),
+                            pledge: *pledge,
+                            cost: *cost,
+                            margin: format!("{}/{}", margin.numerator, margin.denominator),
+                            reward_account: crosscut::addresses::reward_address(
+                                &self.chain.address_hrp,
+                                reward_account.as_slice(),
+                            ),
+                            owners: pool_owners.iter().map(|x| x.to_string()).collect(),
+                            relays: relays.iter().map(Relay::from).collect(),
+                            metadata_url: pool_metadata.as_ref().map(|x| x.url.clone()),
+                            metadata_hash: pool_metadata.as_ref().map(|x| x.hash.to_string()),
+                        };
+
+                        self.register(operator, params, slot, timestamp, output)?;
+                    }
+                    Some(alonzo::Certificate::PoolRetirement(pool, epoch)) => {
+                        self.retire(pool, *epoch, slot, timestamp, output)?;
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        self.send_pools(output)
+    }
+
+    /// Forgets the registrations, re-registrations and retirements of a
+    /// rolled back block and reverts the epoch transition it triggered, if
+    /// any. Values that the certs replaced in the output aren't restored.
+    pub fn undo_block(
+        &mut self,
+        record: &model::UndoRecord,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let slot = record.slot;
+
+        if matches!(&self.state.transition, Some(x) if x.slot >= slot) {
+            let transition = self.state.transition.take().unwrap();
+            self.undo_transition(transition, output)?;
+            self.send_epoch(output)?;
+        }
+
+        let dropped: Vec<_> = self
+            .state
+            .pools
+            .iter()
+            .filter(|(_, x)| x.registered_at >= slot)
+            .map(|(pool, _)| pool.clone())
+            .collect();
+
+        for pool in dropped {
+            self.state.pools.remove(&pool);
+            self.dirty.insert(pool);
+        }
+
+        for (pool, state) in self.state.pools.iter_mut() {
+            if matches!(&state.pending, Some(x) if x.slot >= slot) {
+                state.pending = None;
+                self.dirty.insert(pool.clone());
+            }
+
+            if matches!(state.retiring, Some((_, x)) if x >= slot) {
+                state.retiring = None;
+                self.dirty.insert(pool.clone());
+            }
+        }
+
+        self.send_pools(output)
+    }
+}
+
+/// Value of the `_epoch` key
+#[derive(Serialize, Deserialize)]
+struct EpochState {
+    epoch: Option<u64>,
+    transition: Option<Transition>,
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Reducer {
+        let reducer = Reducer {
+            config: self,
+            chain: chain.clone(),
+            state: Default::default(),
+            dirty: Default::default(),
+            fresh: false,
+        };
+
+        super::Reducer::PoolMetadataByPoolId(reducer)
+    }
+}
@@ -0,0 +1,201 @@
+use gasket::error::AsWorkError;
+use pallas::ledger::primitives::alonzo::{self, StakeCredential};
+use pallas::ledger::traverse::MultiEraBlock;
+use serde::{Deserialize, Serialize};
+
+use super::chain_parameters_by_epoch::ParamsTracker;
+use crate::{crosscut, model, storage};
+
+#[derive(Deserialize, Clone)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+    /// Deposit paid on registration, overrides the value of the protocol
+    /// parameters. Required to start mid-chain, since the parameters can only
+    /// be followed from the start of Shelley.
+    pub key_deposit: Option<u64>,
+}
+
+pub struct Reducer {
+    config: Config,
+    /// Follows the protocol parameters to know the deposit in effect, unless
+    /// the deposit is fixed by config
+    params: Option<ParamsTracker>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Registered,
+    Deregistered,
+}
+
+#[derive(Serialize)]
+struct Registration {
+    status: Status,
+    /// Deposit held by the ledger, refunded on deregistration
+    deposit: u64,
+    slot: u64,
+}
+
+impl Reducer {
+    fn key_prefix(&self) -> &str {
+        self.config
+            .key_prefix
+            .as_deref()
+            .unwrap_or("stake_registration")
+    }
+
+    fn params_key(&self) -> String {
+        format!("{}._params", self.key_prefix())
+    }
+
+    fn key_deposit(&self) -> Result<u64, crate::Error> {
+        let deposit = match &self.params {
+            Some(params) => params.current().key_deposit,
+            None => self.config.key_deposit,
+        };
+
+        // a wrong deposit would be persisted for good, better to stop here
+        deposit.ok_or_else(|| {
+            crate::Error::config(
+                "the key deposit isn't known, set `key_deposit` or provide the Shelley genesis values",
+            )
+        })
+    }
+
+    fn send_params(&self, output: &mut super::OutputPort) -> Result<(), gasket::error::Error> {
+        if let Some(params) = &self.params {
+            let crdt = params.state_command(&self.params_key()).or_work_err()?;
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    fn send_registration(
+        &mut self,
+        cred: &StakeCredential,
+        registration: Registration,
+        timestamp: model::Timestamp,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let hash = match cred {
+            StakeCredential::AddrKeyhash(x) => x.to_string(),
+            StakeCredential::Scripthash(x) => x.to_string(),
+        };
+
+        let key = format!("{}.{}", self.key_prefix(), hash);
+
+        let value = serde_json::to_string(&registration)
+            .map_err(|err| crate::Error::message(err.to_string()))
+            .or_work_err()?;
+
+        let crdt = model::CRDTCommand::LastWriteWins(key, value, timestamp);
+
+        output.send(gasket::messaging::Message::from(crdt))?;
+
+        Ok(())
+    }
+
+    pub fn reduce_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        if let Some(params) = self.params.as_mut() {
+            let changed = params
+                .roll_forward(block)
+                .map_err(|err| {
+                    crate::Error::message(format!(
+                        "{}, set `key_deposit` to track stake registrations from a later point",
+                        err
+                    ))
+                })
+                .or_work_err()?;
+
+            if changed {
+                self.send_params(output)?;
+            }
+        }
+
+        let slot = block.slot();
+
+        for (tx_idx, tx) in block.txs().iter().enumerate() {
+            // certs of txs that failed phase-2 validation don't take effect
+            if !tx.is_valid() {
+                continue;
+            }
+
+            let timestamp = model::tx_timestamp(slot, tx_idx);
+
+            for cert in tx.certs() {
+                match cert.as_alonzo() {
+                    Some(alonzo::Certificate::StakeRegistration(cred)) => {
+                        let registration = Registration {
+                            status: Status::Registered,
+                            deposit: self.key_deposit().or_work_err()?,
+                            slot,
+                        };
+
+                        self.send_registration(cred, registration, timestamp, output)?;
+                    }
+                    Some(alonzo::Certificate::StakeDeregistration(cred)) => {
+                        let registration = Registration {
+                            status: Status::Deregistered,
+                            deposit: 0,
+                            slot,
+                        };
+
+                        self.send_registration(cred, registration, timestamp, output)?;
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn load_state(&mut self, storage: &mut storage::Bootstrapper) -> Result<(), crate::Error> {
+        let key = self.params_key();
+
+        match self.params.as_mut() {
+            Some(params) => params.load_state(storage, &key),
+            None => Ok(()),
+        }
+    }
+
+    /// Only the tracked protocol parameters need to be undone, registrations
+    /// are last-write-wins entries
+    pub fn can_undo(&self) -> bool {
+        self.params.is_some()
+    }
+
+    pub fn undo_block(
+        &mut self,
+        record: &model::UndoRecord,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        if let Some(params) = self.params.as_mut() {
+            params.undo_block(record);
+        }
+
+        self.send_params(output)
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Reducer {
+        let params = match self.key_deposit {
+            Some(_) => None,
+            None => Some(ParamsTracker::new(chain)),
+        };
+
+        let reducer = Reducer {
+            config: self,
+            params,
+        };
+
+        super::Reducer::StakeRegistration(reducer)
+    }
+}
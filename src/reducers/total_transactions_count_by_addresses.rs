@@ -3,7 +3,7 @@ use serde::Deserialize;
 
 use crate::{crosscut, model};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct Config {
     pub key_prefix: Option<String>,
 }
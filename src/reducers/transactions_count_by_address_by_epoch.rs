@@ -7,7 +7,7 @@ use crate::{crosscut, model};
 use gasket::error::AsWorkError;
 use std::collections::HashSet;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct Config {
     pub key_prefix: Option<String>,
     pub policy: Option<ReducerPolicy>,
@@ -42,29 +42,15 @@ impl Reducer {
         ctx: &model::BlockContext,
         input: &OutputRef
     ) -> Result<Option<String>, gasket::error::Error> {
-        let inbound_tx = ctx
-        .find_ref_tx(input.tx_id())
-        .apply_policy(&self.config.policy)
-        .or_work_err()?;
-
-        let inbound_tx = match inbound_tx {
-            Some(x) => x,
-            None => { 
-                log::error!("Didn't find inbound_tx, tx_id:{}", input.tx_id());
-                return Result::Ok(None)
-            },
-        };
-
-        let output_tx = inbound_tx
-            .output_at(input.tx_index() as usize)
-            .ok_or(crate::Error::ledger("output index not found in tx"))
+        let output_tx = ctx
+            .find_utxo(input)
             .apply_policy(&self.config.policy)
             .or_work_err()?;
 
         match output_tx {
-            Some(x) => return Result::Ok(Some(x.address(&self.address_hrp))),
+            Some(x) => return Result::Ok(Some(x.address.clone())),
             None => { 
-                log::error!("Output index not found, index:{}", input.tx_index());
+                log::error!("Didn't find utxo, tx_id:{}, index:{}", input.tx_id(), input.tx_index());
                 return Result::Ok(None)
              }
         }
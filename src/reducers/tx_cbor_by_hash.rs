@@ -0,0 +1,118 @@
+use gasket::error::AsWorkError;
+use pallas::ledger::traverse::{MultiEraBlock, MultiEraTx};
+use serde::Deserialize;
+
+use crate::{crosscut, enrich, model};
+
+#[derive(Deserialize, Clone, Copy)]
+pub enum Encoding {
+    Hex,
+    Raw,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding::Hex
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+    pub encoding: Option<Encoding>,
+    /// Only store txs that touch any of these addresses
+    pub addresses: Option<Vec<String>>,
+    /// Only store txs with outputs holding assets of any of these policies
+    pub policies: Option<Vec<String>>,
+}
+
+pub struct Reducer {
+    config: Config,
+    address_hrp: String,
+}
+
+impl Reducer {
+    fn matches_addresses(&self, tx: &MultiEraTx, ctx: &model::BlockContext) -> bool {
+        let addresses = match &self.config.addresses {
+            Some(x) => x,
+            None => return false,
+        };
+
+        let outputs = tx
+            .outputs()
+            .iter()
+            .any(|x| addresses.contains(&x.address(&self.address_hrp)));
+
+        // inputs are only checked if the enrich stage was able to resolve them
+        let inputs = crosscut::utxos::consumed_inputs(tx)
+            .iter()
+            .filter_map(|x| ctx.find_utxo(x).ok())
+            .any(|x| addresses.contains(&x.address));
+
+        outputs || inputs
+    }
+
+    fn matches_policies(&self, tx: &MultiEraTx) -> bool {
+        let policies = match &self.config.policies {
+            Some(x) => x,
+            None => return false,
+        };
+
+        tx.outputs()
+            .iter()
+            .flat_map(model::output_assets)
+            .any(|(policy, _, _)| policies.contains(&policy.to_string()))
+    }
+
+    fn is_tx_included(&self, tx: &MultiEraTx, ctx: &model::BlockContext) -> bool {
+        if self.config.addresses.is_none() && self.config.policies.is_none() {
+            return true;
+        }
+
+        self.matches_addresses(tx, ctx) || self.matches_policies(tx)
+    }
+
+    pub fn reduce_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        raw: &model::RawBlock,
+        ctx: &model::BlockContext,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        // txs are stored with their original bytes, as found in the block
+        let raw_txs = enrich::split_block(raw).or_work_err()?;
+
+        for (idx, tx) in block.txs().iter().enumerate() {
+            if !self.is_tx_included(tx, ctx) {
+                continue;
+            }
+
+            let key = match &self.config.key_prefix {
+                Some(prefix) => format!("{}.{}", prefix, tx.hash()),
+                None => format!("tx_cbor_by_hash.{}", tx.hash()),
+            };
+
+            let cbor = enrich::tx_cbor(raw_txs.as_ref(), idx, tx).or_work_err()?;
+
+            let crdt = match self.config.encoding.unwrap_or_default() {
+                Encoding::Hex => model::CRDTCommand::AnyWriteWins(key, hex::encode(cbor)),
+                Encoding::Raw => model::CRDTCommand::AnyWriteWinsBytes(key, cbor),
+            };
+
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self, chain: &crosscut::ChainWellKnownInfo) -> super::Reducer {
+        let reducer = Reducer {
+            config: self,
+            address_hrp: chain.address_hrp.clone(),
+        };
+
+        super::Reducer::TxCborByHash(reducer)
+    }
+}
@@ -0,0 +1,62 @@
+use pallas::{crypto::hash::Hash, ledger::traverse::MultiEraBlock};
+use serde::Deserialize;
+
+use crate::model;
+
+#[derive(Deserialize, Clone)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+}
+
+pub struct Reducer {
+    config: Config,
+}
+
+impl Reducer {
+    fn block_key(&self, hash: &Hash<32>) -> String {
+        match &self.config.key_prefix {
+            Some(prefix) => format!("{}.{}", prefix, hash),
+            None => format!("tx_hashes_by_block_hash.{}", hash),
+        }
+    }
+
+    pub fn reduce_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let key = self.block_key(&block.hash());
+
+        // txs are scored by their position so that the set keeps block order
+        for (idx, tx) in block.txs().iter().enumerate() {
+            let crdt =
+                model::CRDTCommand::SortedSetAdd(key.clone(), tx.hash().to_string(), idx as i64);
+
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn undo_block(
+        &mut self,
+        record: &model::UndoRecord,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let key = self.block_key(&record.hash);
+
+        for tx_hash in record.tx_hashes.iter() {
+            let crdt = model::CRDTCommand::SortedSetRemove(key.clone(), tx_hash.to_string());
+            output.send(gasket::messaging::Message::from(crdt))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self) -> super::Reducer {
+        let reducer = Reducer { config: self };
+        super::Reducer::TxHashesByBlockHash(reducer)
+    }
+}
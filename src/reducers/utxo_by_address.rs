@@ -6,7 +6,7 @@ use serde::Deserialize;
 
 use crate::{crosscut, model};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct Config {
     pub key_prefix: Option<String>,
     pub filter: Option<Vec<String>>,
@@ -25,21 +25,8 @@ impl Reducer {
         input: &OutputRef,
         output: &mut super::OutputPort,
     ) -> Result<(), gasket::error::Error> {
-        let output_idx = input.tx_index();
-
-        let inbound_tx = ctx
-            .find_ref_tx(input.tx_id())
-            .apply_policy(&self.config.policy)
-            .or_work_err()?;
-
-        let inbound_tx = match inbound_tx {
-            Some(x) => x,
-            None => return Ok(()),
-        };
-
-        let output_tx = inbound_tx
-            .output_at(input.tx_index() as usize)
-            .ok_or(crate::Error::ledger("output index not found in tx"))
+        let output_tx = ctx
+            .find_utxo(input)
             .apply_policy(&self.config.policy)
             .or_work_err()?;
 
@@ -48,10 +35,10 @@ impl Reducer {
             None => return Ok(()),
         };
 
-        let address = output_tx.address(&self.address_hrp);
+        let address = &output_tx.address;
 
         if let Some(addresses) = &self.config.filter {
-            if let Err(_) = addresses.binary_search(&address.to_string()) {
+            if let Err(_) = addresses.binary_search(address) {
                 return Ok(());
             }
         }
@@ -59,7 +46,7 @@ impl Reducer {
         let crdt = model::CRDTCommand::set_remove(
             self.config.key_prefix.as_deref(),
             &address,
-            format!("{}#{}", inbound_tx.hash(), output_idx),
+            format!("{}#{}", input.tx_id(), input.tx_index()),
         );
 
         output.send(crdt.into())
@@ -98,13 +85,13 @@ impl Reducer {
     ) -> Result<(), gasket::error::Error> {
         for tx in block.txs().into_iter() {
 
-            for input in tx.inputs().iter().filter_map(|i| i.output_ref()) {
+            for input in crosscut::utxos::consumed_inputs(&tx) {
                 self.process_inbound_txo(&ctx, &input, output)
                     .or_work_err()?;
             }
 
-            for (idx, tx_output) in tx.outputs().iter().enumerate() {
-                self.process_outbound_txo(&tx, tx_output, idx, output)?;
+            for (idx, tx_output) in crosscut::utxos::produced_outputs(&tx) {
+                self.process_outbound_txo(&tx, &tx_output, idx, output)?;
             }
         }
 
@@ -0,0 +1,118 @@
+use crosscut::policies::*;
+use gasket::error::AsWorkError;
+use pallas::crypto::hash::Hash;
+use pallas::ledger::traverse::MultiEraOutput;
+use pallas::ledger::traverse::{MultiEraBlock, MultiEraTx, OutputRef};
+use serde::Deserialize;
+
+use crate::{crosscut, model};
+
+#[derive(Deserialize, Clone)]
+pub struct Config {
+    pub key_prefix: Option<String>,
+    /// Policy ids to track, every asset is tracked if not specified
+    pub filter: Option<Vec<String>>,
+    pub policy: Option<ReducerPolicy>,
+}
+
+pub struct Reducer {
+    config: Config,
+}
+
+fn asset_key(policy: &Hash<28>, asset_name: &[u8]) -> String {
+    format!("{}.{}", policy, hex::encode(asset_name))
+}
+
+impl Reducer {
+    fn is_policy_tracked(&self, policy: &Hash<28>) -> bool {
+        match &self.config.filter {
+            Some(policies) => policies.contains(&policy.to_string()),
+            None => true,
+        }
+    }
+
+    fn process_inbound_txo(
+        &mut self,
+        ctx: &model::BlockContext,
+        input: &OutputRef,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let utxo = ctx
+            .find_utxo(input)
+            .apply_policy(&self.config.policy)
+            .or_work_err()?;
+
+        let utxo = match utxo {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        for (policy, asset_name, _) in utxo.assets.iter() {
+            if !self.is_policy_tracked(policy) {
+                continue;
+            }
+
+            let crdt = model::CRDTCommand::set_remove(
+                self.config.key_prefix.as_deref(),
+                &asset_key(policy, asset_name),
+                format!("{}#{}", input.tx_id(), input.tx_index()),
+            );
+
+            output.send(crdt.into())?;
+        }
+
+        Ok(())
+    }
+
+    fn process_outbound_txo(
+        &mut self,
+        tx: &MultiEraTx,
+        tx_output: &MultiEraOutput,
+        output_idx: usize,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        let tx_hash = tx.hash();
+
+        for (policy, asset_name, _) in model::output_assets(tx_output) {
+            if !self.is_policy_tracked(&policy) {
+                continue;
+            }
+
+            let crdt = model::CRDTCommand::set_add(
+                self.config.key_prefix.as_deref(),
+                &asset_key(&policy, &asset_name),
+                format!("{}#{}", tx_hash, output_idx),
+            );
+
+            output.send(crdt.into())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn reduce_block<'b>(
+        &mut self,
+        block: &'b MultiEraBlock<'b>,
+        ctx: &model::BlockContext,
+        output: &mut super::OutputPort,
+    ) -> Result<(), gasket::error::Error> {
+        for tx in block.txs().into_iter() {
+            for input in crosscut::utxos::consumed_inputs(&tx) {
+                self.process_inbound_txo(ctx, &input, output)?;
+            }
+
+            for (idx, tx_output) in crosscut::utxos::produced_outputs(&tx) {
+                self.process_outbound_txo(&tx, &tx_output, idx, output)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn plugin(self) -> super::Reducer {
+        let reducer = Reducer { config: self };
+        super::Reducer::UtxosByAsset(reducer)
+    }
+}
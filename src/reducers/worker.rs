@@ -1,5 +1,7 @@
+use std::collections::VecDeque;
+
 use gasket::{error::AsWorkError, runtime::WorkOutcome};
-use pallas::ledger::traverse::MultiEraBlock;
+use pallas::network::miniprotocols::Point;
 
 use crate::model;
 
@@ -8,41 +10,100 @@ use super::Reducer;
 type InputPort = gasket::messaging::InputPort<model::EnrichedBlockPayload>;
 type OutputPort = gasket::messaging::OutputPort<model::CRDTCommand>;
 
+/// How many of the latest blocks are kept around to be undone on rollback,
+/// matches the security parameter of the chain
+const MAX_ROLLBACK_DEPTH: usize = 2160;
+
 pub struct Worker {
     input: InputPort,
     output: OutputPort,
     reducers: Vec<Reducer>,
+    address_hrp: String,
+    /// Only tracked when some of the reducers can undo blocks
+    recent_blocks: Option<VecDeque<model::UndoRecord>>,
     ops_count: gasket::metrics::Counter,
 }
 
 impl Worker {
-    pub fn new(reducers: Vec<Reducer>, input: InputPort, output: OutputPort) -> Self {
+    pub fn new(
+        reducers: Vec<Reducer>,
+        address_hrp: String,
+        input: InputPort,
+        output: OutputPort,
+    ) -> Self {
+        let recent_blocks = match reducers.iter().any(Reducer::can_undo) {
+            true => Some(VecDeque::new()),
+            false => None,
+        };
+
         Worker {
             reducers,
+            address_hrp,
+            recent_blocks,
             input,
             output,
             ops_count: Default::default(),
         }
     }
 
-    fn reduce_block<'b>(
+    fn reduce_block(
         &mut self,
-        block: &'b [u8],
+        raw: &model::RawBlock,
         ctx: &model::BlockContext,
     ) -> Result<(), gasket::error::Error> {
-        let block = MultiEraBlock::decode(block).or_work_err()?;
+        let block = raw.decode().or_work_err()?;
+
+        if let Some(recent) = &mut self.recent_blocks {
+            recent.push_back(model::UndoRecord::new(&block));
+
+            if recent.len() > MAX_ROLLBACK_DEPTH {
+                recent.pop_front();
+            }
+        }
 
         self.output.send(gasket::messaging::Message::from(
-            model::CRDTCommand::block_starting(&block),
+            model::CRDTCommand::block_starting(raw),
         ))?;
 
         for reducer in self.reducers.iter_mut() {
-            reducer.reduce_block(&block, ctx, &mut self.output)?;
+            reducer.reduce_block(&block, raw, ctx, &mut self.output)?;
             self.ops_count.inc(1);
         }
 
         self.output.send(gasket::messaging::Message::from(
-            model::CRDTCommand::block_finished(&block),
+            model::CRDTCommand::block_finished(raw),
+        ))?;
+
+        Ok(())
+    }
+
+    fn undo_blocks(&mut self, point: &Point) -> Result<(), gasket::error::Error> {
+        let target = match point {
+            Point::Origin => None,
+            Point::Specific(slot, _) => Some(*slot),
+        };
+
+        if let Some(recent) = &mut self.recent_blocks {
+            while let Some(record) = recent.back() {
+                if matches!(target, Some(slot) if record.slot <= slot) {
+                    break;
+                }
+
+                let record = recent.pop_back().unwrap();
+
+                for reducer in self.reducers.iter_mut() {
+                    reducer.undo_block(&record, &mut self.output)?;
+                }
+            }
+
+            if recent.is_empty() {
+                log::warn!("rollback to {:?} may go beyond the undoable blocks", point);
+            }
+        }
+
+        // moves the cursor back to the rollback point
+        self.output.send(gasket::messaging::Message::from(
+            model::CRDTCommand::BlockFinished(point.clone()),
         ))?;
 
         Ok(())
@@ -57,14 +118,22 @@ impl gasket::runtime::Worker for Worker {
     }
 
     fn work(&mut self) -> gasket::runtime::WorkResult {
-        let msg = self.input.recv()?;
+        let msg = match self.input.recv() {
+            Ok(x) => x,
+            // upstream stages are gone (eg: finalized sync), nothing else will arrive
+            Err(gasket::error::Error::RecvError) => return Ok(WorkOutcome::Done),
+            Err(err) => return Err(err),
+        };
 
         match msg.payload {
-            model::EnrichedBlockPayload::RollForward(block, ctx) => {
-                self.reduce_block(&block, &ctx)?
+            model::EnrichedBlockPayload::RollForward(block, mut ctx) => {
+                // decode referenced outputs once, instead of once per reducer
+                ctx.resolve_outputs(&self.address_hrp);
+                self.reduce_block(&block, &ctx)?;
             }
             model::EnrichedBlockPayload::RollBack(point) => {
                 log::warn!("rollback requested for {:?}", point);
+                self.undo_blocks(&point)?;
             }
         }
 
@@ -11,15 +11,18 @@ use crate::{
 pub mod n2c;
 
 pub mod n2n;
+pub mod replay;
 pub mod utils;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 #[serde(tag = "type")]
 pub enum Config {
     N2N(n2n::Config),
 
     #[cfg(target_family = "unix")]
     N2C(n2c::Config),
+
+    Replay(replay::Config),
 }
 
 impl Config {
@@ -27,10 +30,12 @@ impl Config {
         self,
         chain: &crosscut::ChainWellKnownInfo,
         intersect: &crosscut::IntersectConfig,
+        finalize: &Option<crosscut::FinalizeConfig>,
     ) -> Bootstrapper {
         match self {
-            Config::N2N(c) => Bootstrapper::N2N(c.bootstrapper(chain, intersect)),
-            Config::N2C(c) => Bootstrapper::N2C(c.bootstrapper(chain, intersect)),
+            Config::N2N(c) => Bootstrapper::N2N(c.bootstrapper(chain, intersect, finalize)),
+            Config::N2C(c) => Bootstrapper::N2C(c.bootstrapper(chain, intersect, finalize)),
+            Config::Replay(c) => Bootstrapper::Replay(c.bootstrapper()),
         }
     }
 }
@@ -38,6 +43,7 @@ impl Config {
 pub enum Bootstrapper {
     N2N(n2n::Bootstrapper),
     N2C(n2c::Bootstrapper),
+    Replay(replay::Bootstrapper),
 }
 
 impl Bootstrapper {
@@ -45,6 +51,7 @@ impl Bootstrapper {
         match self {
             Bootstrapper::N2N(p) => p.borrow_output_port(),
             Bootstrapper::N2C(p) => p.borrow_output_port(),
+            Bootstrapper::Replay(p) => p.borrow_output_port(),
         }
     }
 
@@ -52,6 +59,7 @@ impl Bootstrapper {
         match self {
             Bootstrapper::N2N(p) => p.spawn_stages(pipeline, cursor),
             Bootstrapper::N2C(p) => p.spawn_stages(pipeline, cursor),
+            Bootstrapper::Replay(p) => p.spawn_stages(pipeline, cursor),
         }
     }
 }
@@ -1,6 +1,5 @@
 use std::{collections::HashMap, ops::Deref};
 
-use pallas::ledger::traverse::MultiEraBlock;
 use pallas::network::miniprotocols::{self, chainsync, Agent, Point};
 use pallas::network::multiplexer;
 
@@ -9,21 +8,45 @@ use gasket::{
     metrics::{Counter, Gauge},
 };
 
-use crate::{crosscut, model::RawBlockPayload, sources::utils};
+use crate::{
+    crosscut,
+    model::{RawBlock, RawBlockPayload},
+    sources::{
+        replay::{self, Recorder},
+        utils::{self, RecentPoints},
+    },
+};
 
 struct ChainObserver {
     min_depth: usize,
     output: OutputPort,
     chain_buffer: chainsync::RollbackBuffer,
-    blocks: HashMap<Point, Vec<u8>>,
+    blocks: HashMap<Point, RawBlock>,
+    recent_points: RecentPoints,
+    chain: crosscut::ChainWellKnownInfo,
+    finalize: Option<crosscut::FinalizeConfig>,
+    recorder: Option<Recorder>,
     block_count: gasket::metrics::Counter,
     chain_tip: Gauge,
 }
 
 impl ChainObserver {
-    fn new(min_depth: usize, block_count: Counter, chain_tip: Gauge, output: OutputPort) -> Self {
+    fn new(
+        min_depth: usize,
+        recent_points: RecentPoints,
+        chain: crosscut::ChainWellKnownInfo,
+        finalize: Option<crosscut::FinalizeConfig>,
+        recorder: Option<Recorder>,
+        block_count: Counter,
+        chain_tip: Gauge,
+        output: OutputPort,
+    ) -> Self {
         Self {
             min_depth,
+            recent_points,
+            chain,
+            finalize,
+            recorder,
             block_count,
             chain_tip,
             output,
@@ -39,13 +62,13 @@ impl chainsync::Observer<chainsync::BlockContent> for ChainObserver {
         content: chainsync::BlockContent,
         tip: &chainsync::Tip,
     ) -> Result<chainsync::Continuation, Box<dyn std::error::Error>> {
-        // parse the block and extract the point of the chain
-        let cbor = Vec::from(content.deref());
-        let block = MultiEraBlock::decode(&cbor)?;
-        let point = Point::Specific(block.slot(), block.hash().to_vec());
+        // parse the block and extract the point of the chain, this is the only
+        // time the block is decoded before reaching the enrich stage
+        let block = RawBlock::from_cbor(Vec::from(content.deref()))?;
+        let point = block.point.clone();
 
         // store the block for later retrieval
-        self.blocks.insert(point.clone(), cbor);
+        self.blocks.insert(point.clone(), block);
 
         // track the new point in our memory buffer
         log::info!("rolling forward to point {:?}", point);
@@ -57,13 +80,30 @@ impl chainsync::Observer<chainsync::BlockContent> for ChainObserver {
 
         // find confirmed block in memory and send down the pipeline
         for point in ready {
+            if utils::is_beyond_finalize(&self.finalize, &self.chain, &point) {
+                log::info!("point {:?} is beyond finalize bounds, stopping", point);
+                return Ok(chainsync::Continuation::DropOut);
+            }
+
             let block = self
                 .blocks
                 .remove(&point)
                 .expect("required block not found in memory");
 
-            self.output.send(RawBlockPayload::roll_forward(block))?;
+            let payload = RawBlockPayload::roll_forward(block);
+            replay::maybe_record(&self.recorder, &payload.payload)?;
+
+            self.output.send(payload)?;
+            self.recent_points.roll_forward(point.clone());
             self.block_count.inc(1);
+
+            // evaluate if we should finalize the thread according to config
+            let block_count = self.recent_points.emitted_count();
+
+            if utils::should_finalize(&self.finalize, &self.chain, &point, &tip.0, block_count) {
+                log::info!("finalize condition reached at point {:?}", point);
+                return Ok(chainsync::Continuation::DropOut);
+            }
         }
 
         // notify chain tip to the pipeline metrics
@@ -82,10 +122,18 @@ impl chainsync::Observer<chainsync::BlockContent> for ChainObserver {
             chainsync::RollbackEffect::Handled => {
                 log::debug!("handled rollback within buffer {:?}", point);
             }
+            chainsync::RollbackEffect::OutOfScope
+                if self.recent_points.latest().as_ref() == Some(point) =>
+            {
+                log::debug!("rollback to latest emitted point, nothing to undo");
+            }
             chainsync::RollbackEffect::OutOfScope => {
                 log::debug!("rollback out of buffer scope, sending event down the pipeline");
-                self.output
-                    .send(RawBlockPayload::roll_back(point.clone()))?;
+                let payload = RawBlockPayload::roll_back(point.clone());
+                replay::maybe_record(&self.recorder, &payload.payload)?;
+
+                self.output.send(payload)?;
+                self.recent_points.roll_back(point);
             }
         }
 
@@ -97,54 +145,58 @@ type OutputPort = gasket::messaging::OutputPort<RawBlockPayload>;
 type MyAgent = chainsync::BlockConsumer<ChainObserver>;
 
 pub struct Worker {
+    path: String,
     channel: multiplexer::StdChannelBuffer,
     min_depth: usize,
     chain: crosscut::ChainWellKnownInfo,
     intersect: crosscut::IntersectConfig,
     cursor: crosscut::Cursor,
-    //finalize_config: Option<FinalizeConfig>,
+    finalize: Option<crosscut::FinalizeConfig>,
+    recorder: Option<Recorder>,
     agent: Option<MyAgent>,
+    recent_points: RecentPoints,
     output: OutputPort,
     block_count: gasket::metrics::Counter,
     chain_tip: gasket::metrics::Gauge,
+    reconnect_count: gasket::metrics::Counter,
 }
 
 impl Worker {
     pub fn new(
+        path: String,
         channel: multiplexer::StdChannelBuffer,
         min_depth: usize,
         chain: crosscut::ChainWellKnownInfo,
         intersect: crosscut::IntersectConfig,
         cursor: crosscut::Cursor,
+        finalize: Option<crosscut::FinalizeConfig>,
+        recorder: Option<Recorder>,
         output: OutputPort,
     ) -> Self {
         Self {
+            path,
             channel,
             min_depth,
             chain,
             intersect,
             cursor,
+            finalize,
+            recorder,
             output,
             agent: None,
+            recent_points: Default::default(),
             block_count: Default::default(),
             chain_tip: Default::default(),
+            reconnect_count: Default::default(),
         }
     }
-}
 
-impl gasket::runtime::Worker for Worker {
-    fn metrics(&self) -> gasket::metrics::Registry {
-        gasket::metrics::Builder::new()
-            .with_counter("block_count", &self.block_count)
-            .with_gauge("chain_tip", &self.chain_tip)
-            .build()
-    }
-
-    fn bootstrap(&mut self) -> Result<(), gasket::error::Error> {
-        let known_points = utils::define_known_points(
+    fn start_agent(&mut self) -> Result<(), gasket::error::Error> {
+        let known_points = utils::define_resume_points(
             &self.chain,
             &self.intersect,
             &self.cursor,
+            &self.recent_points,
             &mut self.channel,
         )
         .or_work_err()?;
@@ -153,6 +205,10 @@ impl gasket::runtime::Worker for Worker {
             known_points,
             ChainObserver::new(
                 self.min_depth,
+                self.recent_points.clone(),
+                self.chain.clone(),
+                self.finalize.clone(),
+                self.recorder.clone(),
                 self.block_count.clone(),
                 self.chain_tip.clone(),
                 self.output.clone(),
@@ -166,10 +222,39 @@ impl gasket::runtime::Worker for Worker {
         Ok(())
     }
 
+    fn reconnect(&mut self) -> Result<(), gasket::error::Error> {
+        let transport = super::bootstrap_transport(&self.path, self.chain.magic).or_work_err()?;
+        self.channel = transport.channel5;
+        self.reconnect_count.inc(1);
+
+        self.start_agent()
+    }
+}
+
+impl gasket::runtime::Worker for Worker {
+    fn metrics(&self) -> gasket::metrics::Registry {
+        gasket::metrics::Builder::new()
+            .with_counter("block_count", &self.block_count)
+            .with_gauge("chain_tip", &self.chain_tip)
+            .with_counter("reconnect_count", &self.reconnect_count)
+            .build()
+    }
+
+    fn bootstrap(&mut self) -> Result<(), gasket::error::Error> {
+        self.start_agent()
+    }
+
     fn work(&mut self) -> gasket::runtime::WorkResult {
         let agent = self.agent.take().unwrap();
 
-        let agent = miniprotocols::run_agent_step(agent, &mut self.channel).or_work_err()?;
+        let agent = match miniprotocols::run_agent_step(agent, &mut self.channel) {
+            Ok(agent) => agent,
+            Err(err) => {
+                log::warn!("chainsync failed, reconnecting to node: {}", err);
+                self.reconnect()?;
+                return Ok(gasket::runtime::WorkOutcome::Partial);
+            }
+        };
 
         let is_done = agent.is_done();
 
@@ -5,12 +5,15 @@ use serde::Deserialize;
 use std::time::Duration;
 
 use self::transport::Transport;
-use crate::{bootstrap::Pipeline, crosscut, model::RawBlockPayload};
+use crate::{
+    bootstrap::Pipeline, crosscut, model::RawBlockPayload, sources::replay::Recorder,
+};
 use gasket::{error::AsWorkError, messaging::OutputPort, retries};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct Config {
     pub path: String,
+    pub record_to: Option<String>,
 }
 
 impl Config {
@@ -18,10 +21,12 @@ impl Config {
         self,
         chain: &crosscut::ChainWellKnownInfo,
         intersect: &crosscut::IntersectConfig,
+        finalize: &Option<crosscut::FinalizeConfig>,
     ) -> Bootstrapper {
         Bootstrapper {
             config: self,
             intersect: intersect.clone(),
+            finalize: finalize.clone(),
             chain: chain.clone(),
             output: Default::default(),
         }
@@ -31,47 +36,55 @@ impl Config {
 pub struct Bootstrapper {
     config: Config,
     intersect: crosscut::IntersectConfig,
+    finalize: Option<crosscut::FinalizeConfig>,
     chain: crosscut::ChainWellKnownInfo,
     output: OutputPort<RawBlockPayload>,
 }
 
-impl Bootstrapper {
-    fn bootstrap_transport(&self) -> Result<Transport, crate::Error> {
-        gasket::retries::retry_operation(
-            || Transport::setup(&self.config.path, self.chain.magic).or_work_err(),
-            &retries::Policy {
-                max_retries: 5,
-                backoff_factor: 2,
-                backoff_unit: Duration::from_secs(1),
-                max_backoff: Duration::from_secs(60),
-            },
-            None,
-        )
-        .map_err(crate::Error::source)
-    }
+/// Connects to the local node, retrying with backoff if it's unavailable
+fn bootstrap_transport(path: &str, magic: u64) -> Result<Transport, crate::Error> {
+    gasket::retries::retry_operation(
+        || Transport::setup(path, magic).or_work_err(),
+        &retries::Policy {
+            max_retries: 5,
+            backoff_factor: 2,
+            backoff_unit: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        },
+        None,
+    )
+    .map_err(crate::Error::source)
+}
 
+impl Bootstrapper {
     pub fn borrow_output_port(&mut self) -> &'_ mut OutputPort<RawBlockPayload> {
         &mut self.output
     }
 
     pub fn spawn_stages(self, pipeline: &mut Pipeline, cursor: &Option<crosscut::PointArg>) {
-        let transport = self
-            .bootstrap_transport()
+        let transport = bootstrap_transport(&self.config.path, self.chain.magic)
             .expect("transport should be connected after several retries");
 
+        let recorder = self
+            .config
+            .record_to
+            .as_deref()
+            .map(|path| Recorder::create(path).expect("recording file should be writable"));
+
         pipeline.register_stage(
             "n2c",
-            gasket::runtime::spawn_stage(
-                self::chainsync::Worker::new(
-                    transport.channel5,
-                    0,
-                    self.chain,
-                    self.intersect,
-                    cursor.clone(),
-                    self.output,
-                ),
-                gasket::runtime::Policy::default(),
+            self::chainsync::Worker::new(
+                self.config.path,
+                transport.channel5,
+                0,
+                self.chain,
+                self.intersect,
+                cursor.clone(),
+                self.finalize,
+                recorder,
+                self.output,
             ),
+            gasket::runtime::Policy::default(),
         );
     }
 }
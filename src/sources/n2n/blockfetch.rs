@@ -5,16 +5,28 @@ use pallas::network::{
 
 use gasket::{error::*, runtime::WorkOutcome};
 
-use super::ChainSyncInternalPayload;
-use crate::model::RawBlockPayload;
+use super::{ChainSyncInternalPayload, Protocol, SharedTransport};
+use crate::{
+    model::{RawBlock, RawBlockPayload},
+    sources::replay::{self, Recorder},
+};
 
 struct Observer<'a> {
+    point: &'a Point,
     output: &'a mut OutputPort,
+    recorder: &'a Option<Recorder>,
+    block_sent: &'a mut bool,
 }
 
 impl<'a> blockfetch::Observer for Observer<'a> {
     fn on_block_received(&mut self, body: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
-        self.output.send(RawBlockPayload::roll_forward(body))?;
+        // we only request one block at a time, so we already know its point
+        let block = RawBlock::new(self.point.clone(), body);
+        let payload = RawBlockPayload::roll_forward(block);
+        replay::maybe_record(self.recorder, &payload.payload)?;
+
+        self.output.send(payload)?;
+        *self.block_sent = true;
 
         Ok(())
     }
@@ -24,58 +36,106 @@ pub type InputPort = gasket::messaging::InputPort<ChainSyncInternalPayload>;
 pub type OutputPort = gasket::messaging::OutputPort<RawBlockPayload>;
 
 pub struct Worker {
+    transport: SharedTransport,
+    generation: u64,
     channel: multiplexer::StdChannelBuffer,
+    recorder: Option<Recorder>,
+    block_sent: bool,
     block_count: gasket::metrics::Counter,
+    reconnect_count: gasket::metrics::Counter,
     input: InputPort,
     output: OutputPort,
 }
 
 impl Worker {
     pub fn new(
+        transport: SharedTransport,
         channel: multiplexer::StdChannelBuffer,
+        recorder: Option<Recorder>,
         input: InputPort,
         output: OutputPort,
     ) -> Self {
         Self {
+            transport,
+            generation: 0,
             channel,
+            recorder,
             input,
             output,
+            block_sent: false,
             block_count: Default::default(),
+            reconnect_count: Default::default(),
         }
     }
 
-    fn fetch_block(&mut self, point: Point) -> Result<(), Error> {
+    fn fetch_block(&mut self, point: &Point) -> Result<(), crate::Error> {
         log::debug!("initiating chainsync");
 
+        self.block_sent = false;
+
         let observer = Observer {
+            point,
             output: &mut self.output,
+            recorder: &self.recorder,
+            block_sent: &mut self.block_sent,
         };
 
-        let agent = blockfetch::BatchClient::initial((point.clone(), point), observer);
-        run_agent(agent, &mut self.channel).or_work_err()?;
+        let agent = blockfetch::BatchClient::initial((point.clone(), point.clone()), observer);
+
+        run_agent(agent, &mut self.channel)
+            .map(|_| ())
+            .map_err(crate::Error::ouroboros)?;
 
         self.block_count.inc(1);
 
         Ok(())
     }
+
+    fn reconnect(&mut self) -> Result<(), crate::Error> {
+        self.channel = self
+            .transport
+            .reconnect(Protocol::BlockFetch, &mut self.generation)?;
+
+        self.reconnect_count.inc(1);
+
+        Ok(())
+    }
 }
 
 impl gasket::runtime::Worker for Worker {
     fn metrics(&self) -> gasket::metrics::Registry {
         gasket::metrics::Builder::new()
             .with_counter("block_count", &self.block_count)
+            .with_counter("reconnect_count", &self.reconnect_count)
             .build()
     }
 
     fn work(&mut self) -> gasket::runtime::WorkResult {
-        let input = self.input.recv()?;
+        let input = match self.input.recv() {
+            Ok(x) => x,
+            // upstream stages are gone (eg: finalized sync), nothing else will arrive
+            Err(gasket::error::Error::RecvError) => return Ok(WorkOutcome::Done),
+            Err(err) => return Err(err),
+        };
 
         match input.payload {
             ChainSyncInternalPayload::RollForward(point) => {
-                self.fetch_block(point).or_work_err()?;
+                if let Err(err) = self.fetch_block(&point) {
+                    log::warn!("block fetch failed, reconnecting to upstream: {}", err);
+                    self.reconnect().or_work_err()?;
+
+                    // the block might have reached the pipeline before the failure, we
+                    // only retry if that's not the case to avoid duplicates
+                    if !self.block_sent {
+                        self.fetch_block(&point).or_work_err()?;
+                    }
+                }
             }
             ChainSyncInternalPayload::RollBack(point) => {
-                self.output.send(RawBlockPayload::roll_back(point))?;
+                let payload = RawBlockPayload::roll_back(point);
+                replay::maybe_record(&self.recorder, &payload.payload).or_work_err()?;
+
+                self.output.send(payload)?;
             }
         };
 
@@ -8,9 +8,12 @@ use gasket::{
     metrics::{Counter, Gauge},
 };
 
-use super::ChainSyncInternalPayload;
+use super::{ChainSyncInternalPayload, Protocol, SharedTransport};
 use crate::Error;
-use crate::{crosscut, sources::utils};
+use crate::{
+    crosscut,
+    sources::utils::{self, RecentPoints},
+};
 
 fn to_traverse<'b>(header: &'b HeaderContent) -> Result<MultiEraHeader<'b>, Error> {
     MultiEraHeader::decode(
@@ -25,6 +28,9 @@ struct ChainObserver {
     min_depth: usize,
     output: gasket::messaging::OutputPort<ChainSyncInternalPayload>,
     chain_buffer: chainsync::RollbackBuffer,
+    recent_points: RecentPoints,
+    chain: crosscut::ChainWellKnownInfo,
+    finalize: Option<crosscut::FinalizeConfig>,
     block_count: gasket::metrics::Counter,
     chain_tip: gasket::metrics::Gauge,
 }
@@ -32,12 +38,18 @@ struct ChainObserver {
 impl ChainObserver {
     fn new(
         min_depth: usize,
+        recent_points: RecentPoints,
+        chain: crosscut::ChainWellKnownInfo,
+        finalize: Option<crosscut::FinalizeConfig>,
         block_count: Counter,
         chain_tip: Gauge,
         output: gasket::messaging::OutputPort<ChainSyncInternalPayload>,
     ) -> Self {
         Self {
             min_depth,
+            recent_points,
+            chain,
+            finalize,
             block_count,
             chain_tip,
             output,
@@ -66,16 +78,24 @@ impl chainsync::Observer<chainsync::HeaderContent> for ChainObserver {
 
         // request download of blocks for confirmed points
         for point in ready {
+            if utils::is_beyond_finalize(&self.finalize, &self.chain, &point) {
+                log::info!("point {:?} is beyond finalize bounds, stopping", point);
+                return Ok(chainsync::Continuation::DropOut);
+            }
+
             log::debug!("requesting block fetch for point {:?}", point);
             self.output
                 .send(ChainSyncInternalPayload::roll_forward(point.clone()))?;
+            self.recent_points.roll_forward(point.clone());
             self.block_count.inc(1);
 
             // evaluate if we should finalize the thread according to config
-            //if should_finalize(&self.finalize_config, &point,
-            // self.block_count) {    return Ok(chainsync::
-            // Continuation::DropOut);
-            //}
+            let block_count = self.recent_points.emitted_count();
+
+            if utils::should_finalize(&self.finalize, &self.chain, &point, &tip.0, block_count) {
+                log::info!("finalize condition reached at point {:?}", point);
+                return Ok(chainsync::Continuation::DropOut);
+            }
         }
 
         // notify chain tip to the pipeline metrics
@@ -94,10 +114,16 @@ impl chainsync::Observer<chainsync::HeaderContent> for ChainObserver {
             chainsync::RollbackEffect::Handled => {
                 log::debug!("handled rollback within buffer {:?}", point);
             }
+            chainsync::RollbackEffect::OutOfScope
+                if self.recent_points.latest().as_ref() == Some(point) =>
+            {
+                log::debug!("rollback to latest emitted point, nothing to undo");
+            }
             chainsync::RollbackEffect::OutOfScope => {
                 log::debug!("rollback out of buffer scope, sending event down the pipeline");
                 self.output
                     .send(ChainSyncInternalPayload::roll_back(point.clone()))?;
+                self.recent_points.roll_back(point);
             }
         }
 
@@ -108,54 +134,57 @@ impl chainsync::Observer<chainsync::HeaderContent> for ChainObserver {
 type OutputPort = gasket::messaging::OutputPort<ChainSyncInternalPayload>;
 
 pub struct Worker {
+    transport: SharedTransport,
+    generation: u64,
     channel: multiplexer::StdChannelBuffer,
     min_depth: usize,
     chain: crosscut::ChainWellKnownInfo,
     intersect: crosscut::IntersectConfig,
     cursor: Option<crosscut::PointArg>,
-    //finalize_config: Option<FinalizeConfig>,
+    finalize: Option<crosscut::FinalizeConfig>,
     agent: Option<chainsync::HeaderConsumer<ChainObserver>>,
+    recent_points: RecentPoints,
     output: OutputPort,
     block_count: gasket::metrics::Counter,
     chain_tip: gasket::metrics::Gauge,
+    reconnect_count: gasket::metrics::Counter,
 }
 
 impl Worker {
     pub fn new(
+        transport: SharedTransport,
         channel: multiplexer::StdChannelBuffer,
         min_depth: usize,
         chain: crosscut::ChainWellKnownInfo,
         intersect: crosscut::IntersectConfig,
         cursor: Option<crosscut::PointArg>,
+        finalize: Option<crosscut::FinalizeConfig>,
         output: OutputPort,
     ) -> Self {
         Self {
+            transport,
+            generation: 0,
             channel,
             min_depth,
             chain,
             intersect,
             cursor,
+            finalize,
             output,
             agent: None,
+            recent_points: Default::default(),
             block_count: Default::default(),
             chain_tip: Default::default(),
+            reconnect_count: Default::default(),
         }
     }
-}
-
-impl gasket::runtime::Worker for Worker {
-    fn metrics(&self) -> gasket::metrics::Registry {
-        gasket::metrics::Builder::new()
-            .with_counter("block_count", &self.block_count)
-            .with_gauge("chain_tip", &self.chain_tip)
-            .build()
-    }
 
-    fn bootstrap(&mut self) -> Result<(), gasket::error::Error> {
-        let known_points = utils::define_known_points(
+    fn start_agent(&mut self) -> Result<(), gasket::error::Error> {
+        let known_points = utils::define_resume_points(
             &self.chain,
             &self.intersect,
             &self.cursor,
+            &self.recent_points,
             &mut self.channel,
         )
         .or_work_err()?;
@@ -166,6 +195,9 @@ impl gasket::runtime::Worker for Worker {
             known_points,
             ChainObserver::new(
                 self.min_depth,
+                self.recent_points.clone(),
+                self.chain.clone(),
+                self.finalize.clone(),
                 self.block_count.clone(),
                 self.chain_tip.clone(),
                 self.output.clone(),
@@ -179,10 +211,42 @@ impl gasket::runtime::Worker for Worker {
         Ok(())
     }
 
+    fn reconnect(&mut self) -> Result<(), gasket::error::Error> {
+        self.channel = self
+            .transport
+            .reconnect(Protocol::ChainSync, &mut self.generation)
+            .or_work_err()?;
+
+        self.reconnect_count.inc(1);
+
+        self.start_agent()
+    }
+}
+
+impl gasket::runtime::Worker for Worker {
+    fn metrics(&self) -> gasket::metrics::Registry {
+        gasket::metrics::Builder::new()
+            .with_counter("block_count", &self.block_count)
+            .with_gauge("chain_tip", &self.chain_tip)
+            .with_counter("reconnect_count", &self.reconnect_count)
+            .build()
+    }
+
+    fn bootstrap(&mut self) -> Result<(), gasket::error::Error> {
+        self.start_agent()
+    }
+
     fn work(&mut self) -> gasket::runtime::WorkResult {
         let agent = self.agent.take().unwrap();
 
-        let agent = miniprotocols::run_agent_step(agent, &mut self.channel).or_work_err()?;
+        let agent = match miniprotocols::run_agent_step(agent, &mut self.channel) {
+            Ok(agent) => agent,
+            Err(err) => {
+                log::warn!("chainsync failed, reconnecting to upstream: {}", err);
+                self.reconnect()?;
+                return Ok(gasket::runtime::WorkOutcome::Partial);
+            }
+        };
 
         let is_done = agent.is_done();
 
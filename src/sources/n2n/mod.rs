@@ -2,7 +2,10 @@ pub mod blockfetch;
 pub mod chainsync;
 mod transport;
 
-use std::time::Duration;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use gasket::{
     error::AsWorkError,
@@ -10,10 +13,12 @@ use gasket::{
     retries,
 };
 
-use pallas::network::miniprotocols::Point;
+use pallas::network::{miniprotocols::Point, multiplexer::StdChannelBuffer};
 use serde::Deserialize;
 
-use crate::{bootstrap::Pipeline, crosscut, model::RawBlockPayload};
+use crate::{
+    bootstrap::Pipeline, crosscut, model::RawBlockPayload, sources::replay::Recorder,
+};
 
 use self::transport::Transport;
 
@@ -37,9 +42,10 @@ impl ChainSyncInternalPayload {
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct Config {
     pub address: String,
+    pub record_to: Option<String>,
 }
 
 impl Config {
@@ -47,10 +53,12 @@ impl Config {
         self,
         chain: &crosscut::ChainWellKnownInfo,
         intersect: &crosscut::IntersectConfig,
+        finalize: &Option<crosscut::FinalizeConfig>,
     ) -> Bootstrapper {
         Bootstrapper {
             config: self,
             intersect: intersect.clone(),
+            finalize: finalize.clone(),
             chain: chain.clone(),
             output: Default::default(),
         }
@@ -60,33 +68,128 @@ impl Config {
 pub struct Bootstrapper {
     config: Config,
     intersect: crosscut::IntersectConfig,
+    finalize: Option<crosscut::FinalizeConfig>,
     chain: crosscut::ChainWellKnownInfo,
     output: OutputPort<RawBlockPayload>,
 }
 
-impl Bootstrapper {
-    fn bootstrap_transport(&self) -> Result<Transport, crate::Error> {
-        gasket::retries::retry_operation(
-            || Transport::setup(&self.config.address, self.chain.magic).or_work_err(),
-            &retries::Policy {
-                max_retries: 5,
-                backoff_factor: 2,
-                backoff_unit: Duration::from_secs(1),
-                max_backoff: Duration::from_secs(60),
-            },
-            None,
-        )
-        .map_err(crate::Error::source)
+/// Connects to the upstream node, retrying with backoff if it's unavailable
+fn bootstrap_transport(address: &str, magic: u64) -> Result<Transport, crate::Error> {
+    gasket::retries::retry_operation(
+        || Transport::setup(address, magic).or_work_err(),
+        &retries::Policy {
+            max_retries: 5,
+            backoff_factor: 2,
+            backoff_unit: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        },
+        None,
+    )
+    .map_err(crate::Error::source)
+}
+
+/// The mini-protocols of the n2n source, each one running on its own stage
+#[derive(Clone, Copy)]
+pub enum Protocol {
+    ChainSync,
+    BlockFetch,
+}
+
+struct SharedTransportState {
+    generation: u64,
+    chainsync: Option<StdChannelBuffer>,
+    blockfetch: Option<StdChannelBuffer>,
+}
+
+/// Reconnect path shared by the headers and blocks stages
+///
+/// Both stages use channels of the same bearer. When any of them fails, a new
+/// transport is set up once and each stage takes its own channel from it, the
+/// generation tells a stage if the other one already reconnected.
+#[derive(Clone)]
+pub struct SharedTransport {
+    address: String,
+    magic: u64,
+    state: Arc<Mutex<SharedTransportState>>,
+}
+
+impl SharedTransport {
+    /// Connects to the upstream node, returning the initial chainsync and
+    /// blockfetch channels, which belong to generation zero
+    fn connect(
+        address: &str,
+        magic: u64,
+    ) -> Result<(Self, StdChannelBuffer, StdChannelBuffer), crate::Error> {
+        let transport = bootstrap_transport(address, magic)?;
+
+        let shared = Self {
+            address: address.to_string(),
+            magic,
+            state: Arc::new(Mutex::new(SharedTransportState {
+                generation: 0,
+                chainsync: None,
+                blockfetch: None,
+            })),
+        };
+
+        Ok((shared, transport.channel2, transport.channel3))
+    }
+
+    /// Provides a new channel for a protocol whose current one failed
+    pub fn reconnect(
+        &self,
+        protocol: Protocol,
+        generation: &mut u64,
+    ) -> Result<StdChannelBuffer, crate::Error> {
+        let mut state = self.state.lock().expect("shared transport lock");
+
+        if state.generation > *generation {
+            let pending = match protocol {
+                Protocol::ChainSync => state.chainsync.take(),
+                Protocol::BlockFetch => state.blockfetch.take(),
+            };
+
+            if let Some(channel) = pending {
+                log::info!("reusing transport set up by the other n2n stage");
+                *generation = state.generation;
+                return Ok(channel);
+            }
+        }
+
+        let transport = bootstrap_transport(&self.address, self.magic)?;
+        state.generation += 1;
+        *generation = state.generation;
+
+        match protocol {
+            Protocol::ChainSync => {
+                state.chainsync = None;
+                state.blockfetch = Some(transport.channel3);
+                Ok(transport.channel2)
+            }
+            Protocol::BlockFetch => {
+                state.chainsync = Some(transport.channel2);
+                state.blockfetch = None;
+                Ok(transport.channel3)
+            }
+        }
     }
+}
 
+impl Bootstrapper {
     pub fn borrow_output_port(&mut self) -> &'_ mut OutputPort<RawBlockPayload> {
         &mut self.output
     }
 
     pub fn spawn_stages(self, pipeline: &mut Pipeline, cursor: &Option<crosscut::PointArg>) {
-        let transport = self
-            .bootstrap_transport()
-            .expect("transport should be connected after several retries");
+        let (transport, channel2, channel3) =
+            SharedTransport::connect(&self.config.address, self.chain.magic)
+                .expect("transport should be connected after several retries");
+
+        let recorder = self
+            .config
+            .record_to
+            .as_deref()
+            .map(|path| Recorder::create(path).expect("recording file should be writable"));
 
         let mut headers_out = OutputPort::<ChainSyncInternalPayload>::default();
         let mut headers_in = InputPort::<ChainSyncInternalPayload>::default();
@@ -94,25 +197,23 @@ impl Bootstrapper {
 
         pipeline.register_stage(
             "n2n-headers",
-            gasket::runtime::spawn_stage(
-                self::chainsync::Worker::new(
-                    transport.channel2,
-                    0,
-                    self.chain,
-                    self.intersect,
-                    cursor.clone(),
-                    headers_out,
-                ),
-                gasket::runtime::Policy::default(),
+            self::chainsync::Worker::new(
+                transport.clone(),
+                channel2,
+                0,
+                self.chain.clone(),
+                self.intersect,
+                cursor.clone(),
+                self.finalize,
+                headers_out,
             ),
+            gasket::runtime::Policy::default(),
         );
 
         pipeline.register_stage(
             "n2n-blocks",
-            gasket::runtime::spawn_stage(
-                self::blockfetch::Worker::new(transport.channel3, headers_in, self.output),
-                gasket::runtime::Policy::default(),
-            ),
+            self::blockfetch::Worker::new(transport, channel3, recorder, headers_in, self.output),
+            gasket::runtime::Policy::default(),
         );
     }
 }
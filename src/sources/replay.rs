@@ -0,0 +1,242 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    sync::{Arc, Mutex},
+};
+
+use gasket::{error::AsWorkError, messaging::OutputPort, runtime::WorkOutcome};
+
+use pallas::{codec::minicbor, network::miniprotocols::Point};
+use serde::Deserialize;
+
+use crate::{
+    bootstrap::Pipeline,
+    crosscut,
+    model::{RawBlock, RawBlockPayload},
+};
+
+// Recordings are a sequence of length-prefixed records, each one a CBOR array
+// with a tag followed by the payload data:
+//
+// - roll forward: [0, block_cbor]
+// - roll back to origin: [1]
+// - roll back to specific point: [2, slot, hash]
+
+fn encode_record(payload: &RawBlockPayload) -> Result<Vec<u8>, crate::Error> {
+    let mut encoder = minicbor::Encoder::new(Vec::new());
+
+    let result = match payload {
+        RawBlockPayload::RollForward(block) => encoder
+            .array(2)
+            .and_then(|e| e.u8(0))
+            .and_then(|e| e.bytes(&block.cbor)),
+        RawBlockPayload::RollBack(Point::Origin) => encoder.array(1).and_then(|e| e.u8(1)),
+        RawBlockPayload::RollBack(Point::Specific(slot, hash)) => encoder
+            .array(3)
+            .and_then(|e| e.u8(2))
+            .and_then(|e| e.u64(*slot))
+            .and_then(|e| e.bytes(hash)),
+    };
+
+    result.map_err(crate::Error::cbor)?;
+
+    Ok(encoder.into_writer())
+}
+
+fn decode_record(record: &[u8]) -> Result<RawBlockPayload, crate::Error> {
+    let mut decoder = minicbor::Decoder::new(record);
+
+    decoder.array().map_err(crate::Error::cbor)?;
+
+    match decoder.u8().map_err(crate::Error::cbor)? {
+        0 => {
+            let cbor = decoder.bytes().map_err(crate::Error::cbor)?;
+            let block = RawBlock::from_cbor(cbor.to_vec())?;
+            Ok(RawBlockPayload::RollForward(Arc::new(block)))
+        }
+        1 => Ok(RawBlockPayload::RollBack(Point::Origin)),
+        2 => {
+            let slot = decoder.u64().map_err(crate::Error::cbor)?;
+            let hash = decoder.bytes().map_err(crate::Error::cbor)?;
+            Ok(RawBlockPayload::RollBack(Point::Specific(
+                slot,
+                hash.to_vec(),
+            )))
+        }
+        x => Err(crate::Error::cbor(format!("unknown record tag {}", x))),
+    }
+}
+
+/// Writes every payload emitted by a source into a file
+///
+/// The handle is cheap to clone so that it can be shared with the chainsync
+/// observers that emit the payloads. Records are appended to any existing
+/// content so that a restarted source keeps extending the same recording.
+#[derive(Clone)]
+pub struct Recorder(Arc<Mutex<BufWriter<File>>>);
+
+impl Recorder {
+    pub fn create(path: &str) -> Result<Self, crate::Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(crate::Error::source)?;
+        let writer = BufWriter::new(file);
+
+        Ok(Self(Arc::new(Mutex::new(writer))))
+    }
+
+    pub fn record(&self, payload: &RawBlockPayload) -> Result<(), crate::Error> {
+        let record = encode_record(payload)?;
+        let mut writer = self.0.lock().expect("recorder lock");
+
+        writer
+            .write_all(&(record.len() as u32).to_be_bytes())
+            .and_then(|_| writer.write_all(&record))
+            .and_then(|_| writer.flush())
+            .map_err(crate::Error::source)
+    }
+}
+
+/// Records a payload if the source was configured to do so
+pub fn maybe_record(
+    recorder: &Option<Recorder>,
+    payload: &RawBlockPayload,
+) -> Result<(), crate::Error> {
+    match recorder {
+        Some(recorder) => recorder.record(payload),
+        None => Ok(()),
+    }
+}
+
+/// Plays back a recording exactly as it was recorded, rollbacks included
+#[derive(Deserialize, Clone)]
+pub struct Config {
+    pub path: String,
+
+    /// Skips the records up to the block of the storage cursor, for resuming
+    /// an interrupted playback instead of starting over
+    pub resume_from_cursor: Option<bool>,
+}
+
+impl Config {
+    pub fn bootstrapper(self) -> Bootstrapper {
+        Bootstrapper {
+            config: self,
+            output: Default::default(),
+        }
+    }
+}
+
+pub struct Bootstrapper {
+    config: Config,
+    output: OutputPort<RawBlockPayload>,
+}
+
+impl Bootstrapper {
+    pub fn borrow_output_port(&mut self) -> &'_ mut OutputPort<RawBlockPayload> {
+        &mut self.output
+    }
+
+    pub fn spawn_stages(self, pipeline: &mut Pipeline, cursor: &crosscut::Cursor) {
+        let worker = Worker {
+            config: self.config,
+            cursor: cursor.clone(),
+            reader: None,
+            output: self.output,
+            block_count: Default::default(),
+        };
+
+        pipeline.register_stage("replay", worker, Default::default());
+    }
+}
+
+pub struct Worker {
+    config: Config,
+    /// Point persisted by storage, records up to (and including) the block of
+    /// this point are skipped when resuming is enabled
+    cursor: crosscut::Cursor,
+    reader: Option<BufReader<File>>,
+    output: OutputPort<RawBlockPayload>,
+    block_count: gasket::metrics::Counter,
+}
+
+impl Worker {
+    fn read_record(&mut self) -> Result<Option<RawBlockPayload>, crate::Error> {
+        let reader = self.reader.as_mut().unwrap();
+
+        let mut len = [0u8; 4];
+
+        match reader.read_exact(&mut len) {
+            Ok(_) => (),
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(crate::Error::source(err)),
+        };
+
+        let mut record = vec![0u8; u32::from_be_bytes(len) as usize];
+        reader
+            .read_exact(&mut record)
+            .map_err(crate::Error::source)?;
+
+        decode_record(&record).map(Some)
+    }
+
+    /// Reads records until the block of the resume point is found
+    fn skip_to_cursor(&mut self, point: &Point) -> Result<(), crate::Error> {
+        while let Some(payload) = self.read_record()? {
+            if let RawBlockPayload::RollForward(block) = payload {
+                if &block.point == point {
+                    log::info!("replay resuming after point {:?}", point);
+                    return Ok(());
+                }
+            }
+        }
+
+        Err(crate::Error::source(format!(
+            "cursor point {:?} not found in recording",
+            point
+        )))
+    }
+}
+
+impl gasket::runtime::Worker for Worker {
+    fn metrics(&self) -> gasket::metrics::Registry {
+        gasket::metrics::Builder::new()
+            .with_counter("block_count", &self.block_count)
+            .build()
+    }
+
+    fn bootstrap(&mut self) -> Result<(), gasket::error::Error> {
+        let file = File::open(&self.config.path).or_work_err()?;
+        self.reader = Some(BufReader::new(file));
+
+        let resume = self.config.resume_from_cursor.unwrap_or(false);
+
+        if let (true, Some(crosscut::PointArg::Specific(..))) = (resume, &self.cursor) {
+            let point: Point = self.cursor.clone().unwrap().try_into().or_work_err()?;
+            self.skip_to_cursor(&point).or_work_err()?;
+        }
+
+        Ok(())
+    }
+
+    fn work(&mut self) -> gasket::runtime::WorkResult {
+        let payload = match self.read_record().or_work_err()? {
+            Some(x) => x,
+            None => {
+                log::info!("end of recording reached");
+                return Ok(WorkOutcome::Done);
+            }
+        };
+
+        if let RawBlockPayload::RollForward(_) = &payload {
+            self.block_count.inc(1);
+        }
+
+        self.output
+            .send(gasket::messaging::Message::from(payload))?;
+
+        Ok(WorkOutcome::Partial)
+    }
+}
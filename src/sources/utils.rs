@@ -1,3 +1,8 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
 use pallas::network::{
     miniprotocols::{chainsync::TipFinder, run_agent, Point},
     multiplexer::StdChannelBuffer,
@@ -5,6 +10,76 @@ use pallas::network::{
 
 use crate::crosscut;
 
+/// Max number of emitted points to remember for re-intersection purposes
+const MAX_RECENT_POINTS: usize = 20;
+
+#[derive(Default)]
+struct RecentPointsState {
+    points: VecDeque<Point>,
+    emitted_count: u64,
+}
+
+/// Keeps track of the latest points that a source sent down the pipeline
+///
+/// When the connection to the upstream node needs to be re-established, these
+/// points are used to find an intersection so that the sync resumes exactly
+/// where it left off. The handle is cheap to clone and shared between the
+/// worker and its chainsync observer.
+#[derive(Clone, Default)]
+pub struct RecentPoints(Arc<Mutex<RecentPointsState>>);
+
+impl RecentPoints {
+    pub fn roll_forward(&self, point: Point) {
+        let mut state = self.0.lock().expect("recent points lock");
+
+        state.points.push_front(point);
+        state.points.truncate(MAX_RECENT_POINTS);
+        state.emitted_count += 1;
+    }
+
+    pub fn roll_back(&self, point: &Point) {
+        let mut state = self.0.lock().expect("recent points lock");
+
+        while let Some(latest) = state.points.front() {
+            if latest == point {
+                break;
+            }
+
+            state.points.pop_front();
+        }
+
+        // the target might be older than every tracked point, we keep it as the
+        // resume point instead of falling back to the startup cursor, which would
+        // replay blocks that were already emitted
+        if state.points.is_empty() {
+            state.points.push_front(point.clone());
+        }
+    }
+
+    pub fn latest(&self) -> Option<Point> {
+        let state = self.0.lock().expect("recent points lock");
+        state.points.front().cloned()
+    }
+
+    /// Total number of blocks emitted since the source started, regardless of
+    /// reconnections
+    pub fn emitted_count(&self) -> u64 {
+        let state = self.0.lock().expect("recent points lock");
+        state.emitted_count
+    }
+
+    /// Returns the tracked points, most recent first, or None if nothing has
+    /// been emitted yet
+    pub fn intersect_candidates(&self) -> Option<Vec<Point>> {
+        let state = self.0.lock().expect("recent points lock");
+
+        match state.points.is_empty() {
+            true => None,
+            false => Some(state.points.iter().cloned().collect()),
+        }
+    }
+}
+
 pub fn find_end_of_chain(
     chain: &crosscut::ChainWellKnownInfo,
     channel: &mut StdChannelBuffer,
@@ -54,3 +129,108 @@ pub fn define_known_points(
         }
     }
 }
+
+/// Defines the points to intersect when (re)starting a chainsync session
+///
+/// If the source already emitted blocks, we resume from those. Otherwise, we
+/// fallback to the storage cursor or the intersect config.
+pub fn define_resume_points(
+    chain: &crosscut::ChainWellKnownInfo,
+    intersect: &crosscut::IntersectConfig,
+    cursor: &crosscut::Cursor,
+    recent: &RecentPoints,
+    channel: &mut StdChannelBuffer,
+) -> Result<Option<Vec<Point>>, crate::Error> {
+    match recent.intersect_candidates() {
+        Some(points) => {
+            log::info!("resuming chainsync from recently emitted points");
+            Ok(Some(points))
+        }
+        None => define_known_points(chain, intersect, cursor, channel),
+    }
+}
+
+/// Evaluates if a point lies past the configured finalize bounds
+pub fn is_beyond_finalize(
+    config: &Option<crosscut::FinalizeConfig>,
+    chain: &crosscut::ChainWellKnownInfo,
+    point: &Point,
+) -> bool {
+    match config {
+        Some(config) => config.is_beyond(chain, point),
+        None => false,
+    }
+}
+
+/// Evaluates if the source should stop after emitting a point
+pub fn should_finalize(
+    config: &Option<crosscut::FinalizeConfig>,
+    chain: &crosscut::ChainWellKnownInfo,
+    point: &Point,
+    tip: &Point,
+    block_count: u64,
+) -> bool {
+    match config {
+        Some(config) => config.is_last(chain, point, tip, block_count),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(slot: u64) -> Point {
+        Point::Specific(slot, slot.to_be_bytes().to_vec())
+    }
+
+    #[test]
+    fn recent_points_keep_latest_first() {
+        let recent = RecentPoints::default();
+        assert_eq!(recent.intersect_candidates(), None);
+
+        for slot in 0..30 {
+            recent.roll_forward(point(slot));
+        }
+
+        let candidates = recent.intersect_candidates().unwrap();
+        assert_eq!(candidates.len(), MAX_RECENT_POINTS);
+        assert_eq!(candidates.first(), Some(&point(29)));
+        assert_eq!(candidates.last(), Some(&point(10)));
+        assert_eq!(recent.latest(), Some(point(29)));
+        assert_eq!(recent.emitted_count(), 30);
+    }
+
+    #[test]
+    fn recent_points_roll_back_to_tracked_point() {
+        let recent = RecentPoints::default();
+
+        for slot in 0..5 {
+            recent.roll_forward(point(slot));
+        }
+
+        recent.roll_back(&point(2));
+
+        assert_eq!(
+            recent.intersect_candidates(),
+            Some(vec![point(2), point(1), point(0)])
+        );
+
+        // rollbacks don't change the amount of emitted blocks
+        assert_eq!(recent.emitted_count(), 5);
+    }
+
+    #[test]
+    fn recent_points_keep_untracked_rollback_target() {
+        let recent = RecentPoints::default();
+
+        for slot in 100..130 {
+            recent.roll_forward(point(slot));
+        }
+
+        recent.roll_back(&point(50));
+
+        assert_eq!(recent.intersect_candidates(), Some(vec![point(50)]));
+        assert_eq!(recent.latest(), Some(point(50)));
+    }
+}
@@ -5,13 +5,30 @@ use serde::Deserialize;
 
 use crate::{bootstrap, crosscut, model};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 #[serde(tag = "type")]
 pub enum Config {
     Redis(redis::Config),
 }
 
 impl Config {
+    /// Overrides the key where the cursor is persisted, used to keep track of
+    /// several pipelines writing into the same storage
+    pub fn with_cursor_key(self, key: String) -> Self {
+        match self {
+            Config::Redis(c) => Config::Redis(redis::Config {
+                cursor_key: Some(key),
+                ..c
+            }),
+        }
+    }
+
+    pub fn cursor_key(&self) -> &str {
+        match self {
+            Config::Redis(c) => c.cursor_key(),
+        }
+    }
+
     pub fn plugin(
         self,
         chain: &crosscut::ChainWellKnownInfo,
@@ -40,6 +57,25 @@ impl Bootstrapper {
         }
     }
 
+    /// Persists the point from where the pipeline will continue, used when the
+    /// state was seeded from outside of the pipeline (eg: utxo dumps)
+    pub fn write_cursor(&mut self, point: &crosscut::PointArg) -> Result<(), crate::Error> {
+        match self {
+            Bootstrapper::Redis(x) => x.write_cursor(point),
+        }
+    }
+
+    /// Reads data persisted by a previous run, used by reducers that need to
+    /// rebuild their in-memory state before resuming
+    pub fn read_state(
+        &mut self,
+        query: &model::StateQuery,
+    ) -> Result<model::StateData, crate::Error> {
+        match self {
+            Bootstrapper::Redis(x) => x.read_state(query),
+        }
+    }
+
     pub fn spawn_stages(self, pipeline: &mut bootstrap::Pipeline) {
         match self {
             Bootstrapper::Redis(x) => x.spawn_stages(pipeline),
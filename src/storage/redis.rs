@@ -1,10 +1,8 @@
-use std::str::FromStr;
+use std::{collections::HashSet, str::FromStr};
 
-use gasket::{
-    error::AsWorkError,
-    runtime::{spawn_stage, WorkOutcome},
-};
+use gasket::{error::AsWorkError, runtime::WorkOutcome};
 
+use pallas::network::miniprotocols::Point;
 use redis::Commands;
 use serde::Deserialize;
 
@@ -15,15 +13,24 @@ type InputPort = gasket::messaging::InputPort<model::CRDTCommand>;
 #[derive(Deserialize, Clone)]
 pub struct Config {
     pub connection_params: String,
+    pub cursor_key: Option<String>,
 }
 
 impl Config {
+    pub fn cursor_key(&self) -> &str {
+        self.cursor_key.as_deref().unwrap_or("_cursor")
+    }
+
     pub fn boostrapper(
         self,
         _chain: &crosscut::ChainWellKnownInfo,
         _intersect: &crosscut::IntersectConfig,
     ) -> Bootstrapper {
         Bootstrapper {
+            reader: Reader {
+                connection_params: self.connection_params.clone(),
+                connection: None,
+            },
             config: self,
             input: Default::default(),
         }
@@ -33,6 +40,7 @@ impl Config {
 pub struct Bootstrapper {
     config: Config,
     input: InputPort,
+    reader: Reader,
 }
 
 impl Bootstrapper {
@@ -45,7 +53,9 @@ impl Bootstrapper {
             .and_then(|x| x.get_connection())
             .map_err(crate::Error::storage)?;
 
-        let raw: Option<String> = connection.get("_cursor").map_err(crate::Error::storage)?;
+        let raw: Option<String> = connection
+            .get(self.config.cursor_key())
+            .map_err(crate::Error::storage)?;
 
         let point = match raw {
             Some(x) => Some(crosscut::PointArg::from_str(&x)?),
@@ -55,14 +65,83 @@ impl Bootstrapper {
         Ok(point)
     }
 
+    pub fn write_cursor(&mut self, point: &crosscut::PointArg) -> Result<(), crate::Error> {
+        let mut connection = redis::Client::open(self.config.connection_params.clone())
+            .and_then(|x| x.get_connection())
+            .map_err(crate::Error::storage)?;
+
+        connection
+            .set(self.config.cursor_key(), point.to_string())
+            .map_err(crate::Error::storage)
+    }
+
+    pub fn read_state(
+        &mut self,
+        query: &model::StateQuery,
+    ) -> Result<model::StateData, crate::Error> {
+        self.reader.read_state(query)
+    }
+
     pub fn spawn_stages(self, pipeline: &mut bootstrap::Pipeline) {
         let worker = Worker {
             config: self.config.clone(),
             connection: None,
             input: self.input,
+            last_slot: Default::default(),
+        };
+
+        pipeline.register_stage("redis", worker, Default::default());
+    }
+}
+
+/// Reads the state persisted by reducers while bootstrapping, the connection is
+/// opened on the first read and shared by the following ones
+struct Reader {
+    connection_params: String,
+    connection: Option<redis::Connection>,
+}
+
+impl Reader {
+    fn connection(&mut self) -> Result<&mut redis::Connection, crate::Error> {
+        if self.connection.is_none() {
+            let connection = redis::Client::open(self.connection_params.clone())
+                .and_then(|x| x.get_connection())
+                .map_err(crate::Error::storage)?;
+
+            self.connection = Some(connection);
+        }
+
+        Ok(self.connection.as_mut().unwrap())
+    }
+
+    fn read_state(&mut self, query: &model::StateQuery) -> Result<model::StateData, crate::Error> {
+        let connection = self.connection()?;
+
+        let data = match query {
+            model::StateQuery::KeyValue(key) => {
+                let value: Option<String> = connection.get(key).map_err(crate::Error::storage)?;
+                value.into()
+            }
+            model::StateQuery::LatestKeyValue(key) => {
+                // last-write-wins values are kept as sorted sets scored by slot
+                let values: Vec<String> = connection
+                    .zrevrange(key, 0, 0)
+                    .map_err(crate::Error::storage)?;
+
+                values.into_iter().next().into()
+            }
+            model::StateQuery::SetMembers(key) => {
+                let members: HashSet<String> =
+                    connection.smembers(key).map_err(crate::Error::storage)?;
+
+                match members.is_empty() {
+                    true => model::StateData::NotFound,
+                    false => model::StateData::SetMembers(members),
+                }
+            }
         };
 
-        pipeline.register_stage("redis", spawn_stage(worker, Default::default()));
+        Ok(data)
     }
 }
 
@@ -70,15 +149,23 @@ pub struct Worker {
     config: Config,
     connection: Option<redis::Connection>,
     input: InputPort,
+    last_slot: gasket::metrics::Gauge,
 }
 
 impl gasket::runtime::Worker for Worker {
     fn metrics(&self) -> gasket::metrics::Registry {
-        gasket::metrics::Builder::new().build()
+        gasket::metrics::Builder::new()
+            .with_gauge("last_slot", &self.last_slot)
+            .build()
     }
 
     fn work(&mut self) -> gasket::runtime::WorkResult {
-        let msg = self.input.recv()?;
+        let msg = match self.input.recv() {
+            Ok(x) => x,
+            // upstream stages are gone (eg: finalized sync), nothing else will arrive
+            Err(gasket::error::Error::RecvError) => return Ok(WorkOutcome::Done),
+            Err(err) => return Err(err),
+        };
 
         match msg.payload {
             model::CRDTCommand::BlockStarting(_) => {
@@ -127,6 +214,29 @@ impl gasket::runtime::Worker for Worker {
                     .srem(key, value)
                     .or_work_err()?;
             }
+            model::CRDTCommand::SortedSetAdd(key, value, score) => {
+                log::debug!(
+                    "adding to sorted set [{}], value [{}], score [{}]",
+                    key,
+                    value,
+                    score
+                );
+
+                self.connection
+                    .as_mut()
+                    .unwrap()
+                    .zadd(key, value, score)
+                    .or_work_err()?;
+            }
+            model::CRDTCommand::SortedSetRemove(key, value) => {
+                log::debug!("removing from sorted set [{}], value [{}]", key, value);
+
+                self.connection
+                    .as_mut()
+                    .unwrap()
+                    .zrem(key, value)
+                    .or_work_err()?;
+            }
             model::CRDTCommand::LastWriteWins(key, value, ts) => {
                 log::debug!("last write for [{}], value [{}], slot [{}]", key, value, ts);
 
@@ -145,6 +255,34 @@ impl gasket::runtime::Worker for Worker {
                     .set(key, value)
                     .or_work_err()?;
             }
+            model::CRDTCommand::AnyWriteWinsBytes(key, value) => {
+                log::debug!("overwrite [{}], {} bytes", key, value.len());
+
+                self.connection
+                    .as_mut()
+                    .unwrap()
+                    .set(key, value)
+                    .or_work_err()?;
+            }
+            model::CRDTCommand::SortedSetPrune(key, prefix, score) => {
+                log::debug!("pruning sorted set [{}] below score [{}]", key, score);
+
+                let connection = self.connection.as_mut().unwrap();
+
+                // exclusive upper bound, the threshold itself is kept
+                let max = format!("({}", score);
+
+                let members: Vec<String> =
+                    connection.zrangebyscore(&key, "-inf", &max).or_work_err()?;
+
+                for member in members {
+                    connection
+                        .del(format!("{}.{}", prefix, member))
+                        .or_work_err()?;
+                }
+
+                connection.zrembyscore(&key, "-inf", &max).or_work_err()?;
+            }
             model::CRDTCommand::PNCounter(key, value) => {
                 log::debug!("increating counter [{}], by [{}]", key, value);
 
@@ -155,12 +293,16 @@ impl gasket::runtime::Worker for Worker {
                     .or_work_err()?;
             }
             model::CRDTCommand::BlockFinished(point) => {
+                if let Point::Specific(slot, _) = &point {
+                    self.last_slot.set(*slot as i64);
+                }
+
                 let cursor_str = crosscut::PointArg::from(point).to_string();
 
                 self.connection
                     .as_mut()
                     .unwrap()
-                    .set("_cursor", &cursor_str)
+                    .set(self.config.cursor_key(), &cursor_str)
                     .or_work_err()?;
 
                 log::info!("new cursor saved to redis {}", &cursor_str)